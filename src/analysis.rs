@@ -1,4 +1,6 @@
-use crate::engine::EngineOutput;
+use std::collections::BTreeMap;
+
+use crate::engine::{EngineOutput, WorkspaceOutput};
 
 pub mod action;
 pub mod checks;
@@ -26,10 +28,21 @@ pub fn run(raw: &EngineOutput) -> state::AnalysisState {
     }
 
     // rules (optional)
-    let rules: Vec<Box<dyn checks::Rule>> = vec![];
+    let rules: Vec<Box<dyn checks::Rule>> = vec![
+        Box::new(checks::DepKindMismatch),
+        Box::new(checks::ModuleTreeGaps),
+        Box::new(checks::UngatedDepUse),
+        Box::new(checks::GateMismatch),
+        Box::new(checks::ModGateMismatch),
+    ];
     let check_actions = checks::run_rules(raw, store.state(), &rules);
     store.dispatch_many(check_actions);
 
     store.dispatch(action::Action::SetPhase(state::Phase::Done));
     store.into_state()
 }
+
+/// Run the analysis once per member crate.
+pub fn run_workspace(raw: &WorkspaceOutput) -> BTreeMap<String, state::AnalysisState> {
+    raw.crates.iter().map(|(name, m)| (name.clone(), run(&m.output))).collect()
+}
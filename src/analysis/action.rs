@@ -1,6 +1,8 @@
 use super::state::{FileAnalysis, Finding, Phase};
 use serde::{Deserialize, Serialize};
 
+use crate::model::SkippedFile;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Action {
     SetPhase(Phase),
@@ -10,6 +12,7 @@ pub enum Action {
     },
     AddFinding(Finding),
     AddFindings(Vec<Finding>),
+    SetSkipped(Vec<SkippedFile>),
     IncExternalDepHit {
         dep: String,
         by: usize,
@@ -4,6 +4,15 @@ use super::{
 };
 use crate::engine::EngineOutput;
 
+pub mod dep_kind;
+pub use dep_kind::DepKindMismatch;
+
+pub mod modules;
+pub use modules::ModuleTreeGaps;
+
+pub mod gates;
+pub use gates::{GateMismatch, ModGateMismatch, UngatedDepUse};
+
 pub trait Rule: Send + Sync {
     fn id(&self) -> &'static str;
     fn findings(&self, raw: &EngineOutput, st: &AnalysisState) -> Vec<Finding>;
@@ -0,0 +1,65 @@
+use crate::{
+    analysis::{
+        checks::Rule,
+        state::{AnalysisState, Finding, Severity},
+    },
+    engine::EngineOutput,
+    model::{DepKind, TargetKind},
+};
+
+/// A dependency used from a target that can't see the table it is declared in:
+/// a dev-dependency in `src/`, a normal dependency in `build.rs`, ...
+pub struct DepKindMismatch;
+
+impl Rule for DepKindMismatch {
+    fn id(&self) -> &'static str {
+        "dep_kind_mismatch"
+    }
+
+    fn findings(&self, raw: &EngineOutput, _st: &AnalysisState) -> Vec<Finding> {
+        let mut out = vec![];
+
+        for r in &raw.reports {
+            for dep in &r.used.packages {
+                let Some(info) = raw.manifest.deps.get(dep) else { continue };
+
+                let mut allowed = r.target.allowed_dep_kinds().to_vec();
+                // `#[cfg(test)]` code in lib/bin targets is compiled with dev-deps
+                let sites = r.external_use_sites.iter().filter(|u| &u.dep == dep);
+                let test_only = sites.clone().all(|u| u.test_only) && sites.count() > 0;
+                if test_only && matches!(r.target, TargetKind::Lib | TargetKind::Bin) {
+                    allowed.push(DepKind::Dev);
+                }
+
+                if info.kinds.iter().any(|k| allowed.contains(k)) {
+                    continue;
+                }
+
+                let declared: Vec<String> = info.kinds.iter().map(|k| format!("[{}]", k.table())).collect();
+                let mut hint = format!("add it to [{}]", allowed[0].table());
+                if info.kinds.contains(&DepKind::Dev) && r.target == TargetKind::Lib {
+                    hint.push_str(" or keep the use under #[cfg(test)]");
+                }
+
+                let target = match r.target_name.as_str() {
+                    "" => format!("{} code", r.target.as_str()),
+                    name => format!("{} target `{name}`", r.target.as_str()),
+                };
+
+                out.push(Finding {
+                    severity: Severity::Warn,
+                    file: Some(r.document.display_path()),
+                    code: "dep-kind-mismatch".to_string(),
+                    message: format!(
+                        "`{}` is declared in {} but used from {target}",
+                        raw.manifest.dep_label(dep),
+                        declared.join(", "),
+                    ),
+                    hint: Some(hint),
+                });
+            }
+        }
+
+        out
+    }
+}
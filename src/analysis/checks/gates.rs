@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    analysis::{
+        checks::Rule,
+        state::{AnalysisState, Finding, Severity},
+    },
+    engine::EngineOutput,
+};
+
+/// Optional-dependency uses that compile with every feature enabling the
+/// dependency turned off (no `cfg` on the module, file or item rules them out).
+pub struct UngatedDepUse;
+
+impl Rule for UngatedDepUse {
+    fn id(&self) -> &'static str {
+        "ungated_dep_use"
+    }
+
+    fn findings(&self, raw: &EngineOutput, _st: &AnalysisState) -> Vec<Finding> {
+        let mut out = vec![];
+
+        for r in &raw.reports {
+            // dep -> lines
+            let mut by_dep = BTreeMap::<&str, Vec<usize>>::new();
+            for u in &r.ungated_uses {
+                by_dep.entry(&u.dep).or_default().push(u.line);
+            }
+
+            for (dep, mut lines) in by_dep {
+                lines.sort_unstable();
+                lines.dedup();
+                let features = &r.ungated_uses.iter().find(|u| u.dep == dep).unwrap().features;
+                let runtime_only = r.ungated_uses.iter().filter(|u| u.dep == dep).all(|u| u.runtime_cfg.is_some());
+
+                let lines = lines.iter().map(|l| l.to_string()).collect::<Vec<_>>().join(", ");
+                let mut hint = format!("gate it on one of: {}", features.join(", "));
+                if runtime_only {
+                    hint.push_str(" (`if cfg!(...)` is checked at run time; the code still has to compile)");
+                }
+
+                out.push(Finding {
+                    severity: Severity::Warn,
+                    file: Some(r.document.display_path()),
+                    code: "ungated-dep-use".to_string(),
+                    message: format!(
+                        "optional dependency `{}` is used without its feature being active (line {lines})",
+                        raw.manifest.dep_label(dep),
+                    ),
+                    hint: Some(hint),
+                });
+            }
+        }
+
+        out
+    }
+}
+
+/// Hand-written file/module gates that disagree with the computed
+/// `corpus_features`: a computed feature no gate covers, or a gate on a
+/// feature the manifest doesn't declare.
+pub struct GateMismatch;
+
+impl Rule for GateMismatch {
+    fn id(&self) -> &'static str {
+        "gate_mismatch"
+    }
+
+    fn findings(&self, raw: &EngineOutput, _st: &AnalysisState) -> Vec<Finding> {
+        let graph = &raw.manifest.feature_graph;
+        let declared = |f: &str| raw.manifest.features_raw.contains_key(f) || graph.implicit.contains(f);
+
+        let mut out = vec![];
+        for r in &raw.reports {
+            let gated: BTreeSet<String> =
+                r.module_cfg.iter().chain(&r.file_cfg).flat_map(|c| c.positive_features()).collect();
+            if gated.is_empty() {
+                continue;
+            }
+            let file = Some(r.document.display_path());
+
+            // a gate on `full` covers `async` when `full` turns it on
+            let covers = |g: &String, c: &String| g == c || graph.closure.get(g).is_some_and(|cl| cl.contains(c));
+            let uncovered: Vec<&String> =
+                r.corpus_features.iter().filter(|c| !gated.iter().any(|g| covers(g, c))).collect();
+            if !uncovered.is_empty() {
+                out.push(Finding {
+                    severity: Severity::Warn,
+                    file: file.clone(),
+                    code: "gate-mismatch".to_string(),
+                    message: format!(
+                        "existing gate requires {:?} but the file needs {:?}",
+                        gated, r.corpus_features,
+                    ),
+                    hint: Some(format!("not covered by the existing gate: {uncovered:?}")),
+                });
+            }
+
+            for g in gated.iter().filter(|g| !declared(g)) {
+                out.push(Finding {
+                    severity: Severity::Warn,
+                    file: file.clone(),
+                    code: "gate-mismatch".to_string(),
+                    message: format!("gated on feature `{g}`, which Cargo.toml doesn't declare"),
+                    hint: Some("the file is never compiled".to_string()),
+                });
+            }
+        }
+
+        out
+    }
+}
+
+/// A file gated on a feature (`#![cfg]`) whose `mod` declaration in the parent
+/// isn't: with the feature off the module is still declared but empty, so
+/// `pub use child::...` in the parent breaks.
+pub struct ModGateMismatch;
+
+impl Rule for ModGateMismatch {
+    fn id(&self) -> &'static str {
+        "mod_gate_mismatch"
+    }
+
+    fn findings(&self, raw: &EngineOutput, _st: &AnalysisState) -> Vec<Finding> {
+        let graph = &raw.manifest.feature_graph;
+        let mut out = vec![];
+
+        for r in &raw.reports {
+            let Some(parent) = &r.module_declared_in else { continue };
+
+            let inner: BTreeSet<String> =
+                r.header_cfg.iter().chain(&r.file_cfg).flat_map(|c| c.positive_features()).collect();
+            let outer: BTreeSet<String> = r.module_cfg.iter().flat_map(|c| c.positive_features()).collect();
+
+            // `mod foo;` gated on `full` is fine for a file gated on `async` if `full` turns it on
+            let implied = |f: &String| outer.iter().any(|g| g == f || graph.closure.get(g).is_some_and(|cl| cl.contains(f)));
+            let missing: Vec<&String> = inner.iter().filter(|f| !implied(f)).collect();
+            if missing.is_empty() {
+                continue;
+            }
+
+            let name = r.module_path.rsplit("::").next().unwrap_or(&r.module_path);
+            out.push(Finding {
+                severity: Severity::Warn,
+                file: Some(r.document.display_path()),
+                code: "mod-gate-mismatch".to_string(),
+                message: format!("`{}` is gated on {missing:?} but `mod {name};` in {parent} is not", r.module_path),
+                hint: Some("run annotate to gate the declaration and its re-exports".to_string()),
+            });
+        }
+
+        out
+    }
+}
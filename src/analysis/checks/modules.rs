@@ -0,0 +1,42 @@
+use crate::{
+    analysis::{
+        checks::Rule,
+        state::{AnalysisState, Finding, Severity},
+    },
+    engine::EngineOutput,
+};
+
+/// Files no `mod` item loads, and `mod` items with no file.
+pub struct ModuleTreeGaps;
+
+impl Rule for ModuleTreeGaps {
+    fn id(&self) -> &'static str {
+        "module_tree_gaps"
+    }
+
+    fn findings(&self, raw: &EngineOutput, _st: &AnalysisState) -> Vec<Finding> {
+        let mut out = vec![];
+
+        for path in &raw.orphans {
+            out.push(Finding {
+                severity: Severity::Warn,
+                file: Some(path.clone()),
+                code: "orphan-file".to_string(),
+                message: format!("`{path}` is not declared by any `mod` item, so it is never compiled"),
+                hint: Some("declare it with `mod ...;` or delete it".to_string()),
+            });
+        }
+
+        for m in &raw.missing_modules {
+            out.push(Finding {
+                severity: Severity::Warn,
+                file: Some(m.declared_in.clone()),
+                code: "missing-module".to_string(),
+                message: format!("`{}` has no file (tried {})", m.module_path, m.candidates.join(", ")),
+                hint: None,
+            });
+        }
+
+        out
+    }
+}
@@ -15,10 +15,11 @@ impl Pass for BuildFileViews {
         let mut out = Vec::new();
         for r in &raw.reports {
             out.push(Action::UpsertFile {
-                path: r.document.relative_path.clone(),
+                path: r.document.display_path(),
                 analysis: file_view(r),
             });
         }
+        out.push(Action::SetSkipped(raw.skipped.clone()));
         out.push(Action::SetPhase(Phase::FileViewsBuilt));
         out
     }
@@ -10,6 +10,7 @@ pub fn reduce_in_place(st: &mut AnalysisState, a: Action) {
 
         Action::AddFinding(f) => st.findings.push(f),
         Action::AddFindings(mut fs) => st.findings.append(&mut fs),
+        Action::SetSkipped(skipped) => st.skipped = skipped,
 
         Action::IncExternalDepHit { dep, by } => {
             let cur = st.crate_totals.external_dep_hits.get(&dep).copied().unwrap_or(0);
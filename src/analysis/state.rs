@@ -1,6 +1,8 @@
 use im::{OrdMap, OrdSet};
 use serde::{Serialize, Deserialize};
 
+use crate::model::SkippedFile;
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AnalysisState {
     pub phase: Phase,
@@ -13,6 +15,10 @@ pub struct AnalysisState {
 
     // findings from checks/passes
     pub findings: Vec<Finding>,
+
+    // files the scan walked past, with the reason
+    #[serde(default)]
+    pub skipped: Vec<SkippedFile>,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
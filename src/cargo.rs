@@ -1,31 +1,222 @@
-use crate::{error::{PassengerError, Result}, model::ManifestInfo};
+use crate::{error::{PassengerError, Result}, model::{DepInfo, DepKind, FeatureGraph, ManifestInfo, SettingSource, TargetInfo, TargetKind}};
 use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
+use std::path::{Path, PathBuf};
 
 fn norm_dep_key(dep: &str) -> String {
     dep.replace('-', "_")
 }
 
+fn str_of(t: Option<&dyn toml_edit::TableLike>, key: &str) -> Option<String> {
+    t.and_then(|t| t.get(key)).and_then(|x| x.as_str()).map(|s| s.to_string())
+}
+
+fn bool_of(t: Option<&dyn toml_edit::TableLike>, keys: &[&str]) -> Option<bool> {
+    let t = t?;
+    keys.iter().find_map(|k| t.get(k).and_then(|x| x.as_bool()))
+}
+
+fn strs_of(t: Option<&dyn toml_edit::TableLike>, key: &str) -> Vec<String> {
+    t.and_then(|t| t.get(key))
+        .and_then(|x| x.as_array())
+        .map(|a| a.iter().filter_map(|x| x.as_str().map(|s| s.to_string())).collect())
+        .unwrap_or_default()
+}
+
+/// Which manifest a setting of a dependency came from.
+fn source_of(member: bool, workspace: bool) -> Option<SettingSource> {
+    match (member, workspace) {
+        (true, true) => Some(SettingSource::Both),
+        (true, false) => Some(SettingSource::Member),
+        (false, true) => Some(SettingSource::Workspace),
+        (false, false) => None,
+    }
+}
+
 fn collect_deps_from_tablelike(
     tbl: &dyn toml_edit::TableLike,
+    ws_deps: Option<&dyn toml_edit::TableLike>,
     all_deps: &mut BTreeSet<String>,
     optional_deps: &mut BTreeSet<String>,
-) {
+    deps: &mut BTreeMap<String, DepInfo>,
+    kind: DepKind,
+    platform: Option<&str>,
+) -> Result<()> {
     for (k, v) in tbl.iter() {
         let key = norm_dep_key(k);
         all_deps.insert(key.clone());
 
         // dep value can be "1.0" OR { version="1", optional=true } OR table form
-        let is_optional = v
-            .as_table_like()
-            .and_then(|t| t.get("optional"))
-            .and_then(|x| x.as_bool())
-            .unwrap_or(false);
+        let t = v.as_table_like();
+
+        // `foo.workspace = true` / `foo = { workspace = true, optional = true }`:
+        // everything but `optional` (and extra `features`) comes from [workspace.dependencies]
+        let inherits = bool_of(t, &["workspace"]).unwrap_or(false);
+        let ws_item = if inherits {
+            Some(ws_deps.and_then(|w| w.get(k)).ok_or_else(|| {
+                PassengerError::Toml(format!(
+                    "dependency `{k}` sets workspace = true but no [workspace.dependencies] entry was found"
+                ))
+            })?)
+        } else {
+            None
+        };
+        let ws_t = ws_item.and_then(|w| w.as_table_like());
+
+        let is_optional = bool_of(t, &["optional"]).unwrap_or(false);
+
+        // `foo = { package = "bar-baz" }`: code says `foo`, crates.io says `bar-baz`
+        let member_package = str_of(t, "package");
+        let ws_package = str_of(ws_t, "package");
+        let package = member_package.clone().or(ws_package.clone()).unwrap_or_else(|| k.to_string());
+
+        let member_version = match t {
+            Some(t) => str_of(Some(t), "version"),
+            None => v.as_str().map(|s| s.to_string()),
+        };
+        let ws_version = match ws_t {
+            Some(w) => str_of(Some(w), "version"),
+            None => ws_item.and_then(|w| w.as_str()).map(|s| s.to_string()),
+        };
+        let version_req = member_version.clone().or(ws_version.clone());
+
+        let member_features = strs_of(t, "features");
+        let ws_features = strs_of(ws_t, "features");
+        let mut features = ws_features.clone();
+        for f in &member_features {
+            if !features.contains(f) {
+                features.push(f.clone());
+            }
+        }
+
+        let member_default = bool_of(t, &["default-features", "default_features"]);
+        let ws_default = bool_of(ws_t, &["default-features", "default_features"]);
+        let default_features = member_default.or(ws_default).unwrap_or(true);
+
+        let mut sources = BTreeMap::new();
+        let settings = [
+            ("package", member_package.is_some(), ws_package.is_some()),
+            ("version", member_version.is_some(), ws_version.is_some()),
+            ("features", !member_features.is_empty(), !ws_features.is_empty()),
+            ("default-features", member_default.is_some(), ws_default.is_some()),
+            ("optional", bool_of(t, &["optional"]).is_some(), false),
+        ];
+        for (name, m, w) in settings {
+            if let Some(src) = source_of(m, w) {
+                sources.insert(name.to_string(), src);
+            }
+        }
+
+        // same key can show up in several tables (deps + dev-deps + target deps)
+        let entry = deps.entry(key.clone()).or_insert_with(|| DepInfo {
+            key: k.to_string(),
+            package,
+            version_req: None,
+            optional: false,
+            features: vec![],
+            default_features,
+            workspace: inherits,
+            sources: BTreeMap::new(),
+            kinds: BTreeSet::new(),
+            platforms: BTreeSet::new(),
+            any_platform: false,
+        });
+        entry.kinds.insert(kind);
+        match platform {
+            Some(p) => {
+                entry.platforms.insert(p.to_string());
+            }
+            None => entry.any_platform = true,
+        }
+        entry.optional |= is_optional;
+        if entry.version_req.is_none() {
+            entry.version_req = version_req;
+        }
+        for f in features {
+            if !entry.features.contains(&f) {
+                entry.features.push(f);
+            }
+        }
+        for (name, src) in sources {
+            entry.sources.entry(name).or_insert(src);
+        }
 
         if is_optional {
             optional_deps.insert(key);
         }
     }
+    Ok(())
+}
+
+/// Find the workspace root manifest for the crate at `manifest_path`: the manifest
+/// itself if it has `[workspace]`, the one `package.workspace` points at, or the
+/// nearest ancestor `Cargo.toml` with a `[workspace]` table that doesn't exclude it.
+/// Like Cargo, an ancestor workspace that neither lists nor excludes the crate is
+/// an error.
+pub fn find_workspace_root(manifest_path: &Path) -> Result<Option<PathBuf>> {
+    let raw = fs::read_to_string(manifest_path)?;
+    let doc = raw.parse::<toml_edit::DocumentMut>()
+        .map_err(|e| PassengerError::Toml(e.to_string()))?;
+
+    if doc.get("workspace").is_some() {
+        return Ok(Some(manifest_path.to_path_buf()));
+    }
+
+    let dir = manifest_path.parent().unwrap_or(Path::new("."));
+    if let Some(ws) = doc.get("package").and_then(|p| p.get("workspace")).and_then(|v| v.as_str()) {
+        let p = dir.join(ws).join("Cargo.toml");
+        return Ok(p.exists().then_some(p));
+    }
+
+    for anc in dir.ancestors().skip(1) {
+        let candidate = anc.join("Cargo.toml");
+        if !candidate.is_file() {
+            continue;
+        }
+        let Ok(raw) = fs::read_to_string(&candidate) else { continue };
+        let Ok(doc) = raw.parse::<toml_edit::DocumentMut>() else { continue };
+        if doc.get("workspace").is_none() {
+            continue;
+        }
+        match workspace_membership(&candidate, dir)? {
+            Membership::Member => return Ok(Some(candidate)),
+            Membership::Excluded => continue,
+            Membership::Outside => {
+                return Err(PassengerError::Toml(format!(
+                    "{} believes it's in the workspace at {}, but is not a member (add it to `members` or `exclude`)",
+                    manifest_path.display(),
+                    candidate.display()
+                )));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Membership {
+    Member,
+    Excluded,
+    Outside,
+}
+
+/// Where `crate_dir` stands in the workspace at `root_manifest`. An explicit
+/// (non-glob) `members` entry wins over `exclude`, as in Cargo.
+fn workspace_membership(root_manifest: &Path, crate_dir: &Path) -> Result<Membership> {
+    let canon = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_path_buf());
+    let crate_dir = canon(crate_dir);
+    if workspace_members(root_manifest)?.iter().any(|m| canon(m) == crate_dir) {
+        return Ok(Membership::Member);
+    }
+
+    let raw = fs::read_to_string(root_manifest)?;
+    let doc = raw.parse::<toml_edit::DocumentMut>()
+        .map_err(|e| PassengerError::Toml(e.to_string()))?;
+    let root = canon(root_manifest.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")));
+    let ws = doc.get("workspace").and_then(|w| w.as_table_like());
+    let excluded = strs_of(ws, "exclude").iter().any(|ex| crate_dir.starts_with(root.join(ex)));
+    Ok(if excluded { Membership::Excluded } else { Membership::Outside })
 }
 
 pub fn load_manifest(path: &str) -> Result<ManifestInfo> {
@@ -40,23 +231,41 @@ pub fn load_manifest(path: &str) -> Result<ManifestInfo> {
         .map(|s| s.to_string())
         .ok_or(PassengerError::MissingPackageName)?;
 
+    // [workspace.dependencies] for `foo.workspace = true` entries
+    let ws_root = find_workspace_root(Path::new(path))?;
+    let ws_doc = match &ws_root {
+        Some(p) => Some(
+            fs::read_to_string(p)?
+                .parse::<toml_edit::DocumentMut>()
+                .map_err(|e| PassengerError::Toml(e.to_string()))?,
+        ),
+        None => None,
+    };
+    let ws_deps = ws_doc
+        .as_ref()
+        .and_then(|d| d.get("workspace"))
+        .and_then(|w| w.get("dependencies"))
+        .and_then(|t| t.as_table_like());
+
     let mut all_deps = BTreeSet::<String>::new();
     let mut optional_deps = BTreeSet::<String>::new();
+    let mut deps = BTreeMap::<String, DepInfo>::new();
 
     // plain deps tables
-    for key in ["dependencies", "dev-dependencies", "build-dependencies"] {
-        if let Some(tbl) = doc.get(key).and_then(|t| t.as_table_like()) {
-            collect_deps_from_tablelike(tbl, &mut all_deps, &mut optional_deps);
+    for kind in DEP_KINDS {
+        if let Some(tbl) = doc.get(kind.table()).and_then(|t| t.as_table_like()) {
+            collect_deps_from_tablelike(tbl, ws_deps, &mut all_deps, &mut optional_deps, &mut deps, kind, None)?;
         }
     }
 
     // target-specific deps: [target.'cfg(...)'.dependencies] etc
     if let Some(targets) = doc.get("target").and_then(|t| t.as_table_like()) {
-        for (_tname, titem) in targets.iter() {
+        for (tname, titem) in targets.iter() {
             if let Some(ttbl) = titem.as_table_like() {
-                for key in ["dependencies", "dev-dependencies", "build-dependencies"] {
-                    if let Some(tbl) = ttbl.get(key).and_then(|x| x.as_table_like()) {
-                        collect_deps_from_tablelike(tbl, &mut all_deps, &mut optional_deps);
+                for kind in DEP_KINDS {
+                    if let Some(tbl) = ttbl.get(kind.table()).and_then(|x| x.as_table_like()) {
+                        let platform = Some(tname);
+                        collect_deps_from_tablelike(tbl, ws_deps, &mut all_deps, &mut optional_deps, &mut deps, kind, platform)?;
                     }
                 }
             }
@@ -79,48 +288,615 @@ pub fn load_manifest(path: &str) -> Result<ManifestInfo> {
         }
     }
 
-    // feature -> deps it enables (keep your exact logic, but it references optional_deps)
-    let mut feature_deps: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
-    for (fname, members) in &features_raw {
-        let mut deps_for_feature = BTreeSet::<String>::new();
+    let feature_graph = build_feature_graph(&features_raw, &deps, &optional_deps);
+
+    // feature -> optional deps it turns on, following feature -> feature edges
+    let feature_deps = feature_graph.closure_deps.clone();
+
+    let mut dep_features: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for (feat, deps) in &feature_deps {
+        for dep in deps {
+            dep_features.entry(dep.clone()).or_default().insert(feat.clone());
+        }
+    }
+
+    let targets = discover_targets(&doc, Path::new(path).parent().unwrap_or(Path::new(".")), &crate_name);
+
+    Ok(ManifestInfo {
+        crate_name,
+        features_raw,
+        all_deps,
+        optional_deps,
+        feature_deps,
+        dep_features,
+        feature_graph,
+        deps,
+        workspace_root: ws_root.map(|p| p.to_string_lossy().to_string()),
+        targets,
+    })
+}
+
+const DEP_KINDS: [DepKind; 3] = [DepKind::Normal, DepKind::Dev, DepKind::Build];
+
+/// Targets the way Cargo finds them: `[lib]`/`[[bin]]`/`[[test]]`/`[[example]]`/
+/// `[[bench]]` entries, plus auto-discovery (unless `autobins = false` etc.) and
+/// the build script. A declared target replaces an auto one of the same name.
+fn discover_targets(doc: &toml_edit::DocumentMut, crate_root: &Path, crate_name: &str) -> Vec<TargetInfo> {
+    let pkg = doc.get("package").and_then(|p| p.as_table_like());
+    let auto = |key: &str| bool_of(pkg, &[key]).unwrap_or(true);
+    let mut out = vec![];
+
+    let lib = doc.get("lib").and_then(|l| l.as_table_like());
+    let lib_path = str_of(lib, "path").unwrap_or_else(|| "src/lib.rs".to_string());
+    if lib.is_some() || crate_root.join(&lib_path).is_file() {
+        let name = str_of(lib, "name").unwrap_or_else(|| crate_name.to_string());
+        out.push(TargetInfo { kind: TargetKind::Lib, name, path: lib_path });
+    }
+
+    let kinds = [
+        (TargetKind::Bin, "bin", "autobins", "src/bin"),
+        (TargetKind::Test, "test", "autotests", "tests"),
+        (TargetKind::Example, "example", "autoexamples", "examples"),
+        (TargetKind::Bench, "bench", "autobenches", "benches"),
+    ];
+    for (kind, table, auto_key, dir) in kinds {
+        let mut found = BTreeMap::<String, String>::new();
+
+        if auto(auto_key) {
+            if kind == TargetKind::Bin && crate_root.join("src/main.rs").is_file() {
+                found.insert(crate_name.to_string(), "src/main.rs".to_string());
+            }
+            found.extend(auto_targets(crate_root, dir));
+        }
+
+        found.extend(declared_targets(doc, table, dir));
+        out.extend(found.into_iter().map(|(name, path)| TargetInfo { kind, name, path }));
+    }
+
+    // `build = "path"`, `build = false`, or `build.rs` if it exists
+    let build = match pkg.and_then(|p| p.get("build")) {
+        Some(v) if v.as_bool() == Some(true) => Some("build.rs".to_string()),
+        Some(v) => v.as_str().map(|s| s.to_string()),
+        None => crate_root.join("build.rs").is_file().then(|| "build.rs".to_string()),
+    };
+    if let Some(path) = build {
+        out.push(TargetInfo { kind: TargetKind::Build, name: "build-script-build".to_string(), path });
+    }
+
+    out
+}
+
+/// `{dir}/*.rs` and `{dir}/*/main.rs`: name -> path.
+fn auto_targets(crate_root: &Path, dir: &str) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    let Ok(entries) = fs::read_dir(crate_root.join(dir)) else { return out };
+    for p in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let Some(stem) = p.file_stem().map(|s| s.to_string_lossy().to_string()) else { continue };
+        if p.is_file() && p.extension().is_some_and(|e| e == "rs") {
+            out.insert(stem.clone(), format!("{dir}/{stem}.rs"));
+        } else if p.join("main.rs").is_file() {
+            out.insert(stem.clone(), format!("{dir}/{stem}/main.rs"));
+        }
+    }
+    out
+}
+
+/// `[[<table>]]` entries: name -> path (defaulting to `{dir}/{name}.rs`).
+fn declared_targets(doc: &toml_edit::DocumentMut, table: &str, dir: &str) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    let Some(arr) = doc.get(table).and_then(|t| t.as_array_of_tables()) else { return out };
+    for t in arr.iter() {
+        let Some(name) = t.get("name").and_then(|v| v.as_str()) else { continue };
+        let path = t
+            .get("path")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| format!("{dir}/{name}.rs"));
+        out.insert(name.to_string(), path);
+    }
+    out
+}
+
+/// Build the `[features]` graph the way Cargo resolves it:
+/// - `other`        -> feature edge (or an optional dep's implicit feature)
+/// - `dep:foo`      -> turns on optional dep `foo`
+/// - `foo/bar`      -> turns on `foo` (if optional) and its feature `bar`
+/// - `foo?/bar`     -> weak: only turns on `bar` if `foo` is already on
+///
+/// Optional deps never named via `dep:` get Cargo's implicit `foo = ["dep:foo"]`.
+fn build_feature_graph(
+    features_raw: &BTreeMap<String, Vec<String>>,
+    deps: &BTreeMap<String, DepInfo>,
+    optional_deps: &BTreeSet<String>,
+) -> FeatureGraph {
+    let mut g = FeatureGraph::default();
+
+    let named_via_dep: BTreeSet<String> = features_raw
+        .values()
+        .flatten()
+        .filter_map(|m| m.strip_prefix("dep:"))
+        .map(|d| norm_dep_key(d.trim()))
+        .collect();
+
+    let mut features = features_raw.clone();
+    for dep in optional_deps {
+        let raw_key = deps.get(dep).map(|d| d.key.clone()).unwrap_or_else(|| dep.clone());
+        if !named_via_dep.contains(dep) && !features.contains_key(&raw_key) {
+            features.insert(raw_key.clone(), vec![format!("dep:{raw_key}")]);
+            g.implicit.insert(raw_key);
+        }
+    }
+
+    for (fname, members) in &features {
+        let edges = g.edges.entry(fname.clone()).or_default();
+        let direct = g.direct_deps.entry(fname.clone()).or_default();
+        let weak = g.weak_deps.entry(fname.clone()).or_default();
+
         for m in members {
+            let m = m.trim();
             if let Some(dep) = m.strip_prefix("dep:") {
                 let dep = norm_dep_key(dep.trim());
                 if optional_deps.contains(&dep) {
-                    deps_for_feature.insert(dep);
+                    direct.insert(dep);
+                }
+            } else if let Some((left, _feat)) = m.split_once("?/") {
+                weak.insert(norm_dep_key(left.trim()));
+            } else if let Some((left, _feat)) = m.split_once('/') {
+                let left = left.trim();
+                let dep = norm_dep_key(left);
+                if optional_deps.contains(&dep) {
+                    direct.insert(dep);
+                    // `foo/bar` also turns on foo's implicit feature, if it has one
+                    if features.contains_key(left) && left != fname {
+                        edges.insert(left.to_string());
+                    }
+                }
+            } else if features.contains_key(m) {
+                edges.insert(m.to_string());
+            } else {
+                // pre-`dep:` style reference to an optional dep
+                let dep = norm_dep_key(m);
+                if optional_deps.contains(&dep) {
+                    direct.insert(dep);
                 }
+            }
+        }
+    }
+
+    for fname in features.keys() {
+        let mut seen = BTreeSet::<String>::new();
+        let mut stack: Vec<String> = g.edges[fname].iter().cloned().collect();
+        while let Some(f) = stack.pop() {
+            if f == *fname || !seen.insert(f.clone()) {
+                continue;
+            }
+            if let Some(next) = g.edges.get(&f) {
+                stack.extend(next.iter().cloned());
+            }
+        }
+
+        let mut all_deps = g.direct_deps[fname].clone();
+        for f in &seen {
+            all_deps.extend(g.direct_deps[f].iter().cloned());
+        }
+
+        g.closure_deps.insert(fname.clone(), all_deps);
+        g.closure.insert(fname.clone(), seen);
+    }
+
+    if let Some(on) = g.closure.get("default") {
+        g.default_on = on.clone();
+        g.default_on.insert("default".to_string());
+    }
+
+    g
+}
+
+/// Rewrite a Cargo.toml so every feature group is a `[features]` entry turning on
+/// its deps via `dep:`, and every grouped dep is `optional = true`. Formatting,
+/// comments and unrelated entries are kept (edits go through `toml_edit`).
+///
+/// A group's `dep:` members are replaced by the group's list; its other members
+/// (feature -> feature edges, `foo/bar`) are kept, and arrays are edited entry by
+/// entry so their layout and comments survive. Plain `"foo"` members naming a
+/// grouped dep anywhere in `[features]` become `"dep:foo"`, since Cargo drops the
+/// implicit `foo` feature once `dep:foo` is used; with an explicit `foo` feature
+/// they name that feature and stay.
+pub fn sync_feature_groups(raw: &str, groups: &BTreeMap<String, Vec<String>>) -> Result<String> {
+    let mut doc = raw.parse::<toml_edit::DocumentMut>()
+        .map_err(|e| PassengerError::Toml(e.to_string()))?;
+
+    let mut grouped_keys = BTreeSet::<String>::new();
+    for (group, members) in groups {
+        for dep in members {
+            let key = mark_optional(&mut doc, dep).ok_or_else(|| {
+                PassengerError::Config(format!(
+                    "feature group `{group}` lists `{dep}`, which is not in [dependencies]"
+                ))
+            })?;
+            grouped_keys.insert(key);
+        }
+    }
+
+    let features = doc
+        .entry("features")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .ok_or_else(|| PassengerError::Toml("[features] is not a table".into()))?;
+
+    // a plain "foo" naming an explicit feature is a feature edge, not the dep
+    let explicit: BTreeSet<String> =
+        features.iter().map(|(k, _)| k.to_string()).chain(groups.keys().cloned()).collect();
+    let implicit_dep = |m: &str| grouped_keys.iter().find(|k| *k == m && !explicit.contains(m)).cloned();
+
+    for (group, members) in groups {
+        let keys: Vec<String> = members
+            .iter()
+            .map(|d| grouped_keys.iter().find(|k| norm_dep_key(k) == norm_dep_key(d)).cloned().unwrap_or_else(|| d.clone()))
+            .collect();
+
+        let existing: Vec<String> = features
+            .get(group)
+            .and_then(|v| v.as_array())
+            .map(|a| a.iter().filter_map(|x| x.as_str().map(|s| s.to_string())).collect())
+            .unwrap_or_default();
+
+        let mut next: Vec<String> = keys.iter().map(|k| format!("dep:{k}")).collect();
+        for m in existing {
+            let plain_grouped = !explicit.contains(&m) && keys.iter().any(|k| norm_dep_key(k) == norm_dep_key(&m));
+            if m.starts_with("dep:") || plain_grouped {
                 continue;
             }
+            next.push(m);
+        }
 
-            if let Some((left, _right)) = m.split_once('/') {
-                let left = norm_dep_key(left.trim());
-                if optional_deps.contains(&left) {
-                    deps_for_feature.insert(left);
+        match features.get_mut(group).and_then(|v| v.as_array_mut()) {
+            Some(arr) => {
+                // drop what's gone, then insert what's new where `next` has it
+                for i in (0..arr.len()).rev() {
+                    if !arr.get(i).and_then(|x| x.as_str()).is_some_and(|m| next.iter().any(|n| n == m)) {
+                        arr.remove(i);
+                    }
                 }
-                continue;
+                // new entries go on their own line in a one-per-line array
+                let line_prefix = arr
+                    .iter()
+                    .last()
+                    .and_then(|x| x.decor().prefix()?.as_str())
+                    .and_then(|p| Some(format!("\n{}", &p[p.rfind('\n')? + 1..])));
+                let mut inserted = false;
+                for (i, m) in next.iter().enumerate() {
+                    if !arr.iter().any(|x| x.as_str() == Some(m.as_str())) {
+                        let at = i.min(arr.len());
+                        arr.insert(at, m.as_str());
+                        if let (Some(prefix), Some(v)) = (&line_prefix, arr.get_mut(at)) {
+                            v.decor_mut().set_prefix(prefix.as_str());
+                        }
+                        inserted = true;
+                    }
+                }
+                if inserted && line_prefix.is_none() {
+                    arr.fmt();
+                }
+            }
+            None => {
+                let arr: toml_edit::Array = next.iter().map(|s| s.as_str()).collect();
+                features.insert(group, toml_edit::value(arr));
             }
+        }
+    }
 
-            let mnorm = norm_dep_key(m.trim());
-            if optional_deps.contains(&mnorm) {
-                deps_for_feature.insert(mnorm);
+    // plain "foo" members elsewhere would point at a now-missing implicit feature
+    let names: Vec<String> = features.iter().map(|(k, _)| k.to_string()).collect();
+    for name in names {
+        if let Some(arr) = features.get_mut(&name).and_then(|v| v.as_array_mut()) {
+            for i in 0..arr.len() {
+                let Some(m) = arr.get(i).and_then(|x| x.as_str()).map(|s| s.to_string()) else { continue };
+                if let Some(k) = implicit_dep(&m) {
+                    arr.replace(i, format!("dep:{k}"));
+                }
             }
         }
-        feature_deps.insert(fname.clone(), deps_for_feature);
     }
 
-    let mut dep_features: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
-    for (feat, deps) in &feature_deps {
-        for dep in deps {
-            dep_features.entry(dep.clone()).or_default().insert(feat.clone());
+    Ok(doc.to_string())
+}
+
+/// Set `optional = true` on a `[dependencies]` (or `[target.*.dependencies]`)
+/// entry, turning `foo = "1"` into `foo = { version = "1", optional = true }`.
+/// Returns the key as written, or `None` if no such dependency exists.
+fn mark_optional(doc: &mut toml_edit::DocumentMut, dep: &str) -> Option<String> {
+    let mut found = None;
+
+    if let Some(tbl) = doc.get_mut("dependencies").and_then(|t| t.as_table_like_mut()) {
+        found = mark_optional_in(tbl, dep);
+    }
+    if let Some(targets) = doc.get_mut("target").and_then(|t| t.as_table_like_mut()) {
+        for (_tname, titem) in targets.iter_mut() {
+            let tbl = titem
+                .as_table_like_mut()
+                .and_then(|t| t.get_mut("dependencies"))
+                .and_then(|t| t.as_table_like_mut());
+            if let Some(key) = tbl.and_then(|t| mark_optional_in(t, dep)) {
+                found.get_or_insert(key);
+            }
         }
     }
 
-    Ok(ManifestInfo {
-        crate_name,
-        features_raw,
-        all_deps,
-        optional_deps,
-        feature_deps,
-        dep_features,
-    })
+    found
+}
+
+fn mark_optional_in(tbl: &mut dyn toml_edit::TableLike, dep: &str) -> Option<String> {
+    let key = tbl.iter().map(|(k, _)| k.to_string()).find(|k| norm_dep_key(k) == norm_dep_key(dep))?;
+    let item = tbl.get_mut(&key)?;
+
+    if let Some(version) = item.as_str().map(|s| s.to_string()) {
+        let mut t = toml_edit::InlineTable::new();
+        t.insert("version", version.into());
+        t.insert("optional", true.into());
+        // keep a trailing `# comment`
+        if let Some(decor) = item.as_value().map(|v| v.decor().clone()) {
+            *t.decor_mut() = decor;
+        }
+        *item = toml_edit::value(t);
+    } else if item.get("optional").and_then(|x| x.as_bool()) != Some(true) {
+        if let Some(t) = item.as_inline_table_mut() {
+            t.insert("optional", true.into());
+            t.fmt();
+        } else if let Some(t) = item.as_table_like_mut() {
+            t.insert("optional", toml_edit::value(true));
+        }
+    }
+    Some(key)
+}
+
+/// Member crate directories of the workspace whose root manifest is `root_manifest`,
+/// from `[workspace].members` (globs allowed) minus `[workspace].exclude`. A root
+/// manifest that is also a package counts as a member.
+pub fn workspace_members(root_manifest: &Path) -> Result<Vec<PathBuf>> {
+    let raw = fs::read_to_string(root_manifest)?;
+    let doc = raw.parse::<toml_edit::DocumentMut>()
+        .map_err(|e| PassengerError::Toml(e.to_string()))?;
+    let root = root_manifest.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf();
+
+    let ws = doc
+        .get("workspace")
+        .and_then(|w| w.as_table_like())
+        .ok_or_else(|| PassengerError::Toml(format!("{} has no [workspace] table", root_manifest.display())))?;
+    let members = strs_of(Some(ws), "members");
+    let exclude = build_globset(&strs_of(Some(ws), "exclude"))?;
+
+    let mut out = BTreeSet::<PathBuf>::new();
+    if doc.get("package").is_some() {
+        out.insert(root.clone());
+    }
+
+    // explicit members stay members even under an `exclude` entry
+    let mut explicit = BTreeSet::<PathBuf>::new();
+    for pat in &members {
+        let is_glob = pat.contains(['*', '?', '[']);
+        if !is_glob {
+            explicit.insert(root.join(pat));
+            continue;
+        }
+
+        let set = build_globset(std::slice::from_ref(pat))?;
+        // `**` can match any number of dirs
+        let depth = if pat.contains("**") { usize::MAX } else { pat.split('/').count() };
+        for ent in walkdir::WalkDir::new(&root).min_depth(1).max_depth(depth).into_iter().filter_map(|e| e.ok()) {
+            if !ent.file_type().is_dir() {
+                continue;
+            }
+            let Ok(rel) = ent.path().strip_prefix(&root) else { continue };
+            if set.is_match(rel) && ent.path().join("Cargo.toml").is_file() {
+                out.insert(ent.path().to_path_buf());
+            }
+        }
+    }
+
+    out.retain(|p| p.strip_prefix(&root).map(|rel| !exclude.is_match(rel)).unwrap_or(true));
+    out.extend(explicit);
+    Ok(out.into_iter().filter(|p| p.join("Cargo.toml").is_file()).collect())
+}
+
+fn build_globset(patterns: &[String]) -> Result<globset::GlobSet> {
+    let mut b = globset::GlobSetBuilder::new();
+    for p in patterns {
+        let glob = globset::Glob::new(p).map_err(|e| PassengerError::Toml(format!("bad glob `{p}`: {e}")))?;
+        b.add(glob);
+    }
+    b.build().map_err(|e| PassengerError::Toml(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tree;
+
+    fn load(dir: &tempfile::TempDir, rel: &str) -> Result<ManifestInfo> {
+        load_manifest(dir.path().join(rel).to_str().unwrap())
+    }
+
+    const PKG: &str = "[package]\nname = \"a\"\nversion = \"0.1.0\"\n";
+
+    #[test]
+    fn renamed_deps_keep_their_code_ident_and_package() {
+        let dir = tree(&[(
+            "Cargo.toml",
+            &format!("{PKG}\n[dependencies]\nfoo = {{ package = \"bar-baz\", version = \"1\", optional = true }}\n\n[features]\nx = [\"dep:foo\"]\n"),
+        )]);
+        let m = load(&dir, "Cargo.toml").unwrap();
+
+        let foo = &m.deps["foo"];
+        assert_eq!((foo.key.as_str(), foo.package.as_str(), foo.version_req.as_deref()), ("foo", "bar-baz", Some("1")));
+        assert!(m.optional_deps.contains("foo"));
+        assert_eq!(m.feature_deps["x"], ["foo".to_string()].into());
+        assert_eq!(m.package_of("foo"), "bar-baz");
+        assert_eq!(m.dep_label("foo"), "foo (bar-baz)");
+        // not renamed: just the ident
+        assert_eq!(m.dep_label("serde"), "serde");
+    }
+
+    #[test]
+    fn workspace_dependencies_are_inherited_and_merged() {
+        let dir = tree(&[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"a\"]\n\n[workspace.dependencies]\n\
+                 tokio = { version = \"1\", features = [\"rt\"] }\nser = { package = \"serde\", version = \"1\" }\n",
+            ),
+            (
+                "a/Cargo.toml",
+                &format!(
+                    "{PKG}\n[dependencies]\ntokio = {{ workspace = true, optional = true, features = [\"net\"] }}\n\
+                     ser.workspace = true\n"
+                ),
+            ),
+        ]);
+        let m = load(&dir, "a/Cargo.toml").unwrap();
+
+        let tokio = &m.deps["tokio"];
+        assert!(tokio.workspace && tokio.optional);
+        assert_eq!(tokio.version_req.as_deref(), Some("1"));
+        assert_eq!(tokio.features, ["rt", "net"]);
+        assert_eq!(tokio.sources["version"], SettingSource::Workspace);
+        assert_eq!(tokio.sources["features"], SettingSource::Both);
+        assert_eq!(m.deps["ser"].package, "serde");
+        assert!(m.optional_deps.contains("tokio") && !m.optional_deps.contains("ser"));
+    }
+
+    #[test]
+    fn inheriting_an_undeclared_workspace_dep_fails() {
+        let dir = tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\"]\n"),
+            ("a/Cargo.toml", &format!("{PKG}\n[dependencies]\nnope.workspace = true\n")),
+        ]);
+        assert!(load(&dir, "a/Cargo.toml").is_err());
+    }
+
+    #[test]
+    fn workspace_root_requires_membership() {
+        let dir = tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"crates/*\"]\nexclude = [\"crates/skip\", \"tools\"]\n"),
+            ("crates/a/Cargo.toml", PKG),
+            ("crates/skip/Cargo.toml", PKG),
+            ("tools/gen/Cargo.toml", PKG),
+            ("stray/Cargo.toml", PKG),
+        ]);
+        let root = |rel: &str| find_workspace_root(&dir.path().join(rel));
+
+        assert_eq!(root("crates/a/Cargo.toml").unwrap(), Some(dir.path().join("Cargo.toml")));
+        assert_eq!(root("crates/skip/Cargo.toml").unwrap(), None);
+        assert_eq!(root("tools/gen/Cargo.toml").unwrap(), None);
+        assert!(root("stray/Cargo.toml").is_err());
+    }
+
+    #[test]
+    fn explicit_members_win_over_exclude() {
+        let dir = tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"tools/gen\"]\nexclude = [\"tools\"]\n"),
+            ("tools/gen/Cargo.toml", PKG),
+        ]);
+        let found = find_workspace_root(&dir.path().join("tools/gen/Cargo.toml")).unwrap();
+        assert_eq!(found, Some(dir.path().join("Cargo.toml")));
+    }
+
+    #[test]
+    fn feature_graph_closes_over_edges_and_weak_deps() {
+        let dir = tree(&[(
+            "Cargo.toml",
+            &format!(
+                "{PKG}\n[dependencies]\nx = {{ version = \"1\", optional = true }}\n\
+                 y = {{ version = \"1\", optional = true }}\nz = {{ version = \"1\", optional = true }}\n\
+                 w = {{ version = \"1\", optional = true }}\n\n\
+                 [features]\ndefault = [\"a\"]\na = [\"b\", \"dep:x\"]\nb = [\"y/feat\"]\nc = [\"z?/f\"]\n\
+                 z = [\"dep:z\"]\n"
+            ),
+        )]);
+        let m = load(&dir, "Cargo.toml").unwrap();
+        let g = &m.feature_graph;
+        let set = |xs: &[&str]| xs.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>();
+
+        // `y/feat` turns on `y`'s implicit feature too
+        assert_eq!(g.closure["default"], set(&["a", "b", "y"]));
+        assert_eq!(g.closure_deps["default"], set(&["x", "y"]));
+        assert_eq!(g.closure_deps["b"], set(&["y"]));
+        // `z?/f` doesn't turn `z` on
+        assert!(g.closure_deps["c"].is_empty());
+        assert_eq!(g.weak_deps["c"], set(&["z"]));
+        // `w` is never named via `dep:`, so Cargo adds `w = ["dep:w"]`; `y` is only named as `y/feat`
+        assert_eq!(g.implicit, set(&["w", "y"]));
+        assert_eq!(g.default_on, set(&["default", "a", "b", "y"]));
+        assert_eq!(m.dep_features["x"], set(&["a", "default"]));
+    }
+
+    fn groups(entries: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+        entries.iter().map(|(g, deps)| (g.to_string(), deps.iter().map(|d| d.to_string()).collect())).collect()
+    }
+
+    #[test]
+    fn sync_writes_groups_and_is_idempotent() {
+        let raw = format!(
+            "{PKG}\n[dependencies]\ntokio = \"1\" # runtime\ntokio-stream = {{ version = \"0.1\" }}\nserde = \"1\"\n"
+        );
+        let once = sync_feature_groups(&raw, &groups(&[("async", &["tokio", "tokio_stream"])])).unwrap();
+
+        assert!(once.contains("tokio = { version = \"1\", optional = true } # runtime"), "{once}");
+        assert!(once.contains("tokio-stream = { version = \"0.1\", optional = true }"), "{once}");
+        assert!(once.contains("serde = \"1\"\n"));
+        assert!(once.contains("async = [\"dep:tokio\", \"dep:tokio-stream\"]"), "{once}");
+
+        let twice = sync_feature_groups(&once, &groups(&[("async", &["tokio", "tokio_stream"])])).unwrap();
+        assert_eq!(once, twice);
+    }
+
+    #[test]
+    fn sync_edits_existing_arrays_in_place() {
+        let raw = format!(
+            "{PKG}\n[dependencies]\na = {{ version = \"1\", optional = true }}\nb = {{ version = \"1\", optional = true }}\n\
+             c = {{ version = \"1\", optional = true }}\n\n[features]\ngrp = [\n    # keep me\n    \"dep:a\",\n    \"other/x\",\n    \"dep:b\",\n]\nother = []\n"
+        );
+        let out = sync_feature_groups(&raw, &groups(&[("grp", &["a", "c"])])).unwrap();
+        let grp = out.split("grp = ").nth(1).unwrap().split("\nother").next().unwrap();
+
+        assert!(grp.contains("# keep me"), "{grp}");
+        assert!(grp.contains("\"dep:a\"") && grp.contains("\"dep:c\"") && grp.contains("\"other/x\""), "{grp}");
+        assert!(!grp.contains("dep:b"), "{grp}");
+    }
+
+    #[test]
+    fn plain_members_only_become_dep_without_an_explicit_feature() {
+        let raw = format!(
+            "{PKG}\n[dependencies]\nfoo = \"1\"\nbar = \"1\"\n\n[features]\nuses-foo = [\"foo\"]\nuses-bar = [\"bar\"]\n\
+             bar = [\"dep:bar\", \"extra\"]\nextra = []\n"
+        );
+        let out = sync_feature_groups(&raw, &groups(&[("net", &["foo", "bar"])])).unwrap();
+
+        assert!(out.contains("uses-foo = [\"dep:foo\"]"), "{out}");
+        assert!(out.contains("uses-bar = [\"bar\"]"), "{out}");
+    }
+
+    #[test]
+    fn sync_rejects_unknown_deps() {
+        let raw = format!("{PKG}\n[dependencies]\nfoo = \"1\"\n");
+        assert!(sync_feature_groups(&raw, &groups(&[("g", &["nope"])])).is_err());
+    }
+
+    #[test]
+    fn member_globs_reach_nested_crates() {
+        let dir = tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"crates/*\", \"libs/**\"]\nexclude = [\"libs/old/**\"]\n"),
+            ("crates/a/Cargo.toml", PKG),
+            ("crates/a/nested/Cargo.toml", PKG),
+            ("libs/x/y/z/Cargo.toml", PKG),
+            ("libs/old/legacy/Cargo.toml", PKG),
+            ("libs/notacrate/readme.md", ""),
+        ]);
+        let members: Vec<String> = workspace_members(&dir.path().join("Cargo.toml"))
+            .unwrap()
+            .iter()
+            .map(|p| p.strip_prefix(dir.path()).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        assert_eq!(members, ["crates/a", "libs/x/y/z"]);
+    }
 }
@@ -1,10 +1,13 @@
-use crate::analysis::run as r;
-use crate::engine::{RunContext, run_scan};
+use crate::analysis::{run as r, run_workspace};
+use crate::cargo::sync_feature_groups;
+use crate::config::{CliOverrides, load_config};
+use crate::engine::{EngineOutput, RunContext, run_scan, run_workspace_scan, unsynced_groups, workspace_contexts};
+use crate::model::{CfgExpr, ChangeReason, FileReport, GateLevel, ModGate, PlanSource};
 use crate::error::{PassengerError, Result};
 use crate::passenger::command::{PassengerCmd, passenger};
-use crate::passenger::{self, CheckpointOptions, PassengerStore};
 use crate::{packs, plans};
 use clap::{Parser, Subcommand};
+use serde::Serialize;
 use std::fs;
 use std::path::PathBuf;
 
@@ -16,24 +19,42 @@ pub struct Cli {
     #[arg(long, default_value = ".")]
     pub root: PathBuf,
 
-    /// Relative source directory to scan
-    #[arg(long, default_value = "src")]
-    pub src: String,
+    /// Relative source directory to scan [default: src]
+    #[arg(long)]
+    pub src: Option<String>,
 
     /// Manifest path (defaults to {root}/Cargo.toml)
     #[arg(long)]
     pub manifest: Option<PathBuf>,
 
-    #[arg(long, default_value = "rust")]
-    pub lang: String,
+    /// Language pack [default: rust]
+    #[arg(long)]
+    pub lang: Option<String>,
 
-    #[arg(long, default_value = "verbose")]
-    pub plan: String,
+    /// Header plan: built-in (`verbose`, `compact`) or `.passenger/plans/<name>.tpl` [default: verbose]
+    #[arg(long)]
+    pub plan: Option<String>,
 
     /// Emit JSON instead of human output
     #[arg(long)]
     pub json: bool,
 
+    /// Only scan files matching this glob (relative to the crate root; repeatable)
+    #[arg(long)]
+    pub include: Vec<String>,
+
+    /// Never scan files matching this glob (relative to the crate root; repeatable)
+    #[arg(long)]
+    pub exclude: Vec<String>,
+
+    /// Skip files ignored by .gitignore/.ignore
+    #[arg(long)]
+    pub gitignore: bool,
+
+    /// Run over every member of the workspace `--root` belongs to
+    #[arg(long)]
+    pub workspace: bool,
+
     #[command(subcommand)]
     pub cmd: Command,
 }
@@ -43,14 +64,42 @@ pub enum Command {
     /// Scan and print reports
     Scan,
     /// Annotate files (insert/replace header blocks)
+    ///
+    /// The end marker carries a hash of the computed header lines so hand edits
+    /// to them show up as conflicts; headers written before the stamp existed
+    /// get it, and so change once, on their next run. With a conflict anywhere
+    /// `--write` writes nothing.
     Annotate {
-        /// Actually write changes (otherwise dry-run)
+        /// Actually write changes (otherwise dry-run: a unified diff per changed file)
         #[arg(long)]
         write: bool,
 
-        /// Exit non-zero if changes would be made (CI mode)
+        /// List stale files with the reason and exit non-zero if any (CI mode)
         #[arg(long)]
         check: bool,
+
+        /// Overwrite headers whose computed fields were edited by hand
+        #[arg(long)]
+        force: bool,
+
+        /// Where feature gates go: `file` (`#![cfg]` in the header) or `item`
+        /// (`#[cfg]` on the items that need them) [default: config or file]
+        #[arg(long)]
+        gate_level: Option<String>,
+    },
+    /// Remove header blocks (and with `--gates` the generated item and `mod` gates)
+    Strip {
+        /// Actually write changes (otherwise dry-run)
+        #[arg(long)]
+        write: bool,
+
+        /// Print a unified diff per changed file
+        #[arg(long)]
+        diff: bool,
+
+        /// Also remove the item-level and `mod` gates annotate generated
+        #[arg(long)]
+        gates: bool,
     },
     Scaffold {
         #[arg(long)]
@@ -73,83 +122,289 @@ pub enum Command {
         #[clap(subcommand)]
         cmd: PassengerCmd,
     },
+    /// Manage [features] in Cargo.toml from config feature groups
+    Features {
+        #[command(subcommand)]
+        cmd: FeaturesCmd,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum FeaturesCmd {
+    /// Write `[feature_groups]` from code_passenger.toml into Cargo.toml (with
+    /// `--workspace`, also the workspace root's `[workspace.feature_groups]`)
+    Sync {
+        /// Actually write changes (otherwise dry-run)
+        #[arg(long)]
+        write: bool,
+
+        /// Exit non-zero if Cargo.toml would change (CI mode)
+        #[arg(long, conflicts_with = "write")]
+        check: bool,
+
+        /// Print a unified diff of the planned change
+        #[arg(long)]
+        diff: bool,
+    },
 }
 
 pub fn run() -> Result<()> {
+    run_with(&plans::SectionRegistry::default())
+}
+
+/// [`run`] with custom header sections (`PlanSection::Custom`) available to the
+/// plans.
+pub fn run_with(sections: &plans::SectionRegistry) -> Result<()> {
     let cli = Cli::parse();
     let manifest_path = cli
         .manifest
         .clone()
         .unwrap_or_else(|| cli.root.join("Cargo.toml"));
 
+    let overrides = CliOverrides {
+        lang: cli.lang.clone(),
+        plan: cli.plan.clone(),
+        src: cli.src.clone(),
+        include: cli.include.clone(),
+        exclude: cli.exclude.clone(),
+        gitignore: cli.gitignore,
+    };
+    let mut config = load_config(&cli.root)?;
+    config.merge_cli(&overrides);
+
     let ctx = RunContext {
         root: cli.root.clone(),
-        src_rel: cli.src.clone(),
+        src_rel: config.src().to_string(),
         manifest_path,
-        lang: cli.lang.clone(),
+        lang: config.lang().to_string(),
+        config,
+        cli: overrides,
     };
 
     match cli.cmd {
         Command::Scan => {
-            let out = run_scan(&ctx)?;
             if cli.json {
                 // println!("{}", serde_json::to_string_pretty(&out.reports).unwrap());
 
-                let a = r(&out);
                 let file = std::fs::File::create("report.json")?;
                 let writer = std::io::BufWriter::new(file);
 
-                let _ = serde_json::to_writer_pretty(writer, &a);
+                if cli.workspace {
+                    let _ = serde_json::to_writer_pretty(writer, &run_workspace(&run_workspace_scan(&ctx)?));
+                } else {
+                    let _ = serde_json::to_writer_pretty(writer, &r(&run_scan(&ctx)?));
+                }
                 println!("Saved report to report.json");
+            } else if cli.workspace {
+                for (name, m) in &run_workspace_scan(&ctx)?.crates {
+                    println!("#### crate {name} ({}) ####", m.root.display());
+                    if !m.unsynced_groups.is_empty() {
+                        println!("feature groups not in Cargo.toml yet (run features sync --write): {}", m.unsynced_groups.join(", "));
+                    }
+                    print_scan(&m.output, ctx.config.top_n());
+                }
             } else {
-                let top_n = 5;
+                print_scan(&run_scan(&ctx)?, ctx.config.top_n());
+            }
+        }
 
-                for r in &out.reports {
-                    println!("== {} ==", r.document.relative_path);
+        Command::Annotate { write, check, force, gate_level } => {
+            let mut changed_any = false;
+            let mut conflicts = 0;
+            let (mut stale, mut total) = (0, 0);
+            let mut planned: Vec<PlannedFile> = vec![];
+            // written once every unit is in, and only if no header conflicts
+            let mut pending: Vec<(PathBuf, String, Option<String>)> = vec![];
+            let gate_level = gate_level
+                .map(|s| GateLevel::parse(&s).ok_or_else(|| PassengerError::Unsupported(format!("unknown gate level '{s}'"))))
+                .transpose()?;
+
+            for unit in units(cli.workspace, ctx)? {
+                let pack = packs::get_pack(&unit.lang)?;
+                let level = gate_level.unwrap_or(unit.config.gate_level());
+                let out = run_scan(&unit)?;
+                let unsynced = unsynced_groups(&unit)?;
+                if !unsynced.is_empty() {
+                    eprintln!("note: {}: feature groups not in Cargo.toml yet, not gated: {}", unit.manifest_path.display(), unsynced.join(", "));
+                }
+                let analysis = r(&out);
+
+                // crate-relative path -> file; parent file -> (child module, its file-level gate)
+                let mut files = BTreeMap::<String, Annotated>::new();
+                let mut children = BTreeMap::<String, Vec<(String, Option<CfgExpr>)>>::new();
+
+                // tests, examples, ... only when configured; build scripts never
+                for r in out.reports.iter().filter(|r| unit.config.annotates(r.target)) {
+                    let scan_root = r.document.scan_root.as_deref().unwrap_or(&unit.src_rel);
+                    let crate_rel = format!("{scan_root}/{}", r.document.relative_path);
+                    let crate_rel = crate_rel.strip_prefix("./").unwrap_or(&crate_rel).to_string();
+                    let path = unit.root.join(scan_root).join(&r.document.relative_path);
+                    let original = fs::read_to_string(&path)?;
+
+                    let (plan_name, source) = match pack.plan_directive(&original) {
+                        Some(name) => (name, PlanSource::Directive),
+                        None => unit.config.plan_for(&crate_rel),
+                    };
+                    let opts = unit.config.plan_options(&crate_rel)?;
+                    let plan = plans::get_plan(&unit.root, plan_name, opts.clone())
+                        .map_err(|e| PassengerError::Config(format!("{crate_rel}: plan from {source}: {e}")))?;
+                    sections.check(plan.sections())?;
+
+                    // item-level gating leaves the header only what every item needs
+                    let mut r = r.clone();
+                    let body = match pack.gate_items(&original, &r, level) {
+                        Some(gated) => {
+                            r.corpus_features = gated.file_features;
+                            gated.content
+                        }
+                        None => original.clone(),
+                    };
+                    let fresh = pack.render_header(plan.as_ref(), &plans::RenderContext { report: &r, analysis: &analysis, sections });
+                    let header = plans::reconcile(plan.as_ref(), &fresh, pack.existing_header(&body));
+                    let conflict = header.conflict && !force;
+                    if conflict {
+                        conflicts += 1;
+                        eprintln!("conflict: {}: computed header fields were edited by hand; left as is", path.display());
+                    }
+                    let updated = match conflict {
+                        true => original.clone(),
+                        false => pack.apply_header(&body, &header.header),
+                    };
+
+                    if let Some(parent) = &r.module_declared_in {
+                        children.entry(parent.clone()).or_default().push((r.module_path.clone(), plan.select_gates(&r, &opts.gates)));
+                    }
+                    let plan = (plan_name.to_string(), source);
+                    files.insert(crate_rel, Annotated { path, original, updated, module_path: r.module_path.clone(), conflict, plan });
+                }
 
-                    print_set("used_deps", &r.used.packages); // or r.used.packages
-                    print_set("used_mods", &r.used.modules); // or r.used.modules
-                    print_set("corpus_features", &r.corpus_features);
+                // second pass: gate `mod child;` in parents of gated files
+                for (parent, kids) in children {
+                    let Some(f) = files.get_mut(&parent).filter(|f| !f.conflict) else { continue };
+                    let prefix = format!("{}::", f.module_path);
+                    let gates: Vec<ModGate> = kids
+                        .into_iter()
+                        .filter_map(|(module, gate)| {
+                            let rel = module.strip_prefix(&prefix)?;
+                            Some(ModGate { path: rel.split("::").map(str::to_string).collect(), gate: gate? })
+                        })
+                        .collect();
+                    if gates.is_empty() {
+                        continue;
+                    }
+                    if let Some(updated) = pack.gate_mod_decls(&f.updated, &gates) {
+                        f.updated = updated;
+                    }
+                }
 
-                    // Optional: quick totals
-                    println!(
-                        "use_sites: external={}, internal={}",
-                        r.external_use_sites.len(),
-                        r.internal_use_sites.len()
-                    );
+                for (rel, f) in &files {
+                    let (plan, source) = &f.plan;
+                    let changed = f.updated != f.original;
+                    let reasons = pack.change_reasons(&f.original, &f.updated);
+                    let why = reasons.iter().map(|r| r.to_string()).collect::<Vec<_>>().join(", ");
+                    total += 1;
+
+                    if cli.json {
+                        planned.push(PlannedFile {
+                            path: f.path.display().to_string(),
+                            crate_rel: rel.clone(),
+                            plan: plan.clone(),
+                            plan_source: source.clone(),
+                            status: match (f.conflict, changed) {
+                                (true, _) => "conflict",
+                                (false, true) => "changed",
+                                (false, false) => "unchanged",
+                            },
+                            reasons,
+                            patch: changed.then(|| unified_diff(&format!("a/{rel}"), &format!("b/{rel}"), &f.original, &f.updated)),
+                            edits: line_edits(&f.original, &f.updated),
+                        });
+                    } else if check {
+                        if changed {
+                            println!("{rel}: stale ({why})");
+                        }
+                    } else if f.conflict {
+                        println!("{rel}: plan {plan} ({source}), conflict, left as is");
+                    } else if !changed {
+                        println!("{rel}: plan {plan} ({source}), up to date");
+                    } else if !write {
+                        println!("{rel}: plan {plan} ({source}), would change ({why})");
+                        print_diff(rel, &f.original, &f.updated);
+                    }
 
-                    print_counts("external_symbols", &r.external_dep_symbol_counts, top_n);
-                    print_counts("internal_symbols", &r.internal_dep_symbol_counts, top_n);
+                    if changed {
+                        changed_any = true;
+                        stale += 1;
+                        if write {
+                            let line = (!cli.json && !check).then(|| format!("{rel}: plan {plan} ({source}), updated ({why})"));
+                            pending.push((f.path.clone(), f.updated.clone(), line));
+                        }
+                    }
+                }
+            }
 
-                    println!();
+            if write && conflicts > 0 {
+                eprintln!("no files written");
+            } else {
+                for (path, updated, line) in pending {
+                    fs::write(&path, updated)?;
+                    if let Some(line) = line {
+                        println!("{line}");
+                    }
                 }
             }
+
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&planned)?);
+            } else if stale > 0 && !write {
+                println!("{stale} of {total} files would change{}", if check { "" } else { " (run with --write)" });
+            }
+
+            if conflicts > 0 {
+                return Err(PassengerError::HeaderConflicts(conflicts));
+            }
+            if check && changed_any {
+                return Err(PassengerError::ChangesNeeded);
+            }
         }
 
-        Command::Annotate { write, check } => {
-            let out = run_scan(&ctx)?;
-            let pack = packs::get_pack(&cli.lang)?;
-            let plan = plans::get_plan(&cli.plan)?;
+        Command::Strip { write, diff, gates } => {
+            let mut stripped_any = false;
 
-            let mut changed_any = false;
+            for unit in units(cli.workspace, ctx)? {
+                let pack = packs::get_pack(&unit.lang)?;
+                let out = run_scan(&unit)?;
 
-            for r in &out.reports {
-                let header = pack.render_header(plan.as_ref(), r);
+                // skipped files too: they may carry headers from before they were skipped
+                let mut paths = BTreeSet::<String>::new();
+                // tests, examples, ... only when configured; build scripts never
+                for r in out.reports.iter().filter(|r| unit.config.annotates(r.target)) {
+                    let scan_root = r.document.scan_root.as_deref().unwrap_or(&unit.src_rel);
+                    let crate_rel = format!("{scan_root}/{}", r.document.relative_path);
+                    paths.insert(crate_rel.strip_prefix("./").unwrap_or(&crate_rel).to_string());
+                }
+                paths.extend(out.skipped.iter().map(|s| s.path.clone()));
 
-                let file_path = cli.root.join(&cli.src).join(&r.document.relative_path);
-                let original = fs::read_to_string(&file_path)?;
-                let updated = pack.apply_header(&original, &header);
+                for rel in paths {
+                    let path = unit.root.join(&rel);
+                    let Ok(original) = fs::read_to_string(&path) else { continue };
+                    let Some(stripped) = pack.strip(&original, gates) else { continue };
 
-                if updated != original {
-                    changed_any = true;
+                    stripped_any = true;
+                    if diff {
+                        print_diff(&rel, &original, &stripped);
+                    }
                     if write {
-                        fs::write(&file_path, updated)?;
+                        fs::write(&path, &stripped)?;
+                        println!("stripped {}", path.display());
+                    } else if !diff {
+                        println!("{}: would strip (run with --write or --diff)", path.display());
                     }
                 }
             }
 
-            if check && changed_any {
-                return Err(PassengerError::ChangesNeeded);
+            if !stripped_any {
+                println!("nothing to strip");
             }
         }
 
@@ -160,7 +415,7 @@ pub fn run() -> Result<()> {
             scaffold_plan,
             write,
         } => {
-            let pack = packs::get_pack(&cli.lang)?;
+            let pack = packs::get_pack(&ctx.lang)?;
             let plan = crate::scaffolds::get_scaffold_plan(&scaffold_plan)?;
 
             let kind = crate::model::ScaffoldKind::parse(&kind).ok_or_else(|| {
@@ -202,12 +457,151 @@ pub fn run() -> Result<()> {
             }
         }
         Command::Passenger { cmd } => { passenger(cmd,ctx)? }
+
+        Command::Features { cmd } => match cmd {
+            FeaturesCmd::Sync { write, check, diff } => {
+                let mut out_of_sync = false;
+
+                for unit in units(cli.workspace, ctx)? {
+                    let original = fs::read_to_string(&unit.manifest_path)?;
+                    let updated = sync_feature_groups(&original, &unit.config.feature_groups)?;
+
+                    if updated == original {
+                        println!("{}: feature groups in sync", unit.manifest_path.display());
+                        continue;
+                    }
+
+                    out_of_sync = true;
+                    if diff {
+                        print_diff(&unit.manifest_path.display().to_string(), &original, &updated);
+                    }
+                    if write {
+                        fs::write(&unit.manifest_path, &updated)?;
+                        println!("updated {}", unit.manifest_path.display());
+                    } else if !diff {
+                        println!("{}: would update (run with --write or --diff)", unit.manifest_path.display());
+                    }
+                }
+
+                if check && out_of_sync {
+                    return Err(PassengerError::FeaturesOutOfSync);
+                }
+            }
+        },
     }
 
     Ok(())
 }
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// One file annotate may rewrite.
+struct Annotated {
+    path: PathBuf,
+    original: String,
+    updated: String,
+    module_path: String,
+    /// hand-edited header; left untouched
+    conflict: bool,
+    /// header plan applied, and what picked it
+    plan: (String, PlanSource),
+}
+
+/// One file of `annotate --json`.
+#[derive(Debug, Serialize)]
+struct PlannedFile {
+    path: String,
+    crate_rel: String,
+    plan: String,
+    plan_source: PlanSource,
+    /// `changed`, `unchanged` or `conflict` (hand-edited header, left as is)
+    status: &'static str,
+    reasons: Vec<ChangeReason>,
+    /// unified diff, `a/<crate_rel>` -> `b/<crate_rel>`
+    patch: Option<String>,
+    edits: Vec<LineEdit>,
+}
+
+#[derive(Debug, Serialize)]
+struct LineEdit {
+    old_start: usize,
+    old_end: usize,
+    text: String,
+}
+
+/// The crates a command runs over: every workspace member with `--workspace`,
+/// otherwise just `--root`.
+fn units(workspace: bool, ctx: RunContext) -> Result<Vec<RunContext>> {
+    if workspace {
+        workspace_contexts(&ctx)
+    } else {
+        Ok(vec![ctx])
+    }
+}
+
+fn print_scan(out: &EngineOutput, top_n: usize) {
+    for r in &out.reports {
+        print_report(out, r, top_n);
+    }
+
+    if !out.orphans.is_empty() {
+        println!("orphans (not declared by any mod item):");
+        for o in &out.orphans {
+            println!("  {o}");
+        }
+    }
+
+    for m in &out.missing_modules {
+        println!("missing module: {} (declared in {})", m.module_path, m.declared_in);
+    }
+
+    if !out.skipped.is_empty() {
+        println!("skipped:");
+        for s in &out.skipped {
+            println!("  {}: {}", s.path, s.reason.describe());
+        }
+    }
+}
+
+fn print_report(out: &EngineOutput, r: &FileReport, top_n: usize) {
+    println!("== {} ==", r.document.display_path());
+    println!("target: {} {}", r.target.as_str(), r.target_name);
+    println!("module: {}", r.module_path);
+    if !r.module_cfg.is_empty() {
+        print_cfgs("module_cfg", &r.module_cfg);
+    }
+    if !r.file_cfg.is_empty() {
+        print_cfgs("file_cfg", &r.file_cfg);
+    }
+    println!("scan_mode: {:?}", r.scan_mode);
+
+    let used_deps: Vec<String> =
+        r.used.packages.iter().map(|d| out.manifest.dep_label(d)).collect();
+    print_set("used_deps", &used_deps);
+    print_set("used_mods", &r.used.modules); // or r.used.modules
+    print_set("corpus_features", &r.corpus_features);
+
+    // Optional: quick totals
+    println!(
+        "use_sites: external={}, internal={}",
+        r.external_use_sites.len(),
+        r.internal_use_sites.len()
+    );
+
+    print_counts("external_symbols", &r.external_dep_symbol_counts, top_n);
+    print_counts("internal_symbols", &r.internal_dep_symbol_counts, top_n);
+
+    if !r.ungated_uses.is_empty() {
+        println!("ungated_uses:");
+        for u in &r.ungated_uses {
+            let gate = u.gate.as_ref().map(|g| format!(" under {g}")).unwrap_or_default();
+            let runtime = u.runtime_cfg.as_ref().map(|g| format!(" (runtime: cfg!({g}))")).unwrap_or_default();
+            println!("  {} line {} in {}{gate}{runtime}; needs one of {:?}", out.manifest.dep_label(&u.dep), u.line, u.scope, u.features);
+        }
+    }
+
+    println!();
+}
 
 fn preview_syms(syms: &BTreeMap<String, usize>, top_n: usize) -> String {
     let mut v: Vec<(&String, &usize)> = syms.iter().collect();
@@ -241,6 +635,59 @@ fn print_counts(title: &str, counts: &BTreeMap<String, BTreeMap<String, usize>>,
     }
 }
 
+fn unified_diff(old_label: &str, new_label: &str, old: &str, new: &str) -> String {
+    let diff = similar::TextDiff::from_lines(old, new);
+    diff.unified_diff().context_radius(3).header(old_label, new_label).to_string()
+}
+
+/// Unified diff, colored when stdout is a terminal and `NO_COLOR` is unset.
+fn print_diff(path: &str, old: &str, new: &str) {
+    use std::io::IsTerminal;
+
+    let diff = unified_diff(path, path, old, new);
+    if !std::io::stdout().is_terminal() || std::env::var_os("NO_COLOR").is_some() {
+        print!("{diff}");
+        return;
+    }
+    for line in diff.lines() {
+        let color = if line.starts_with("+++") || line.starts_with("---") {
+            "1"
+        } else if line.starts_with("@@") {
+            "36"
+        } else if line.starts_with('+') {
+            "32"
+        } else if line.starts_with('-') {
+            "31"
+        } else {
+            ""
+        };
+        match color {
+            "" => println!("{line}"),
+            c => println!("\x1b[{c}m{line}\x1b[0m"),
+        }
+    }
+}
+
+/// `old_start..old_end` (0-based lines of the original) replaced by the lines in
+/// `text`.
+fn line_edits(old: &str, new: &str) -> Vec<LineEdit> {
+    let diff = similar::TextDiff::from_lines(old, new);
+    diff.ops()
+        .iter()
+        .filter(|op| op.tag() != similar::DiffTag::Equal)
+        .map(|op| LineEdit {
+            old_start: op.old_range().start,
+            old_end: op.old_range().end,
+            text: diff.new_slices()[op.new_range()].concat(),
+        })
+        .collect()
+}
+
+fn print_cfgs(label: &str, cfgs: &[crate::model::CfgExpr]) {
+    let cfgs: Vec<String> = cfgs.iter().map(|c| c.to_string()).collect();
+    println!("{label}: {}", cfgs.join(", "));
+}
+
 fn print_set<T: std::fmt::Debug>(label: &str, set: &T) {
     println!("{label}: {set:?}");
 }
@@ -0,0 +1,531 @@
+//! Project configuration, read from `code_passenger.toml` next to `Cargo.toml` or
+//! from `[package.metadata.code_passenger]` (`[workspace.metadata.code_passenger]`
+//! in a virtual manifest). CLI flags win over both.
+use crate::{
+    error::{PassengerError, Result},
+    model::{AttrCrates, CfgExpr, GateLevel, GateOptions, PlanOptions, PlanSection, PlanSource, TargetKind},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+pub const CONFIG_FILE: &str = "code_passenger.toml";
+const METADATA_KEY: &str = "code_passenger";
+
+pub const DEFAULT_LANG: &str = "rust";
+pub const DEFAULT_PLAN: &str = "verbose";
+pub const DEFAULT_SRC: &str = "src";
+pub const DEFAULT_TOP_N: usize = 5;
+pub const DEFAULT_MAX_USAGES: usize = 8;
+/// features that count as corpus features even when they gate no dependency
+pub const DEFAULT_CORPUS_WHITELIST: [&str; 3] = ["std", "alloc", "no_std"];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// language pack (`--lang`)
+    pub lang: Option<String>,
+    /// header plan (`--plan`)
+    pub plan: Option<String>,
+    /// source dir relative to the crate root (`--src`)
+    pub src: Option<String>,
+    /// more dirs to scan besides `src` (`examples`, `benches`, ...)
+    pub extra_roots: Vec<String>,
+    /// globs, relative to the crate root, of files to scan; empty = everything
+    pub include: Vec<String>,
+    /// globs, relative to the crate root, of files never scanned (`src/generated/**`)
+    pub exclude: Vec<String>,
+    /// skip files matched by `.gitignore` / `.ignore` files
+    pub respect_gitignore: bool,
+    /// kinds of targets annotate writes headers and gates into (`lib`, `bin`,
+    /// `test`, `example`, `bench`, `other`); default lib and bin. Build scripts
+    /// are never annotated: a gate there would remove `main`.
+    pub annotate_targets: Option<Vec<TargetKind>>,
+    /// `file` (default) or `item`: where annotate puts feature gates
+    pub gate_level: Option<GateLevel>,
+    /// groups of interchangeable features (`[["rt-tokio", "rt-async-std"]]`); a
+    /// file needing several from one group is gated on `any(...)` of them
+    pub gate_alternatives: Vec<Vec<String>>,
+    /// also emit `#![cfg_attr(docsrs, doc(cfg(...)))]` next to file gates (the
+    /// crate root needs `#![cfg_attr(docsrs, feature(doc_cfg))]`)
+    pub docsrs: bool,
+    /// drop corpus features another selected feature turns on anyway (`json` +
+    /// `serde_json` gates as just `json`); off by default since it changes gates
+    pub prune_implied_features: bool,
+    /// replaces [`DEFAULT_CORPUS_WHITELIST`]
+    pub corpus_whitelist: Option<Vec<String>>,
+    /// how many symbols per dep `scan` prints
+    pub top_n: Option<usize>,
+    /// how many files the header's `usages:` field lists before `(+N more)`
+    pub max_usages: Option<usize>,
+    /// header sections to render, in order, instead of the plan's own:
+    /// `document_details`, `feature_notes`, `corpus_gates`, or the name of a
+    /// registered custom section (`OWNERS`)
+    pub sections: Option<Vec<String>>,
+    /// dir relative to the crate root (`src/net`) or glob (`src/api/**`,
+    /// `src/bin/*.rs`) -> settings for the files it covers; the longest matching
+    /// key wins
+    pub overrides: BTreeMap<String, DirOverride>,
+    /// derive name -> crate (`Serialize = "serde"`), layered over the pack's built-ins
+    pub derives: BTreeMap<String, String>,
+    /// helper attribute name -> crate (`serde = "serde"`)
+    pub helper_attrs: BTreeMap<String, String>,
+    /// feature name -> deps it should turn on (`async = ["tokio", "tokio-stream"]`);
+    /// `features sync` writes these into Cargo.toml
+    pub feature_groups: BTreeMap<String, Vec<String>>,
+    /// settings read from the workspace root's config in `--workspace` mode
+    pub workspace: WorkspaceConfig,
+}
+
+/// Settings given as CLI flags; they beat every config file, including workspace
+/// members' own.
+#[derive(Debug, Clone, Default)]
+pub struct CliOverrides {
+    pub lang: Option<String>,
+    pub plan: Option<String>,
+    pub src: Option<String>,
+    /// added to the configured lists
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// turns `respect_gitignore` on
+    pub gitignore: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DirOverride {
+    pub plan: Option<String>,
+    pub corpus_whitelist: Option<Vec<String>>,
+    /// cfg predicate replacing the computed file gate (`'any(unix, feature = "compat")'`)
+    pub gate: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    /// concept-doc layout: `async = [...]` plus optional `async-INCLUDE_CRATES = [...]`
+    /// / `async-EXCLUDE_CRATES = [...]` picking which member crates get the group.
+    /// Scan and annotate see them once `features sync` has written them into the
+    /// members' Cargo.toml; until then `scan --workspace` lists them as unsynced.
+    #[serde(default)]
+    pub feature_groups: BTreeMap<String, Vec<String>>,
+}
+
+/// A workspace feature group with its crate selection resolved.
+#[derive(Debug, Clone, Default)]
+pub struct WorkspaceGroup {
+    pub deps: Vec<String>,
+    /// `None` = every member crate
+    pub include_crates: Option<BTreeSet<String>>,
+    pub exclude_crates: BTreeSet<String>,
+}
+
+impl WorkspaceGroup {
+    pub fn applies_to(&self, crate_name: &str) -> bool {
+        let included = self.include_crates.as_ref().is_none_or(|s| s.contains(crate_name));
+        included && !self.exclude_crates.contains(crate_name)
+    }
+}
+
+impl WorkspaceConfig {
+    /// Fold the `-INCLUDE_CRATES`/`-EXCLUDE_CRATES` keys into their groups.
+    pub fn groups(&self) -> Result<BTreeMap<String, WorkspaceGroup>> {
+        let mut out = BTreeMap::<String, WorkspaceGroup>::new();
+        for (k, v) in &self.feature_groups {
+            if k.ends_with("-INCLUDE_CRATES") || k.ends_with("-EXCLUDE_CRATES") {
+                continue;
+            }
+            out.insert(k.clone(), WorkspaceGroup { deps: v.clone(), ..Default::default() });
+        }
+
+        for (k, v) in &self.feature_groups {
+            let (name, include) = match (k.strip_suffix("-INCLUDE_CRATES"), k.strip_suffix("-EXCLUDE_CRATES")) {
+                (Some(n), _) => (n, true),
+                (_, Some(n)) => (n, false),
+                _ => continue,
+            };
+            let group = out.get_mut(name).ok_or_else(|| {
+                PassengerError::Config(format!("workspace.feature_groups.{k}: no feature group `{name}`"))
+            })?;
+            let crates = v.iter().cloned().collect();
+            if include {
+                group.include_crates = Some(crates);
+            } else {
+                group.exclude_crates = crates;
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl ProjectConfig {
+    /// Derive/helper mappings with crate names normalized to code idents.
+    pub fn attr_crates(&self) -> AttrCrates {
+        let norm = |m: &BTreeMap<String, String>| {
+            m.iter().map(|(k, v)| (k.clone(), v.replace('-', "_"))).collect()
+        };
+        AttrCrates {
+            derives: norm(&self.derives),
+            helper_attrs: norm(&self.helper_attrs),
+        }
+    }
+
+    /// Layer CLI flags over the file; unset flags keep the configured value.
+    pub fn merge_cli(&mut self, cli: &CliOverrides) {
+        if let Some(v) = &cli.lang {
+            self.lang = Some(v.clone());
+        }
+        if let Some(v) = &cli.plan {
+            self.plan = Some(v.clone());
+        }
+        if let Some(v) = &cli.src {
+            self.src = Some(v.clone());
+        }
+        self.include.extend(cli.include.iter().cloned());
+        self.exclude.extend(cli.exclude.iter().cloned());
+        self.respect_gitignore |= cli.gitignore;
+    }
+
+    pub fn lang(&self) -> &str {
+        self.lang.as_deref().unwrap_or(DEFAULT_LANG)
+    }
+
+    pub fn src(&self) -> &str {
+        self.src.as_deref().unwrap_or(DEFAULT_SRC)
+    }
+
+    pub fn gate_level(&self) -> GateLevel {
+        self.gate_level.unwrap_or_default()
+    }
+
+    /// Whether annotate touches files of a `kind` target.
+    pub fn annotates(&self, kind: TargetKind) -> bool {
+        if kind == TargetKind::Build {
+            return false;
+        }
+        match &self.annotate_targets {
+            Some(kinds) => kinds.contains(&kind),
+            None => matches!(kind, TargetKind::Lib | TargetKind::Bin),
+        }
+    }
+
+    pub fn top_n(&self) -> usize {
+        self.top_n.unwrap_or(DEFAULT_TOP_N)
+    }
+
+    /// Override with the longest key covering `crate_rel` (a path relative to the
+    /// crate root, `/`-separated): a dir containing it or a glob matching it.
+    pub fn override_for(&self, crate_rel: &str) -> Option<&DirOverride> {
+        self.override_entry(crate_rel).map(|(_, o)| o)
+    }
+
+    fn override_entry(&self, crate_rel: &str) -> Option<(&str, &DirOverride)> {
+        let crate_rel = crate_rel.strip_prefix("./").unwrap_or(crate_rel);
+        self.overrides
+            .iter()
+            .filter(|(key, _)| override_covers(key, crate_rel))
+            .max_by_key(|(key, _)| key.trim_end_matches('/').len())
+            .map(|(key, o)| (key.as_str(), o))
+    }
+
+    /// Header plan for `crate_rel`, and where it came from; a
+    /// `code_passenger:plan=` directive in the file beats all of these.
+    pub fn plan_for(&self, crate_rel: &str) -> (&str, PlanSource) {
+        if let Some((key, plan)) = self.override_entry(crate_rel).and_then(|(k, o)| Some((k, o.plan.as_deref()?))) {
+            return (plan, PlanSource::Override(key.to_string()));
+        }
+        match &self.plan {
+            Some(plan) => (plan, PlanSource::Config),
+            None => (DEFAULT_PLAN, PlanSource::Default),
+        }
+    }
+
+    pub fn corpus_whitelist_for(&self, crate_rel: &str) -> BTreeSet<String> {
+        match self.override_for(crate_rel).and_then(|o| o.corpus_whitelist.as_ref()).or(self.corpus_whitelist.as_ref()) {
+            Some(list) => list.iter().cloned().collect(),
+            None => DEFAULT_CORPUS_WHITELIST.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Gate selection settings for the file at `crate_rel`.
+    pub fn gate_options(&self, crate_rel: &str) -> Result<GateOptions> {
+        let gate = match self.override_for(crate_rel).and_then(|o| o.gate.as_deref()) {
+            Some(g) => Some(parse_gate(g)?),
+            None => None,
+        };
+        Ok(GateOptions { alternatives: self.gate_alternatives.clone(), docsrs: self.docsrs, gate })
+    }
+
+    /// Header rendering settings for the file at `crate_rel`.
+    pub fn plan_options(&self, crate_rel: &str) -> Result<PlanOptions> {
+        Ok(PlanOptions {
+            gates: self.gate_options(crate_rel)?,
+            max_usages: self.max_usages.unwrap_or(DEFAULT_MAX_USAGES),
+            sections: self.sections.as_ref().map(|names| names.iter().map(|n| PlanSection::parse(n)).collect()),
+        })
+    }
+
+    pub fn include_globs(&self) -> Result<GlobList> {
+        GlobList::new("include", &self.include)
+    }
+
+    pub fn exclude_globs(&self) -> Result<GlobList> {
+        GlobList::new("exclude", &self.exclude)
+    }
+}
+
+fn is_glob(key: &str) -> bool {
+    key.contains(['*', '?', '[', '{'])
+}
+
+/// `key` is a dir holding `crate_rel` or a glob matching it.
+fn override_covers(key: &str, crate_rel: &str) -> bool {
+    if is_glob(key) {
+        return globset::Glob::new(key).is_ok_and(|g| g.compile_matcher().is_match(crate_rel));
+    }
+    let dir = key.trim_end_matches('/');
+    crate_rel.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn parse_gate(s: &str) -> Result<CfgExpr> {
+    CfgExpr::parse(s).ok_or_else(|| PassengerError::Config(format!("gate: bad cfg predicate `{s}`")))
+}
+
+/// Compiled globs that remember their source patterns, so a match can be reported.
+#[derive(Debug, Clone)]
+pub struct GlobList {
+    patterns: Vec<String>,
+    set: globset::GlobSet,
+}
+
+impl GlobList {
+    pub fn new(key: &str, patterns: &[String]) -> Result<Self> {
+        let mut b = globset::GlobSetBuilder::new();
+        for p in patterns {
+            let glob = globset::Glob::new(p)
+                .map_err(|e| PassengerError::Config(format!("{key}: bad glob `{p}`: {e}")))?;
+            b.add(glob);
+        }
+        let set = b.build().map_err(|e| PassengerError::Config(format!("{key}: {e}")))?;
+        Ok(Self { patterns: patterns.to_vec(), set })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// First pattern matching `path`.
+    pub fn first_match(&self, path: &str) -> Option<&str> {
+        self.set.matches(path).first().map(|&i| self.patterns[i].as_str())
+    }
+}
+
+/// Load `{root}/code_passenger.toml` or the `code_passenger` metadata table of
+/// `{root}/Cargo.toml`; neither means defaults, both is an error.
+pub fn load_config(root: &Path) -> Result<ProjectConfig> {
+    let path = root.join(CONFIG_FILE);
+    let from_file = if path.exists() {
+        let raw = fs::read_to_string(&path)?;
+        let cfg = toml::from_str::<ProjectConfig>(&raw)
+            .map_err(|e| PassengerError::Config(format!("{}: {e}", path.display())))?;
+        Some(cfg)
+    } else {
+        None
+    };
+
+    let manifest = root.join("Cargo.toml");
+    let from_manifest = if manifest.exists() { metadata_config(&manifest)? } else { None };
+
+    match (from_file, from_manifest) {
+        (Some(_), Some((table, _))) => Err(PassengerError::Config(format!(
+            "{} and [{table}] in {} both configure code_passenger; keep one",
+            path.display(),
+            manifest.display()
+        ))),
+        (Some(cfg), None) | (None, Some((_, cfg))) => {
+            cfg.workspace.groups()?;
+            cfg.include_globs()?;
+            cfg.exclude_globs()?;
+            for (key, o) in &cfg.overrides {
+                if is_glob(key) {
+                    globset::Glob::new(key)
+                        .map_err(|e| PassengerError::Config(format!("overrides: bad glob `{key}`: {e}")))?;
+                }
+                if let Some(g) = &o.gate {
+                    parse_gate(g)?;
+                }
+            }
+            Ok(cfg)
+        }
+        (None, None) => Ok(ProjectConfig::default()),
+    }
+}
+
+/// `[package.metadata.code_passenger]` / `[workspace.metadata.code_passenger]`,
+/// with the table name it came from. Deserialized as part of the whole manifest so
+/// errors point at a line and column in Cargo.toml.
+fn metadata_config(manifest: &Path) -> Result<Option<(String, ProjectConfig)>> {
+    #[derive(Deserialize)]
+    struct Manifest {
+        package: Option<Section>,
+        workspace: Option<Section>,
+    }
+    #[derive(Deserialize)]
+    struct Section {
+        metadata: Option<Metadata>,
+    }
+    #[derive(Deserialize)]
+    struct Metadata {
+        code_passenger: Option<ProjectConfig>,
+    }
+
+    let raw = fs::read_to_string(manifest)?;
+    let doc: Manifest =
+        toml::from_str(&raw).map_err(|e| PassengerError::Config(format!("{}: {e}", manifest.display())))?;
+
+    let pick = |section: Option<Section>| section?.metadata?.code_passenger;
+    Ok(match (pick(doc.package), pick(doc.workspace)) {
+        (Some(cfg), _) => Some((format!("package.metadata.{METADATA_KEY}"), cfg)),
+        (None, Some(cfg)) => Some((format!("workspace.metadata.{METADATA_KEY}"), cfg)),
+        (None, None) => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tree;
+
+    fn ws(entries: &[(&str, &[&str])]) -> WorkspaceConfig {
+        WorkspaceConfig {
+            feature_groups: entries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.iter().map(|s| s.to_string()).collect()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn workspace_groups_fold_in_crate_selection() {
+        let groups = ws(&[
+            ("async", &["tokio"]),
+            ("async-INCLUDE_CRATES", &["a", "b"]),
+            ("async-EXCLUDE_CRATES", &["b"]),
+            ("json", &["serde_json"]),
+        ])
+        .groups()
+        .unwrap();
+
+        assert_eq!(groups.keys().collect::<Vec<_>>(), ["async", "json"]);
+        assert!(groups["async"].applies_to("a"));
+        assert!(!groups["async"].applies_to("b"));
+        assert!(!groups["async"].applies_to("c"));
+        assert!(groups["json"].applies_to("c"));
+    }
+
+    #[test]
+    fn crate_lists_need_their_group() {
+        let err = ws(&[("net-INCLUDE_CRATES", &["a"])]).groups().unwrap_err();
+        assert!(err.to_string().contains("no feature group `net`"), "{err}");
+    }
+
+    const PKG: &str = "[package]\nname = \"a\"\nversion = \"0.1.0\"\n";
+
+    #[test]
+    fn reads_the_file_or_the_metadata_table() {
+        let dir = tree(&[("Cargo.toml", PKG), (CONFIG_FILE, "plan = \"compact\"\nexclude = [\"src/gen/**\"]\n")]);
+        let cfg = load_config(dir.path()).unwrap();
+        assert_eq!(cfg.plan.as_deref(), Some("compact"));
+        assert_eq!(cfg.exclude, ["src/gen/**"]);
+
+        let dir = tree(&[("Cargo.toml", &format!("{PKG}\n[package.metadata.code_passenger]\ntop_n = 3\n"))]);
+        assert_eq!(load_config(dir.path()).unwrap().top_n(), 3);
+
+        let dir = tree(&[("Cargo.toml", PKG)]);
+        assert_eq!(load_config(dir.path()).unwrap().top_n(), DEFAULT_TOP_N);
+    }
+
+    #[test]
+    fn both_sources_at_once_is_an_error() {
+        let dir = tree(&[
+            ("Cargo.toml", &format!("{PKG}\n[package.metadata.code_passenger]\ntop_n = 3\n")),
+            (CONFIG_FILE, "top_n = 4\n"),
+        ]);
+        assert!(load_config(dir.path()).unwrap_err().to_string().contains("keep one"));
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_with_their_position() {
+        let dir = tree(&[(CONFIG_FILE, "plan = \"verbose\"\ntop-n = 3\n")]);
+        let err = load_config(dir.path()).unwrap_err().to_string();
+        assert!(err.contains("line 2, column 1") && err.contains("unknown field `top-n`"), "{err}");
+
+        let dir = tree(&[("Cargo.toml", &format!("{PKG}\n[package.metadata.code_passenger]\nplna = \"x\"\n"))]);
+        let err = load_config(dir.path()).unwrap_err().to_string();
+        assert!(err.contains("line 6, column 1") && err.contains("unknown field `plna`"), "{err}");
+    }
+
+    #[test]
+    fn bad_globs_and_gates_fail_at_load() {
+        let dir = tree(&[(CONFIG_FILE, "exclude = [\"src/[\"]\n")]);
+        assert!(load_config(dir.path()).unwrap_err().to_string().contains("exclude: bad glob"));
+
+        let dir = tree(&[(CONFIG_FILE, "[overrides.\"src/net\"]\ngate = \"any(\"\n")]);
+        assert!(load_config(dir.path()).unwrap_err().to_string().contains("bad cfg predicate"));
+    }
+
+    #[test]
+    fn cli_flags_win_and_lists_add_up() {
+        let mut cfg = ProjectConfig {
+            plan: Some("compact".into()),
+            src: Some("lib".into()),
+            exclude: vec!["a/**".into()],
+            ..Default::default()
+        };
+        cfg.merge_cli(&CliOverrides {
+            plan: Some("verbose".into()),
+            exclude: vec!["b/**".into()],
+            gitignore: true,
+            ..Default::default()
+        });
+        assert_eq!(cfg.plan.as_deref(), Some("verbose"));
+        assert_eq!(cfg.src(), "lib");
+        assert_eq!(cfg.lang(), DEFAULT_LANG);
+        assert_eq!(cfg.exclude, ["a/**", "b/**"]);
+        assert!(cfg.respect_gitignore);
+    }
+
+    #[test]
+    fn longest_override_wins() {
+        let cfg: ProjectConfig = toml::from_str(
+            "plan = \"compact\"\ncorpus_whitelist = [\"std\"]\n\
+             [overrides.\"src\"]\nplan = \"a\"\n\
+             [overrides.\"src/net\"]\nplan = \"b\"\ncorpus_whitelist = []\n\
+             [overrides.\"src/bin/*.rs\"]\nplan = \"c\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(cfg.plan_for("src/net/http.rs"), ("b", PlanSource::Override("src/net".into())));
+        assert_eq!(cfg.plan_for("src/lib.rs").0, "a");
+        assert_eq!(cfg.plan_for("src/bin/tool.rs").0, "c");
+        assert_eq!(cfg.plan_for("tests/it.rs"), ("compact", PlanSource::Config));
+        // `src/network` isn't under `src/net`
+        assert_eq!(cfg.plan_for("src/network.rs").0, "a");
+        assert!(cfg.corpus_whitelist_for("src/net/x.rs").is_empty());
+        assert_eq!(cfg.corpus_whitelist_for("src/lib.rs"), ["std".to_string()].into());
+    }
+
+    #[test]
+    fn annotate_targets_default_to_lib_and_bin_and_never_build() {
+        let cfg = ProjectConfig::default();
+        assert!(cfg.annotates(TargetKind::Lib) && cfg.annotates(TargetKind::Bin));
+        assert!(!cfg.annotates(TargetKind::Test) && !cfg.annotates(TargetKind::Build));
+
+        let cfg: ProjectConfig = toml::from_str("annotate_targets = [\"lib\", \"test\", \"build\"]").unwrap();
+        assert!(cfg.annotates(TargetKind::Test) && !cfg.annotates(TargetKind::Bin));
+        assert!(!cfg.annotates(TargetKind::Build));
+    }
+}
@@ -1,8 +1,9 @@
 use crate::{
-    cargo::load_manifest,
+    cargo::{find_workspace_root, load_manifest, sync_feature_groups, workspace_members},
+    config::{load_config, CliOverrides, ProjectConfig},
     error::{PassengerError, Result},
-    model::{DocumentDetails, FeatureNote, FileReport, ManifestInfo, UsedSymbols},
-    packs,
+    model::{CfgExpr, DocumentDetails, FeatureNote, FileReport, FileScan, ManifestInfo, MissingModule, ModuleFile, ReExports, ScanContext, ScanMode, SkipReason, SkippedFile, TargetInfo, TargetKind, UngatedUse, Usage, UseSite, UsedSymbols},
+    packs::{self, normalize_path},
 };
 use std::{
     collections::{BTreeMap, BTreeSet},
@@ -10,7 +11,6 @@ use std::{
     path::{Path, PathBuf},
 };
 use serde::{Deserialize, Serialize};
-use walkdir::WalkDir;
 
 fn rel_to_src(src_root: &Path, p: &Path) -> Result<String> {
     let rel = p.strip_prefix(src_root)
@@ -18,6 +18,24 @@ fn rel_to_src(src_root: &Path, p: &Path) -> Result<String> {
     Ok(rel.to_string_lossy().to_string())
 }
 
+/// Module path implied by a file's location under the src root:
+/// `lib.rs` -> `crate`, `net/mod.rs` -> `crate::net`, `net/http.rs` -> `crate::net::http`.
+fn module_path_from_rel(rel: &str) -> String {
+    let rel = rel.replace('\\', "/");
+    let stem = rel.strip_suffix(".rs").unwrap_or(&rel);
+    let mut parts: Vec<&str> = stem.split('/').filter(|s| !s.is_empty()).collect();
+    if matches!(parts.last(), Some(&"mod")) || (parts.len() == 1 && matches!(parts[0], "lib" | "main")) {
+        parts.pop();
+    }
+
+    let mut out = "crate".to_string();
+    for p in parts {
+        out.push_str("::");
+        out.push_str(p);
+    }
+    out
+}
+
 // very pragmatic “scope notes”
 fn scope_label_from_header(header: &str) -> Option<String> {
     let h = header.trim();
@@ -97,10 +115,8 @@ fn compute_dep_scopes(content: &str, used_deps: &BTreeSet<String>) -> BTreeMap<S
         depth += opens;
         for _ in 0..closes {
             if depth == 0 { break; }
-            if let Some(top) = stack.last() {
-                if top.1 == depth {
-                    stack.pop();
-                }
+            if stack.last().is_some_and(|top| top.1 == depth) {
+                stack.pop();
             }
             depth -= 1;
         }
@@ -109,16 +125,238 @@ fn compute_dep_scopes(content: &str, used_deps: &BTreeSet<String>) -> BTreeMap<S
     dep_scopes
 }
 
+/// A source file read ahead of scanning.
+struct Source {
+    path: PathBuf,
+    /// relative to its scan root
+    rel: String,
+    /// relative to the crate root, `/`-separated; what config globs/overrides match
+    crate_rel: String,
+    scan_root: Option<String>,
+    target: TargetKind,
+    target_name: String,
+    /// where a module tree places the file
+    module: Option<ModuleFile>,
+    content: String,
+}
+
+/// Files under `entry` (or `entry` itself), sorted by name. With `gitignore`,
+/// `.gitignore`/`.ignore` rules (including parent dirs' and the global git
+/// excludes) prune the walk, also outside git repos; the files and dirs they
+/// hid come back second so they can be reported.
+fn walk(entry: &Path, gitignore: bool) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let walker = ignore::WalkBuilder::new(entry)
+        .standard_filters(false)
+        .git_ignore(gitignore)
+        .git_global(gitignore)
+        .git_exclude(gitignore)
+        .ignore(gitignore)
+        .parents(gitignore)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let (mut files, mut dirs) = (vec![], vec![]);
+    let mut seen = BTreeSet::<PathBuf>::new();
+    for ent in walker.filter_map(|e| e.ok()) {
+        match ent.file_type() {
+            Some(t) if t.is_dir() => dirs.push(ent.path().to_path_buf()),
+            Some(t) if t.is_file() => files.push(ent.path().to_path_buf()),
+            _ => {}
+        }
+        seen.insert(ent.into_path());
+    }
+
+    let mut hidden = vec![];
+    if gitignore {
+        for dir in dirs {
+            let Ok(rd) = fs::read_dir(&dir) else { continue };
+            let mut kids: Vec<PathBuf> = rd.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| !seen.contains(p)).collect();
+            kids.sort();
+            hidden.extend(kids);
+        }
+    }
+    (files, hidden)
+}
+
+/// Files compiled into one target (or module dirs shared by a kind of target).
+struct ScanUnit {
+    kind: TargetKind,
+    name: String,
+    /// files or dirs to walk
+    entries: Vec<PathBuf>,
+}
+
+/// Manifest targets first, then catch-all dirs for their modules (`src/bin`,
+/// `src`, `tests`, ...), then configured `extra_roots`. Each file goes to the
+/// first unit that reaches it.
+fn scan_units(ctx: &RunContext, manifest: &ManifestInfo) -> Vec<ScanUnit> {
+    let src_root = ctx.root.join(&ctx.src_rel);
+    let mut units = vec![];
+
+    for t in manifest.targets.iter().filter(|t| t.kind != TargetKind::Lib) {
+        let entry = ctx.root.join(&t.path);
+        // `tests/it/main.rs` owns `tests/it/`; `src/main.rs` doesn't own `src/`
+        let dir = entry
+            .parent()
+            .filter(|d| entry.file_name().is_some_and(|f| f == "main.rs") && *d != src_root && *d != ctx.root);
+        units.push(ScanUnit {
+            kind: t.kind,
+            name: t.name.clone(),
+            entries: vec![dir.map(Path::to_path_buf).unwrap_or(entry)],
+        });
+    }
+
+    // `src/` modules belong to the lib; in a bin-only crate to the bin at
+    // `src/main.rs` (or the first one)
+    let lib = manifest.targets.iter().find(|t| t.kind == TargetKind::Lib);
+    let bins = || manifest.targets.iter().filter(|t| t.kind == TargetKind::Bin);
+    let (src_kind, src_name) = match lib {
+        Some(t) => (TargetKind::Lib, t.name.clone()),
+        None => match bins().find(|t| ctx.root.join(&t.path) == src_root.join("main.rs")).or_else(|| bins().next()) {
+            Some(t) => (TargetKind::Bin, t.name.clone()),
+            None => (TargetKind::Lib, manifest.crate_name.clone()),
+        },
+    };
+    let catch_all = [
+        (TargetKind::Bin, String::new(), src_root.join("bin")),
+        (src_kind, src_name, src_root.clone()),
+        (TargetKind::Test, String::new(), ctx.root.join("tests")),
+        (TargetKind::Example, String::new(), ctx.root.join("examples")),
+        (TargetKind::Bench, String::new(), ctx.root.join("benches")),
+    ];
+    for (kind, name, dir) in catch_all {
+        units.push(ScanUnit { kind, name, entries: vec![dir] });
+    }
+
+    for r in &ctx.config.extra_roots {
+        units.push(ScanUnit { kind: TargetKind::Other, name: String::new(), entries: vec![ctx.root.join(r)] });
+    }
+
+    units
+}
+
+#[derive(Clone)]
 pub struct RunContext {
     pub root: PathBuf,
     pub src_rel: String,
     pub manifest_path: PathBuf,
     pub lang: String,
+    /// config with `cli` already merged in
+    pub config: ProjectConfig,
+    pub cli: CliOverrides,
 }
 #[derive(Serialize, Deserialize)]
 pub struct EngineOutput {
     pub manifest: ManifestInfo,
+    #[serde(default)]
+    pub reexports: ReExports,
     pub reports: Vec<FileReport>,
+    /// files walked past by include/exclude globs, ignore files or the opt-out marker
+    #[serde(default)]
+    pub skipped: Vec<SkippedFile>,
+    /// scanned files no target's module tree declares (crate-relative)
+    #[serde(default)]
+    pub orphans: Vec<String>,
+    /// `mod foo;` items with no file behind them
+    #[serde(default)]
+    pub missing_modules: Vec<MissingModule>,
+}
+
+/// One member crate's scan in a workspace run.
+#[derive(Serialize, Deserialize)]
+pub struct WorkspaceMember {
+    pub root: PathBuf,
+    pub manifest_path: PathBuf,
+    /// feature groups that apply to this crate (its own plus the workspace's)
+    pub feature_groups: BTreeMap<String, Vec<String>>,
+    /// those of them not in the crate's Cargo.toml yet; the scan doesn't see
+    /// them until `features sync --write` puts them there
+    pub unsynced_groups: Vec<String>,
+    pub output: EngineOutput,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WorkspaceOutput {
+    pub root_manifest: PathBuf,
+    /// keyed by crate name
+    pub crates: BTreeMap<String, WorkspaceMember>,
+}
+
+/// Per-member run contexts for the workspace `ctx.manifest_path` belongs to.
+/// Each member keeps its own `code_passenger.toml`; workspace feature groups
+/// (from the workspace root config) are added where the crate is selected and
+/// trimmed to the deps the crate actually has. Scans read features from the
+/// members' manifests, so groups count once `features sync` wrote them there;
+/// see [`unsynced_groups`].
+pub fn workspace_contexts(ctx: &RunContext) -> Result<Vec<RunContext>> {
+    let root_manifest = find_workspace_root(&ctx.manifest_path)?.ok_or_else(|| {
+        PassengerError::Config(format!("{} is not part of a workspace", ctx.manifest_path.display()))
+    })?;
+    let ws_dir = root_manifest.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf();
+    let ws_config = if ws_dir == ctx.root { ctx.config.clone() } else { load_config(&ws_dir)? };
+    let ws_groups = ws_config.workspace.groups()?;
+
+    let mut out = vec![];
+    for dir in workspace_members(&root_manifest)? {
+        let manifest_path = dir.join("Cargo.toml");
+        let manifest = load_manifest(manifest_path.to_str().ok_or_else(|| PassengerError::Path("bad manifest path".into()))?)?;
+        let has_dep = |d: &str| manifest.deps.keys().any(|k| k.replace('-', "_") == d.replace('-', "_"));
+
+        let mut config = if dir == ws_dir { ws_config.clone() } else { load_config(&dir)? };
+        config.merge_cli(&ctx.cli);
+        for (name, group) in &ws_groups {
+            if !group.applies_to(&manifest.crate_name) || config.feature_groups.contains_key(name) {
+                continue;
+            }
+            let deps: Vec<String> = group.deps.iter().filter(|d| has_dep(d)).cloned().collect();
+            if !deps.is_empty() {
+                config.feature_groups.insert(name.clone(), deps);
+            }
+        }
+
+        // a member's own `src`/`lang` settings win over the invoking crate's
+        out.push(RunContext {
+            root: dir,
+            src_rel: config.src.clone().unwrap_or_else(|| ctx.src_rel.clone()),
+            manifest_path,
+            lang: config.lang.clone().unwrap_or_else(|| ctx.lang.clone()),
+            config,
+            cli: ctx.cli.clone(),
+        });
+    }
+    Ok(out)
+}
+
+pub fn run_workspace_scan(ctx: &RunContext) -> Result<WorkspaceOutput> {
+    let root_manifest = find_workspace_root(&ctx.manifest_path)?.ok_or_else(|| {
+        PassengerError::Config(format!("{} is not part of a workspace", ctx.manifest_path.display()))
+    })?;
+
+    let mut crates = BTreeMap::new();
+    for member in workspace_contexts(ctx)? {
+        let output = run_scan(&member)?;
+        let unsynced_groups = unsynced_groups(&member)?;
+        crates.insert(output.manifest.crate_name.clone(), WorkspaceMember {
+            root: member.root,
+            manifest_path: member.manifest_path,
+            feature_groups: member.config.feature_groups,
+            unsynced_groups,
+            output,
+        });
+    }
+    Ok(WorkspaceOutput { root_manifest, crates })
+}
+
+/// Feature groups of `ctx` that `features sync --write` would still change the
+/// crate's Cargo.toml for (a group sync rejects counts too).
+pub fn unsynced_groups(ctx: &RunContext) -> Result<Vec<String>> {
+    let raw = fs::read_to_string(&ctx.manifest_path)?;
+    let synced = |name: &String, deps: &Vec<String>| {
+        let one = BTreeMap::from([(name.clone(), deps.clone())]);
+        sync_feature_groups(&raw, &one).is_ok_and(|s| s == raw)
+    };
+    Ok(ctx.config.feature_groups.iter().filter(|(n, d)| !synced(n, d)).map(|(n, _)| n.clone()).collect())
 }
 
 pub fn run_scan(ctx: &RunContext) -> Result<EngineOutput> {
@@ -130,36 +368,140 @@ pub fn run_scan(ctx: &RunContext) -> Result<EngineOutput> {
     // currently only Rust pack uses optional_deps-aware detection
     let pack = packs::get_pack(&ctx.lang)?;
 
-    for ent in WalkDir::new(&src_root).into_iter().filter_map(|e| e.ok()) {
-        if !ent.file_type().is_file() { continue; }
-        let path = ent.path();
-        if !pack.matches_path(path) { continue; }
+    // read every source first: re-exports are crate-wide, so all of them have to
+    // be known before any single file's paths can be resolved
+    let include = ctx.config.include_globs()?;
+    let exclude = ctx.config.exclude_globs()?;
+    let mut claimed = BTreeSet::<PathBuf>::new();
+    let mut sources: Vec<Source> = vec![];
+    let mut skipped: Vec<SkippedFile> = vec![];
+    for unit in scan_units(ctx, &manifest) {
+        for entry in &unit.entries {
+            let (files, hidden) = walk(entry, ctx.config.respect_gitignore);
+            // ignored dirs are reported once, not walked
+            for path in hidden {
+                let is_dir = path.is_dir();
+                if !(is_dir || pack.matches_path(&path)) || !claimed.insert(path.clone()) { continue; }
+                let crate_rel = rel_to_src(&ctx.root, &path)?.replace('\\', "/");
+                let path = if is_dir { format!("{crate_rel}/") } else { crate_rel };
+                skipped.push(SkippedFile { path, reason: SkipReason::Ignored });
+            }
 
-        let rel = rel_to_src(&src_root, path)?;
-        let filename = path.file_name().unwrap().to_string_lossy().to_string();
+            for path in &files {
+                if !pack.matches_path(path) { continue; }
+                // targets first, then the dirs that hold their modules
+                if !claimed.insert(path.to_path_buf()) { continue; }
+
+                let crate_rel = rel_to_src(&ctx.root, path)?.replace('\\', "/");
+                let reason = if let Some(pattern) = exclude.first_match(&crate_rel) {
+                    Some(SkipReason::Excluded { pattern: pattern.to_string() })
+                } else if !include.is_empty() && include.first_match(&crate_rel).is_none() {
+                    Some(SkipReason::NotIncluded)
+                } else {
+                    None
+                };
+                if let Some(reason) = reason {
+                    skipped.push(SkippedFile { path: crate_rel, reason });
+                    continue;
+                }
 
-        let content = fs::read_to_string(path)?;
-
-        let used: UsedSymbols = if ctx.lang == "rust" || ctx.lang == "rs" {
-            // usage depends on optional_deps list
-            #[cfg(feature="lang_rust")]
-            {
-                crate::packs::rust::detectors::detect_usage_with_deps(
-                    &content,
-                    &manifest.all_deps,
-                    &manifest.crate_name
-                )
-                // crate::packs::rust::detect_usage_with_optiona l_deps(&content, &manifest.optional_deps)
-            }
-            #[cfg(not(feature="lang_rust"))]
-            {
-                UsedSymbols::default()
+                let content = fs::read_to_string(path)?;
+                if pack.opted_out(&content) {
+                    skipped.push(SkippedFile { path: crate_rel, reason: SkipReason::OptOut });
+                    continue;
+                }
+
+                let (scan_root, rel) = match path.strip_prefix(&src_root) {
+                    Ok(rel) => (None, rel.to_string_lossy().to_string()),
+                    Err(_) => match crate_rel.split_once('/') {
+                        Some((top, rest)) => (Some(top.to_string()), rest.to_string()),
+                        None => (Some(".".to_string()), crate_rel.clone()),
+                    },
+                };
+
+                sources.push(Source {
+                    path: path.to_path_buf(),
+                    rel,
+                    crate_rel,
+                    scan_root,
+                    target: unit.kind,
+                    target_name: unit.name.clone(),
+                    module: None,
+                    content,
+                });
             }
-        } else {
-            pack.detect_usage(&content)
+        }
+    }
+
+    // module trees from every target's crate root; the first target to reach a
+    // file names it (the lib comes first)
+    let mut modules = BTreeMap::<PathBuf, ModuleFile>::new();
+    let mut missing_modules = vec![];
+    let mut have_trees = false;
+    let mut reached_by = BTreeMap::<PathBuf, &TargetInfo>::new();
+    for t in &manifest.targets {
+        let Some(tree) = pack.module_tree(&ctx.root.join(&t.path)) else { continue };
+        have_trees = true;
+        for (p, m) in tree.files {
+            reached_by.entry(p.clone()).or_insert(t);
+            modules.entry(p).or_insert(m);
+        }
+        missing_modules.extend(tree.missing);
+    }
+
+    // without a lib, `src/` modules go to the bin whose tree reaches them
+    let bin_only = !manifest.targets.iter().any(|t| t.kind == TargetKind::Lib);
+    let mut orphans = vec![];
+    for src in &mut sources {
+        let key = normalize_path(&src.path);
+        if let Some(t) = reached_by.get(&key).filter(|t| bin_only && t.kind == TargetKind::Bin) {
+            src.target = t.kind;
+            src.target_name = t.name.clone();
+        }
+        src.module = modules.get(&key).cloned();
+        if have_trees && src.module.is_none() {
+            orphans.push(src.crate_rel.clone());
+        }
+    }
+
+    let attr_crates = ctx.config.attr_crates();
+
+    let mut reexports = ReExports::new();
+    // other targets' `pub use`s aren't the library's
+    for src in sources.iter().filter(|s| s.target == TargetKind::Lib) {
+        let module_path = src.module.as_ref().map(|m| m.module_path.clone()).unwrap_or_else(|| module_path_from_rel(&src.rel));
+        reexports.extend(pack.collect_reexports(&src.content, &manifest, &module_path));
+    }
+
+    for Source { path, rel, crate_rel, scan_root, target, target_name, module, content } in sources {
+        let filename = path.file_name().unwrap().to_string_lossy().to_string();
+        let (module_path, module_cfg, module_declared_in) = match module {
+            Some(m) => (m.module_path, m.cfg, m.declared_in),
+            None => (module_path_from_rel(&rel), vec![], None),
+        };
+        let scan_ctx = ScanContext {
+            module_path: &module_path,
+            reexports: &reexports,
+            attr_crates: &attr_crates,
         };
 
-        let external_use_sites = scan_dep_use_sites(&content, &used.packages);
+        let scan = match pack.scan_syntax(&content, &manifest, scan_ctx) {
+            Some(scan) => scan,
+            None => regex_scan(ctx, pack.as_ref(), &manifest, &content),
+        };
+        let FileScan {
+            mode: scan_mode,
+            used,
+            external_use_sites,
+            internal_use_sites,
+            dep_scopes,
+            scope_cfgs,
+            file_cfg,
+            header_cfg,
+        } = scan;
+
+        let outer: Vec<CfgExpr> = module_cfg.iter().chain(&file_cfg).chain(&header_cfg).cloned().collect();
+        let ungated_uses = ungated_uses(&manifest, &outer, &external_use_sites);
 
         let mut external_dep_symbol_counts: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
         for u in &external_use_sites {
@@ -169,8 +511,6 @@ pub fn run_scan(ctx: &RunContext) -> Result<EngineOutput> {
                 .or_insert(1);
         }
 
-        let internal_use_sites = scan_internal_use_sites(&content, &manifest.crate_name);
-
         let mut internal_dep_symbol_counts: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
         for u in &internal_use_sites {
             internal_dep_symbol_counts
@@ -179,15 +519,12 @@ pub fn run_scan(ctx: &RunContext) -> Result<EngineOutput> {
                 .or_insert(1);
         }
 
-        let dep_scopes = compute_dep_scopes(&content, &used.packages);
-
         // feature notes + corpus selection
         let mut notes: Vec<FeatureNote> = vec![];
         let mut corpus_features: BTreeSet<String> = BTreeSet::new();
 
         // corpus heuristic: features that actually gate optional deps OR known “platform features”
-        let corpus_name_whitelist: BTreeSet<&'static str> =
-            ["std", "alloc", "no_std"].into_iter().collect();
+        let corpus_name_whitelist = ctx.config.corpus_whitelist_for(&crate_rel);
 
         for (feat, deps) in &manifest.feature_deps {
             let deps_vec: Vec<String> = deps.iter().cloned().collect();
@@ -201,23 +538,49 @@ pub fn run_scan(ctx: &RunContext) -> Result<EngineOutput> {
                 }
             }
 
-            let corpus = !deps.is_empty() || corpus_name_whitelist.contains(feat.as_str());
+            let corpus = !deps.is_empty() || corpus_name_whitelist.contains(feat);
 
             if corpus && used_in_file {
                 corpus_features.insert(feat.clone());
             }
 
+            let packages = deps_vec.iter().map(|d| manifest.package_of(d).to_string()).collect();
+
             notes.push(FeatureNote {
                 feature_name: feat.clone(),
                 deps: deps_vec,
+                packages,
                 scope: scopes.into_iter().collect(),
                 corpus,
                 used_in_file,
             });
         }
 
+        if ctx.config.prune_implied_features {
+            prune_implied_features(&mut corpus_features, &manifest, &used.packages);
+        }
+
+        let mut platform_cfgs: Vec<CfgExpr> = vec![];
+        for c in used.packages.iter().filter_map(|d| manifest.deps.get(d)?.platform_cfg()) {
+            if !platform_cfgs.contains(&c) {
+                platform_cfgs.push(c);
+            }
+        }
+
         reports.push(FileReport {
-            document: DocumentDetails { filename, relative_path: rel },
+            document: DocumentDetails { filename, relative_path: rel, scan_root },
+            target,
+            target_name,
+            module_path,
+            module_cfg,
+            module_declared_in,
+            file_cfg,
+            header_cfg,
+            platform_cfgs,
+            scope_cfgs,
+            ungated_uses,
+            usages: vec![],
+            scan_mode,
             used,
             notes,
             corpus_features: corpus_features.into_iter().collect(),
@@ -228,7 +591,170 @@ pub fn run_scan(ctx: &RunContext) -> Result<EngineOutput> {
         });
     }
 
-    Ok(EngineOutput { manifest, reports })
+    link_usages(&mut reports, &manifest.crate_name);
+
+    Ok(EngineOutput { manifest, reexports, reports, skipped, orphans, missing_modules })
+}
+
+/// Reverse index of internal use sites: each `crate::a::b::Item` reference lands
+/// on the file whose module path is its longest prefix, within the same target.
+/// Other targets' `<crate_name>::...` paths land in the library.
+fn link_usages(reports: &mut [FileReport], crate_name: &str) {
+    let mut usages = vec![BTreeSet::<Usage>::new(); reports.len()];
+    let crate_ident = crate_name.replace('-', "_");
+
+    for (from, r) in reports.iter().enumerate() {
+        for u in &r.internal_use_sites {
+            if !u.resolved.starts_with("crate::") {
+                continue;
+            }
+            // `dep` keeps the root as written
+            let resolved = &u.resolved;
+            let same_target = r.target == TargetKind::Lib || u.dep != crate_ident;
+            let in_scope = |t: &FileReport| match same_target {
+                true => t.target == r.target && t.target_name == r.target_name,
+                false => t.target == TargetKind::Lib,
+            };
+            let under = |module: &str| resolved.strip_prefix(module).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"));
+
+            let target = reports
+                .iter()
+                .enumerate()
+                .filter(|(i, t)| *i != from && in_scope(t) && under(&t.module_path))
+                .max_by_key(|(_, t)| t.module_path.len());
+            let Some((to, t)) = target else { continue };
+
+            // a longer match in the referring file itself means it's local
+            if same_target && under(&r.module_path) && r.module_path.len() > t.module_path.len() {
+                continue;
+            }
+            usages[to].insert(Usage { file: r.document.display_path(), scope: u.scope.clone() });
+        }
+    }
+
+    for (r, u) in reports.iter_mut().zip(usages) {
+        r.usages = u.into_iter().collect();
+    }
+}
+
+/// Uses of optional deps that still compile with every feature enabling the dep
+/// turned off. `outer` gates the whole file (module decl, `#![cfg]`s); a use is
+/// guarded only when its combined gate is known false once the dep's features are
+/// off (features Cargo.toml doesn't declare are never on either). Anything else
+/// (`unix`, unrelated features) is left open.
+fn ungated_uses(manifest: &ManifestInfo, outer: &[CfgExpr], sites: &[UseSite]) -> Vec<UngatedUse> {
+    let declared = |f: &str| manifest.features_raw.contains_key(f) || manifest.feature_graph.implicit.contains(f);
+    let mut out = vec![];
+    for u in sites {
+        let Some(features) = manifest.dep_features.get(&u.dep).filter(|f| !f.is_empty()) else { continue };
+
+        let gate = CfgExpr::all_of(outer.iter().chain(&u.cfg).cloned().collect());
+        let off = |leaf: &CfgExpr| match leaf {
+            CfgExpr::KeyValue { key, value } if key == "feature" && (features.contains(value) || !declared(value)) => {
+                Some(false)
+            }
+            _ => None,
+        };
+        if gate.as_ref().and_then(|g| g.eval(&off)) == Some(false) {
+            continue;
+        }
+
+        out.push(UngatedUse {
+            dep: u.dep.clone(),
+            line: u.line,
+            scope: u.scope.clone(),
+            features: features.iter().cloned().collect(),
+            gate,
+            runtime_cfg: u.runtime_cfg.clone(),
+        });
+    }
+    out
+}
+
+/// Features imply each other (`full = ["async", "tls"]`), so the raw "used in file"
+/// set over-gates. Two passes:
+/// 1. drop umbrella features whose used deps are all covered by selected features
+///    they turn on (`full` when `async` already covers the file's tokio use);
+/// 2. drop features another selected feature turns on anyway (gates are an AND,
+///    so `json` + `serde_json` is just `json`).
+///
+/// Only runs with `prune_implied_features` set: it changes corpus gates.
+fn prune_implied_features(corpus: &mut BTreeSet<String>, manifest: &ManifestInfo, used: &BTreeSet<String>) {
+    let graph = &manifest.feature_graph;
+    // `a` turns `b` on (and not the other way round, so cycles never prune both)
+    let implies = |a: &str, b: &str| {
+        a != b
+            && graph.closure.get(a).is_some_and(|c| c.contains(b))
+            && !graph.closure.get(b).is_some_and(|c| c.contains(a))
+    };
+    let used_deps = |f: &str| -> BTreeSet<String> {
+        manifest.feature_deps.get(f).map(|d| d.intersection(used).cloned().collect()).unwrap_or_default()
+    };
+
+    let umbrellas: Vec<String> = corpus
+        .iter()
+        .filter(|f| {
+            let mut covered = BTreeSet::new();
+            for g in corpus.iter().filter(|g| implies(f, g)) {
+                covered.extend(used_deps(g));
+            }
+            !covered.is_empty() && used_deps(f).is_subset(&covered)
+        })
+        .cloned()
+        .collect();
+    for f in umbrellas {
+        corpus.remove(&f);
+    }
+
+    let redundant: Vec<String> = corpus
+        .iter()
+        .filter(|g| corpus.iter().any(|f| implies(f, g)))
+        .cloned()
+        .collect();
+    for g in redundant {
+        corpus.remove(&g);
+    }
+}
+
+/// Line/regex scanning, used when the pack has no parser or the file doesn't parse.
+fn regex_scan(ctx: &RunContext, pack: &dyn packs::LanguagePack, manifest: &ManifestInfo, content: &str) -> FileScan {
+    let used: UsedSymbols = if ctx.lang == "rust" || ctx.lang == "rs" {
+        // usage depends on optional_deps list
+        #[cfg(feature="lang_rust")]
+        {
+            crate::packs::rust::detectors::detect_usage_with_deps(
+                content,
+                &manifest.all_deps,
+                &manifest.crate_name
+            )
+        }
+        #[cfg(not(feature="lang_rust"))]
+        {
+            UsedSymbols::default()
+        }
+    } else {
+        pack.detect_usage(content)
+    };
+
+    #[cfg(feature="lang_rust")]
+    let aliases = crate::packs::rust::detectors::detect_aliases(content, &manifest.all_deps);
+    #[cfg(not(feature="lang_rust"))]
+    let aliases = BTreeMap::new();
+
+    let external_use_sites = scan_dep_use_sites(content, &used.packages, &aliases);
+    let internal_use_sites = scan_internal_use_sites(content, &manifest.crate_name);
+    let dep_scopes = compute_dep_scopes(content, &used.packages);
+
+    FileScan {
+        mode: ScanMode::Regex,
+        used,
+        external_use_sites,
+        internal_use_sites,
+        dep_scopes,
+        scope_cfgs: BTreeMap::new(),
+        file_cfg: vec![],
+        header_cfg: vec![],
+    }
 }
 
 
@@ -245,6 +771,7 @@ use regex::Regex;
 fn scan_dep_use_sites(
     content: &str,
     used_deps: &std::collections::BTreeSet<String>,
+    aliases: &BTreeMap<String, String>,
 ) -> Vec<crate::model::UseSite> {
     use crate::model::{UseKind, UseSite};
 
@@ -300,18 +827,26 @@ fn scan_dep_use_sites(
         };
 
         for caps in re.captures_iter(line) {
-            let dep = caps.name("dep").unwrap().as_str().to_string();
-            if !used_deps.contains(&dep) { continue; }
+            let written = caps.name("dep").unwrap().as_str().to_string();
 
             let tail_raw = caps.name("tail").unwrap().as_str();
             let bang = caps.name("bang").is_some();
 
             // normalize tail spacing: "a :: b" -> "a::b"
-            let tail = tail_raw.split_whitespace().collect::<String>().replace("::", "::");
+            let tail = tail_raw.split_whitespace().collect::<String>();
+            let path = format!("{written}::{tail}");
+
+            // follow `use dep::x as alias;`
+            let resolved = match aliases.get(&written) {
+                Some(target) => format!("{target}::{tail}"),
+                None => path.clone(),
+            };
+            let mut segs = resolved.split("::");
+            let dep = segs.next().unwrap_or("").to_string();
+            if !used_deps.contains(&dep) { continue; }
 
             // head = first segment after dep::
-            let head = tail.split("::").next().unwrap_or("").to_string();
-            let path = format!("{dep}::{tail}");
+            let head = segs.next().unwrap_or("").to_string();
 
             let kind = if let Some(k) = kind_hint.clone() {
                 // if line is `use ...` etc, keep that (most useful)
@@ -325,10 +860,14 @@ fn scan_dep_use_sites(
             out.push(UseSite {
                 dep,
                 path,
+                resolved,
                 head,
                 kind,
                 line: line_no,
                 scope: scope.clone(),
+                test_only: false,
+                cfg: None,
+                runtime_cfg: None,
             });
         }
 
@@ -337,10 +876,8 @@ fn scan_dep_use_sites(
         depth += opens;
         for _ in 0..closes {
             if depth == 0 { break; }
-            if let Some(top) = stack.last() {
-                if top.1 == depth {
-                    stack.pop();
-                }
+            if stack.last().is_some_and(|top| top.1 == depth) {
+                stack.pop();
             }
             depth -= 1;
         }
@@ -438,11 +975,15 @@ fn scan_internal_use_sites(content: &str, crate_name: &str) -> Vec<crate::model:
 
             out.push(UseSite {
                 dep,
+                resolved: path.clone(),
                 path,
                 head,
                 kind,
                 line: line_no,
                 scope: scope.clone(),
+                test_only: false,
+                cfg: None,
+                runtime_cfg: None,
             });
         }
 
@@ -450,10 +991,8 @@ fn scan_internal_use_sites(content: &str, crate_name: &str) -> Vec<crate::model:
         depth += opens;
         for _ in 0..closes {
             if depth == 0 { break; }
-            if let Some(top) = stack.last() {
-                if top.1 == depth {
-                    stack.pop();
-                }
+            if stack.last().is_some_and(|top| top.1 == depth) {
+                stack.pop();
             }
             depth -= 1;
         }
@@ -461,3 +1000,237 @@ fn scan_internal_use_sites(content: &str, crate_name: &str) -> Vec<crate::model:
 
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tree;
+
+    fn ctx(dir: &tempfile::TempDir, config: ProjectConfig) -> RunContext {
+        RunContext {
+            root: dir.path().to_path_buf(),
+            src_rel: "src".to_string(),
+            manifest_path: dir.path().join("Cargo.toml"),
+            lang: "rust".to_string(),
+            config,
+            cli: CliOverrides::default(),
+        }
+    }
+
+    fn corpus(out: &EngineOutput, rel: &str) -> Vec<String> {
+        out.reports.iter().find(|r| r.document.relative_path == rel).unwrap().corpus_features.clone()
+    }
+
+    const UMBRELLA: &str = "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n\
+        [dependencies]\nserde_json = { version = \"1\", optional = true }\n\n\
+        [features]\njson = [\"dep:serde_json\"]\nfull = [\"json\"]\n";
+
+    #[test]
+    fn implied_features_are_kept_by_default() {
+        let dir = tree(&[("Cargo.toml", UMBRELLA), ("src/lib.rs", "pub fn f() { serde_json::json!(1); }\n")]);
+        let out = run_scan(&ctx(&dir, ProjectConfig::default())).unwrap();
+        assert_eq!(corpus(&out, "lib.rs"), ["full", "json"]);
+    }
+
+    #[test]
+    fn pruning_drops_umbrella_features() {
+        let dir = tree(&[("Cargo.toml", UMBRELLA), ("src/lib.rs", "pub fn f() { serde_json::json!(1); }\n")]);
+        let config = ProjectConfig { prune_implied_features: true, ..Default::default() };
+        let out = run_scan(&ctx(&dir, config)).unwrap();
+        assert_eq!(corpus(&out, "lib.rs"), ["json"]);
+    }
+
+    #[test]
+    fn renamed_deps_show_their_package_in_notes_and_headers() {
+        let dir = tree(&[
+            (
+                "Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n\
+                 [dependencies]\nfoo = { package = \"bar-baz\", version = \"1\", optional = true }\n\n\
+                 [features]\nx = [\"dep:foo\"]\n",
+            ),
+            ("src/lib.rs", "pub fn f() { foo::run(); }\n"),
+        ]);
+        let out = run_scan(&ctx(&dir, ProjectConfig::default())).unwrap();
+        let r = out.reports.iter().find(|r| r.document.relative_path == "lib.rs").unwrap();
+
+        assert_eq!(r.notes.len(), 1);
+        assert_eq!((r.notes[0].feature_name.as_str(), r.notes[0].dep_labels()), ("x", vec!["foo (bar-baz)".to_string()]));
+        assert_eq!(corpus(&out, "lib.rs"), ["x"]);
+
+        let analysis = crate::analysis::state::AnalysisState::default();
+        let opts = crate::model::PlanOptions::default();
+        let cx = crate::plans::SectionContext { report: r, analysis: &analysis, opts: &opts, gate: None };
+        assert!(crate::plans::feature_notes(&cx).contains("//! deps:[\"foo (bar-baz)\"]\n"));
+    }
+
+    #[test]
+    fn workspace_groups_reach_only_selected_members_and_show_until_synced() {
+        let member = |name: &str| {
+            format!("[package]\nname = \"{name}\"\nversion = \"0.1.0\"\n\n[dependencies]\ntokio = {{ version = \"1\", optional = true }}\n")
+        };
+        let (a, b) = (member("a"), member("b"));
+        let dir = tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            (
+                crate::config::CONFIG_FILE,
+                "[workspace.feature_groups]\nasync = [\"tokio\"]\nasync-INCLUDE_CRATES = [\"a\"]\n",
+            ),
+            ("a/Cargo.toml", &a),
+            ("a/src/lib.rs", "pub fn f() {}\n"),
+            ("b/Cargo.toml", &b),
+            ("b/src/lib.rs", "pub fn f() {}\n"),
+        ]);
+        let scan = || run_workspace_scan(&ctx(&dir, load_config(dir.path()).unwrap())).unwrap();
+
+        let out = scan();
+        assert_eq!(out.crates["a"].feature_groups.keys().collect::<Vec<_>>(), ["async"]);
+        assert_eq!(out.crates["a"].unsynced_groups, ["async"]);
+        assert!(out.crates["b"].feature_groups.is_empty());
+        assert!(out.crates["b"].unsynced_groups.is_empty());
+
+        let synced = sync_feature_groups(&a, &out.crates["a"].feature_groups).unwrap();
+        fs::write(dir.path().join("a/Cargo.toml"), synced).unwrap();
+        assert!(scan().crates["a"].unsynced_groups.is_empty());
+    }
+
+    fn target_of(out: &EngineOutput, crate_rel_suffix: &str) -> (TargetKind, String) {
+        let r = out
+            .reports
+            .iter()
+            .find(|r| {
+                let root = r.document.scan_root.as_deref().unwrap_or("src");
+                format!("{root}/{}", r.document.relative_path) == crate_rel_suffix
+            })
+            .unwrap_or_else(|| panic!("no report for {crate_rel_suffix}"));
+        (r.target, r.target_name.clone())
+    }
+
+    const BIN_PKG: &str = "[package]\nname = \"tool\"\nversion = \"0.1.0\"\n";
+
+    #[test]
+    fn bin_only_crates_tag_src_modules_with_their_bin() {
+        let dir = tree(&[
+            ("Cargo.toml", BIN_PKG),
+            ("src/main.rs", "mod util;\nfn main() { util::f(); }\n"),
+            ("src/util.rs", "pub fn f() {}\n"),
+            ("src/bin/other/main.rs", "mod helper;\nfn main() {}\n"),
+            ("src/bin/other/helper.rs", "pub fn g() {}\n"),
+            ("build.rs", "fn main() {}\n"),
+            ("tests/it.rs", "#[test]\nfn t() {}\n"),
+        ]);
+        let out = run_scan(&ctx(&dir, ProjectConfig::default())).unwrap();
+
+        assert_eq!(target_of(&out, "src/main.rs"), (TargetKind::Bin, "tool".into()));
+        assert_eq!(target_of(&out, "src/util.rs"), (TargetKind::Bin, "tool".into()));
+        assert_eq!(target_of(&out, "src/bin/other/helper.rs"), (TargetKind::Bin, "other".into()));
+        assert_eq!(target_of(&out, "./build.rs").0, TargetKind::Build);
+        assert_eq!(target_of(&out, "tests/it.rs").0, TargetKind::Test);
+    }
+
+    #[test]
+    fn lib_crates_keep_src_modules_on_the_lib() {
+        let dir = tree(&[
+            ("Cargo.toml", BIN_PKG),
+            ("src/lib.rs", "pub mod util;\n"),
+            ("src/util.rs", "pub fn f() {}\n"),
+            ("src/main.rs", "fn main() { tool::util::f(); }\n"),
+        ]);
+        let out = run_scan(&ctx(&dir, ProjectConfig::default())).unwrap();
+        assert_eq!(target_of(&out, "src/util.rs"), (TargetKind::Lib, "tool".into()));
+        assert_eq!(target_of(&out, "src/main.rs").0, TargetKind::Bin);
+    }
+
+    fn skipped(out: &EngineOutput) -> Vec<(String, String)> {
+        out.skipped.iter().map(|f| (f.path.clone(), f.reason.describe())).collect()
+    }
+
+    const PLAIN: &str = "[package]\nname = \"a\"\nversion = \"0.1.0\"\n";
+
+    #[test]
+    fn gitignored_dirs_are_reported_once_and_not_walked() {
+        let dir = tree(&[
+            ("Cargo.toml", PLAIN),
+            (".gitignore", "/src/gen/\nsrc/scratch.rs\n"),
+            ("src/lib.rs", "pub mod a;\n"),
+            ("src/a.rs", "\n"),
+            ("src/gen/big.rs", "\n"),
+            ("src/gen/deeper/more.rs", "\n"),
+            ("src/scratch.rs", "\n"),
+        ]);
+        let config = ProjectConfig { respect_gitignore: true, ..Default::default() };
+        let out = run_scan(&ctx(&dir, config)).unwrap();
+
+        let scanned: Vec<&str> = out.reports.iter().map(|r| r.document.relative_path.as_str()).collect();
+        assert_eq!(scanned, ["a.rs", "lib.rs"]);
+        let ignored = SkipReason::Ignored.describe();
+        assert_eq!(skipped(&out), [("src/gen/".to_string(), ignored.clone()), ("src/scratch.rs".to_string(), ignored)]);
+
+        // without the option everything is scanned
+        let out = run_scan(&ctx(&dir, ProjectConfig::default())).unwrap();
+        assert_eq!(out.reports.len(), 5);
+        assert!(out.skipped.is_empty());
+    }
+
+    #[test]
+    fn globs_and_the_opt_out_marker_skip_files_with_a_reason() {
+        let dir = tree(&[
+            ("Cargo.toml", PLAIN),
+            ("src/lib.rs", "pub mod a;\npub mod b;\npub mod c;\n"),
+            ("src/a.rs", "// code_passenger:skip\n"),
+            ("src/b.rs", "\n"),
+            ("src/c.rs", "\n"),
+        ]);
+        let config = ProjectConfig {
+            include: vec!["src/*.rs".into()],
+            exclude: vec!["src/b.rs".into()],
+            ..Default::default()
+        };
+        let out = run_scan(&ctx(&dir, config)).unwrap();
+
+        let scanned: Vec<&str> = out.reports.iter().map(|r| r.document.relative_path.as_str()).collect();
+        assert_eq!(scanned, ["c.rs", "lib.rs"]);
+        let reasons: BTreeMap<String, String> = skipped(&out).into_iter().collect();
+        assert_eq!(reasons["src/a.rs"], SkipReason::OptOut.describe());
+        assert_eq!(reasons["src/b.rs"], SkipReason::Excluded { pattern: "src/b.rs".into() }.describe());
+    }
+
+    #[test]
+    fn undeclared_files_are_orphans_and_dangling_mods_missing() {
+        let dir = tree(&[
+            ("Cargo.toml", PLAIN),
+            ("src/lib.rs", "pub mod a;\nmod gone;\n"),
+            ("src/a.rs", "\n"),
+            ("src/old.rs", "\n"),
+            ("src/bin/tool.rs", "fn main() {}\n"),
+        ]);
+        let out = run_scan(&ctx(&dir, ProjectConfig::default())).unwrap();
+
+        assert_eq!(out.orphans, ["src/old.rs"]);
+        let missing: Vec<&str> = out.missing_modules.iter().map(|m| m.module_path.as_str()).collect();
+        assert_eq!(missing, ["crate::gone"]);
+        let a = out.reports.iter().find(|r| r.document.relative_path == "a.rs").unwrap();
+        assert_eq!((a.module_path.as_str(), a.module_declared_in.as_deref()), ("crate::a", Some("src/lib.rs")));
+    }
+
+    #[test]
+    fn usages_land_on_the_longest_matching_module() {
+        let dir = tree(&[
+            ("Cargo.toml", PLAIN),
+            ("src/lib.rs", "pub mod net;\nfn run() { crate::net::http::get(); }\n"),
+            ("src/net.rs", "pub mod http;\npub fn local() { crate::net::local(); }\n"),
+            ("src/net/http.rs", "pub fn get() {}\n"),
+            ("tests/it.rs", "#[test]\nfn t() { a::net::local(); }\n"),
+        ]);
+        let out = run_scan(&ctx(&dir, ProjectConfig::default())).unwrap();
+        let usages = |rel: &str| -> Vec<(String, String)> {
+            let r = out.reports.iter().find(|r| r.document.relative_path == rel).unwrap();
+            r.usages.iter().map(|u| (u.file.clone(), u.scope.clone())).collect()
+        };
+
+        assert_eq!(usages("net/http.rs"), [("lib.rs".to_string(), "fn run".to_string())]);
+        // its own `crate::net::local` isn't a usage; the test target's `a::net` is
+        assert_eq!(usages("net.rs"), [("tests/it.rs".to_string(), "fn t".to_string())]);
+        assert!(usages("lib.rs").is_empty());
+    }
+}
@@ -22,11 +22,20 @@ pub enum PassengerError {
     #[error("path error: {0}")]
     Path(String),
 
+    #[error("config error: {0}")]
+    Config(String),
+
     #[error("unsupported: {0}")]
     Unsupported(String),
 
     #[error("changes needed (run annotate --write)")]
     ChangesNeeded,
+
+    #[error("{0} header(s) have hand-edited computed fields (fix them or rerun annotate with --force)")]
+    HeaderConflicts(usize),
+
+    #[error("Cargo.toml out of sync with feature groups (run features sync --write)")]
+    FeaturesOutOfSync,
 }
 
 pub type Result<T> = std::result::Result<T, PassengerError>;
\ No newline at end of file
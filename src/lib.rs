@@ -1,5 +1,6 @@
 pub mod cli;
 pub mod cargo;
+pub mod config;
 pub mod model;
 pub mod error;
 pub mod plans;
@@ -8,3 +9,6 @@ pub mod packs;
 pub mod engine;
 pub mod scaffolds;
 pub mod analysis;
+
+#[cfg(test)]
+mod test_util;
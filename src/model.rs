@@ -10,17 +10,275 @@ pub struct ManifestInfo {
     pub all_deps: BTreeSet<String>,
     /// optional dependency keys (as used in code: '-' normalized to '_')
     pub optional_deps: BTreeSet<String>,
-    /// feature -> enabled deps (subset of optional_deps), normalized to code idents
+    /// feature -> enabled deps (subset of optional_deps), normalized to code idents.
+    /// Transitive: includes deps turned on through other features. Also has an
+    /// entry for each implicit optional-dep feature.
     pub feature_deps: BTreeMap<String, BTreeSet<String>>,
-    /// dep -> features that enable it
+    /// dep -> features that enable it (directly or transitively)
     pub dep_features: BTreeMap<String, BTreeSet<String>>,
+    /// full feature graph: edges, direct/weak deps, closures, implicit features
+    #[serde(default)]
+    pub feature_graph: FeatureGraph,
+    /// code ident -> Cargo.toml entry (real package name, version requirement)
+    #[serde(default)]
+    pub deps: BTreeMap<String, DepInfo>,
+    /// root Cargo.toml of the workspace this crate belongs to, if any
+    #[serde(default)]
+    pub workspace_root: Option<String>,
+    /// every target Cargo would build: declared (`[[bin]]`, `[[test]]`, ...) and
+    /// auto-discovered (`src/main.rs`, `tests/*.rs`, `build.rs`, ...)
+    #[serde(default)]
+    pub targets: Vec<TargetInfo>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetKind {
+    #[default]
+    Lib,
+    Bin,
+    Test,
+    Example,
+    Bench,
+    /// the build script
+    Build,
+    /// a configured `extra_roots` dir that isn't a Cargo target
+    Other,
+}
+
+impl TargetKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TargetKind::Lib => "lib",
+            TargetKind::Bin => "bin",
+            TargetKind::Test => "test",
+            TargetKind::Example => "example",
+            TargetKind::Bench => "bench",
+            TargetKind::Build => "build script",
+            TargetKind::Other => "other",
+        }
+    }
+
+    /// Dependency tables code in this kind of target may use.
+    pub fn allowed_dep_kinds(self) -> &'static [DepKind] {
+        match self {
+            TargetKind::Lib | TargetKind::Bin => &[DepKind::Normal],
+            TargetKind::Test | TargetKind::Example | TargetKind::Bench => &[DepKind::Normal, DepKind::Dev],
+            TargetKind::Build => &[DepKind::Build],
+            TargetKind::Other => &[DepKind::Normal, DepKind::Dev, DepKind::Build],
+        }
+    }
+}
+
+/// Files reachable from one crate root through `mod` declarations.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModuleTree {
+    /// file -> the module it is loaded as
+    pub files: BTreeMap<std::path::PathBuf, ModuleFile>,
+    /// `mod foo;` declarations with no file behind them
+    pub missing: Vec<MissingModule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModuleFile {
+    /// canonical path (`crate::passenger::store`)
+    pub module_path: String,
+    /// `#[cfg(...)]` predicates on the `mod` declarations leading here, outermost first
+    #[serde(default)]
+    pub cfg: Vec<CfgExpr>,
+    /// file holding the `mod` item, relative to the crate root; `None` for crate roots
+    #[serde(default)]
+    pub declared_in: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MissingModule {
+    pub module_path: String,
+    /// file holding the `mod` item, relative to the crate root
+    pub declared_in: String,
+    /// files that were tried, relative to the crate root
+    pub candidates: Vec<String>,
+}
+
+/// A source file `run_scan` walked past, and why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedFile {
+    /// relative to the crate root; ends in `/` for a whole ignored dir
+    pub path: String,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SkipReason {
+    /// matched an `exclude` glob
+    Excluded { pattern: String },
+    /// `include` globs are set and none matched
+    NotIncluded,
+    /// matched a `.gitignore` / `.ignore` rule
+    Ignored,
+    /// carries the pack's opt-out marker comment
+    OptOut,
+}
+
+impl SkipReason {
+    pub fn describe(&self) -> String {
+        match self {
+            SkipReason::Excluded { pattern } => format!("excluded by `{pattern}`"),
+            SkipReason::NotIncluded => "not matched by any include glob".to_string(),
+            SkipReason::Ignored => "ignored by .gitignore/.ignore".to_string(),
+            SkipReason::OptOut => "opt-out marker".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetInfo {
+    pub kind: TargetKind,
+    pub name: String,
+    /// entry file relative to the crate root (`src/bin/tool.rs`, `build.rs`)
+    pub path: String,
+}
+
+/// Which table a dependency is declared in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DepKind {
+    Normal,
+    Dev,
+    Build,
+}
+
+impl DepKind {
+    pub fn table(self) -> &'static str {
+        match self {
+            DepKind::Normal => "dependencies",
+            DepKind::Dev => "dev-dependencies",
+            DepKind::Build => "build-dependencies",
+        }
+    }
+}
+
+impl ManifestInfo {
+    /// Real package name behind a code ident (differs for `foo = { package = "bar-baz" }`).
+    pub fn package_of<'a>(&'a self, ident: &'a str) -> &'a str {
+        self.deps.get(ident).map(|d| d.package.as_str()).unwrap_or(ident)
+    }
+
+    /// "foo" or, for renamed deps, "foo (bar-baz)".
+    pub fn dep_label(&self, ident: &str) -> String {
+        dep_label(ident, self.package_of(ident))
+    }
+}
+
+pub fn dep_label(ident: &str, package: &str) -> String {
+    if package.replace('-', "_") == ident {
+        ident.to_string()
+    } else {
+        format!("{ident} ({package})")
+    }
+}
+
+/// `[features]` resolved the way Cargo sees it. All keys are feature names.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FeatureGraph {
+    /// feature -> features it lists directly
+    pub edges: BTreeMap<String, BTreeSet<String>>,
+    /// feature -> optional deps it turns on directly (`dep:foo`, `foo/bar`, implicit `foo`)
+    pub direct_deps: BTreeMap<String, BTreeSet<String>>,
+    /// feature -> deps it only touches weakly (`foo?/bar` does not turn `foo` on)
+    pub weak_deps: BTreeMap<String, BTreeSet<String>>,
+    /// feature -> every feature it turns on, transitively (itself excluded)
+    pub closure: BTreeMap<String, BTreeSet<String>>,
+    /// feature -> every optional dep it turns on, transitively
+    pub closure_deps: BTreeMap<String, BTreeSet<String>>,
+    /// `foo = ["dep:foo"]` features Cargo adds for optional deps never named via `dep:`
+    pub implicit: BTreeSet<String>,
+    /// features on when default features are on (`default` and its closure)
+    pub default_on: BTreeSet<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepInfo {
+    /// key as written in Cargo.toml ("foo-bar")
+    pub key: String,
+    /// `package = "..."` if set, otherwise the key
+    pub package: String,
+    /// version requirement ("1.0", "^0.4"); None for path/git-only deps
+    pub version_req: Option<String>,
+    pub optional: bool,
+    /// enabled features of the dependency (workspace + member, merged)
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default = "default_true")]
+    pub default_features: bool,
+    /// `workspace = true`: inherited from `[workspace.dependencies]`
+    #[serde(default)]
+    pub workspace: bool,
+    /// setting ("version", "package", "features", ...) -> manifest it came from
+    #[serde(default)]
+    pub sources: BTreeMap<String, SettingSource>,
+    /// tables the dep is declared in (a key may be in several)
+    #[serde(default)]
+    pub kinds: BTreeSet<DepKind>,
+    /// `[target.<spec>.*dependencies]` tables the dep is declared under
+    #[serde(default)]
+    pub platforms: BTreeSet<String>,
+    /// declared in a plain (not target-specific) table too
+    #[serde(default = "default_true")]
+    pub any_platform: bool,
+}
+
+impl DepInfo {
+    /// Where a target-specific dep exists: `cfg(unix)` specs as one predicate.
+    /// `None` if it's available everywhere or a spec is a bare target triple.
+    pub fn platform_cfg(&self) -> Option<CfgExpr> {
+        if self.any_platform {
+            return None;
+        }
+        let specs: Option<Vec<CfgExpr>> = self
+            .platforms
+            .iter()
+            .map(|p| p.strip_prefix("cfg(")?.strip_suffix(')').and_then(CfgExpr::parse))
+            .collect();
+        match specs? {
+            mut one if one.len() == 1 => one.pop(),
+            many if many.is_empty() => None,
+            many => Some(CfgExpr::Any(many)),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SettingSource {
+    Member,    // the crate's own Cargo.toml
+    Workspace, // [workspace.dependencies] in the workspace root
+    Both,      // merged (e.g. `features` from both)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DocumentDetails {
     pub filename: String,
     /// path relative to src root (e.g. "net/http.rs")
     pub relative_path: String,
+    /// top-level dir (relative to the crate root) the file lives under when it
+    /// isn't in the src dir: `tests`, `examples`, or `.` for `build.rs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan_root: Option<String>,
+}
+
+impl DocumentDetails {
+    /// `relative_path`, prefixed with the scan root for files outside src.
+    pub fn display_path(&self) -> String {
+        match &self.scan_root {
+            Some(root) => format!("{root}/{}", self.relative_path),
+            None => self.relative_path.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -29,41 +287,363 @@ pub struct UsedSymbols {
     pub modules: std::collections::BTreeSet<String>,  // optional (crate::foo, std::fs, etc.)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FeatureNote {
     pub feature_name: String,
     pub deps: Vec<String>,
+    /// real package name per entry of `deps` (same order)
+    #[serde(default)]
+    pub packages: Vec<String>,
     pub scope: Vec<String>,
     pub corpus: bool,
     pub used_in_file: bool,
 }
 
+impl FeatureNote {
+    /// `deps` with the real package name added for renamed deps.
+    pub fn dep_labels(&self) -> Vec<String> {
+        self.deps
+            .iter()
+            .enumerate()
+            .map(|(i, d)| dep_label(d, self.packages.get(i).map(|p| p.as_str()).unwrap_or(d)))
+            .collect()
+    }
+}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub enum UseKind {
     UseStmt,       // `use dep::foo`
     ExternCrate,   // `extern crate dep;`
     Attribute,     // `#[dep::something]`
     MacroCall,     // `dep::foo!()`
+    Derive,        // `#[derive(Serialize)]`
+    HelperAttr,    // `#[serde(rename = "x")]`
+    #[default]
     Path,          // everything else `dep::foo::bar`
+    ImportedIdent, // `Mutex::new()` after `use dep::sync::Mutex;`
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UseSite {
     pub dep: String,     // "regex"
-    pub path: String,    // "regex::RegexSet"
-    pub head: String,    // "RegexSet" (first segment after dep::)
+    pub path: String,    // "regex::RegexSet" (as written, may go through an alias)
+    pub resolved: String,// "regex::RegexSet" (after aliases / re-exports are followed)
+    pub head: String,    // "RegexSet" (first segment after dep:: in the resolved path)
     pub kind: UseKind,
     pub line: usize,     // 1-based
     pub scope: String,   // "fn run" / "impl Foo" / "file"
+    /// inside a `#[cfg(test)]` / `#[test]` item
+    #[serde(default)]
+    pub test_only: bool,
+    /// item-level `cfg`/`cfg_attr` gates around the use (file gates excluded)
+    #[serde(default)]
+    pub cfg: Option<CfgExpr>,
+    /// enclosing `if cfg!(...)` condition
+    #[serde(default)]
+    pub runtime_cfg: Option<CfgExpr>,
+}
+
+/// A `cfg` predicate (`#[cfg(...)]`, `#[cfg_attr(pred, ...)]`, `cfg!(...)`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CfgExpr {
+    /// `test`, `unix`, `debug_assertions`
+    Name(String),
+    /// `feature = "net"`, `target_os = "linux"`
+    KeyValue { key: String, value: String },
+    All(Vec<CfgExpr>),
+    Any(Vec<CfgExpr>),
+    Not(Box<CfgExpr>),
+}
+
+impl CfgExpr {
+    pub fn feature(name: &str) -> Self {
+        CfgExpr::KeyValue { key: "feature".to_string(), value: name.to_string() }
+    }
+
+    /// Conjunction of `gates`: `None` if empty, the gate itself if there's one.
+    pub fn all_of(mut gates: Vec<CfgExpr>) -> Option<CfgExpr> {
+        match gates.len() {
+            0 => None,
+            1 => gates.pop(),
+            _ => Some(CfgExpr::All(gates)),
+        }
+    }
+
+    /// Three-valued evaluation: `leaf` answers for `Name`/`KeyValue` predicates,
+    /// `None` meaning "could be either".
+    pub fn eval(&self, leaf: &dyn Fn(&CfgExpr) -> Option<bool>) -> Option<bool> {
+        match self {
+            CfgExpr::Name(_) | CfgExpr::KeyValue { .. } => leaf(self),
+            CfgExpr::All(xs) => {
+                let vals: Vec<Option<bool>> = xs.iter().map(|x| x.eval(leaf)).collect();
+                if vals.contains(&Some(false)) {
+                    Some(false)
+                } else if vals.iter().all(|v| *v == Some(true)) {
+                    Some(true)
+                } else {
+                    None
+                }
+            }
+            CfgExpr::Any(xs) => {
+                let vals: Vec<Option<bool>> = xs.iter().map(|x| x.eval(leaf)).collect();
+                if vals.contains(&Some(true)) {
+                    Some(true)
+                } else if vals.iter().all(|v| *v == Some(false)) {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+            CfgExpr::Not(x) => x.eval(leaf).map(|v| !v),
+        }
+    }
+
+    /// Parse a predicate as written inside `cfg(...)`: `unix`, `target_os = "linux"`,
+    /// `any(feature = "a", not(windows))`.
+    pub fn parse(s: &str) -> Option<CfgExpr> {
+        let mut p = CfgParser { rest: s };
+        let expr = p.expr()?;
+        p.rest.trim().is_empty().then_some(expr)
+    }
+
+    /// The parts of a top-level `all(...)`, or the predicate itself.
+    pub fn conjuncts(&self) -> Vec<&CfgExpr> {
+        match self {
+            CfgExpr::All(xs) => xs.iter().collect(),
+            other => vec![other],
+        }
+    }
+
+    /// Features the predicate requires or allows (`feature = "x"` not under a `not`).
+    pub fn positive_features(&self) -> BTreeSet<String> {
+        fn walk(e: &CfgExpr, negated: bool, out: &mut BTreeSet<String>) {
+            match e {
+                CfgExpr::KeyValue { key, value } if key == "feature" && !negated => {
+                    out.insert(value.clone());
+                }
+                CfgExpr::Name(_) | CfgExpr::KeyValue { .. } => {}
+                CfgExpr::All(xs) | CfgExpr::Any(xs) => xs.iter().for_each(|x| walk(x, negated, out)),
+                CfgExpr::Not(x) => walk(x, !negated, out),
+            }
+        }
+        let mut out = BTreeSet::new();
+        walk(self, false, &mut out);
+        out
+    }
+}
+
+impl std::fmt::Display for CfgExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |xs: &[CfgExpr]| xs.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", ");
+        match self {
+            CfgExpr::Name(n) => write!(f, "{n}"),
+            CfgExpr::KeyValue { key, value } => write!(f, "{key} = {value:?}"),
+            CfgExpr::All(xs) => write!(f, "all({})", list(xs)),
+            CfgExpr::Any(xs) => write!(f, "any({})", list(xs)),
+            CfgExpr::Not(x) => write!(f, "not({x})"),
+        }
+    }
+}
+
+struct CfgParser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> CfgParser<'a> {
+    fn eat(&mut self, c: char) -> bool {
+        match self.rest.trim_start().strip_prefix(c) {
+            Some(r) => {
+                self.rest = r;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        let s = self.rest.trim_start();
+        let end = s.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(s.len());
+        let (id, rest) = s.split_at(end);
+        self.rest = rest;
+        (!id.is_empty()).then_some(id)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let s = self.rest.trim_start().strip_prefix('"')?;
+        let end = s.find('"')?;
+        self.rest = &s[end + 1..];
+        Some(s[..end].to_string())
+    }
+
+    fn expr(&mut self) -> Option<CfgExpr> {
+        let id = self.ident()?;
+        if self.eat('(') {
+            let mut xs = vec![];
+            while !self.eat(')') {
+                xs.push(self.expr()?);
+                if !self.eat(',') && !self.rest.trim_start().starts_with(')') {
+                    return None;
+                }
+            }
+            return match id {
+                "all" => Some(CfgExpr::All(xs)),
+                "any" => Some(CfgExpr::Any(xs)),
+                "not" if xs.len() == 1 => xs.pop().map(|x| CfgExpr::Not(Box::new(x))),
+                _ => None,
+            };
+        }
+        if self.eat('=') {
+            return Some(CfgExpr::KeyValue { key: id.to_string(), value: self.string()? });
+        }
+        Some(CfgExpr::Name(id.to_string()))
+    }
+}
+
+/// Gate selection settings for one file (see `plans::select_gate`).
+#[derive(Debug, Clone, Default)]
+pub struct GateOptions {
+    /// groups of interchangeable features (`["rt-tokio", "rt-async-std"]`): a file
+    /// needing several of one group is gated on `any(...)` of them
+    pub alternatives: Vec<Vec<String>>,
+    /// also emit `#![cfg_attr(docsrs, doc(cfg(...)))]`
+    pub docsrs: bool,
+    /// hand-picked gate replacing the computed one
+    pub gate: Option<CfgExpr>,
+}
+
+/// Header rendering settings for one file.
+#[derive(Debug, Clone, Default)]
+pub struct PlanOptions {
+    pub gates: GateOptions,
+    /// how many `usages:` entries to list before `(+N more)`
+    pub max_usages: usize,
+    /// replaces the plan's own section list
+    pub sections: Option<Vec<PlanSection>>,
+}
+
+/// A file + scope referring into another module (`cli.rs`, `fn run`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Usage {
+    pub file: String,
+    pub scope: String,
+}
+
+/// An optional dependency use that compiles even with every feature enabling
+/// the dependency turned off.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UngatedUse {
+    pub dep: String,
+    pub line: usize,
+    pub scope: String,
+    /// features that turn the dep on
+    pub features: Vec<String>,
+    /// everything gating the use (module, file and item cfgs); `None` = nothing
+    pub gate: Option<CfgExpr>,
+    /// `if cfg!(...)` around the use: checked at run time, the code still compiles
+    pub runtime_cfg: Option<CfgExpr>,
+}
+
+/// Crate-wide `pub use` re-exports: absolute item path -> what it stands for
+/// ("crate::tsync" -> "tokio::sync").
+pub type ReExports = BTreeMap<String, String>;
+
+/// Crates behind derives / helper attributes that are written without a path
+/// (`#[derive(Serialize)]`, `#[serde(rename = "x")]`). name -> crate ident.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AttrCrates {
+    pub derives: BTreeMap<String, String>,
+    pub helper_attrs: BTreeMap<String, String>,
+}
+
+impl AttrCrates {
+    /// `self` with `overrides` layered on top (overrides win per name).
+    pub fn layered(mut self, overrides: &AttrCrates) -> Self {
+        self.derives.extend(overrides.derives.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self.helper_attrs.extend(overrides.helper_attrs.iter().map(|(k, v)| (k.clone(), v.clone())));
+        self
+    }
+}
+
+/// Crate-level context handed to the pack's syntax scan for one file.
+#[derive(Debug, Clone, Copy)]
+pub struct ScanContext<'a> {
+    /// "crate" / "crate::net::http"
+    pub module_path: &'a str,
+    pub reexports: &'a ReExports,
+    /// user mappings, layered over the pack's built-in ones
+    pub attr_crates: &'a AttrCrates,
+}
+
+/// How a file's use sites were extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub enum ScanMode {
+    Syntax,        // parsed into a syntax tree by the language pack
+    #[default]
+    Regex,         // line/regex fallback (no parser, or the file failed to parse)
+}
+
+/// Raw per-file scan result, before feature notes are derived from it.
+#[derive(Debug, Clone, Default)]
+pub struct FileScan {
+    pub mode: ScanMode,
+    pub used: UsedSymbols,
+    pub external_use_sites: Vec<UseSite>,
+    pub internal_use_sites: Vec<UseSite>,
+    /// dep -> scope labels the dep is used in
+    pub dep_scopes: BTreeMap<String, BTreeSet<String>>,
+    /// full scope path (`impl Foo > fn new`) -> gate on that scope (only gated scopes)
+    pub scope_cfgs: BTreeMap<String, CfgExpr>,
+    /// hand-written inner `#![cfg(...)]` attributes
+    pub file_cfg: Vec<CfgExpr>,
+    /// inner `#![cfg(...)]` attributes inside the generated header block
+    pub header_cfg: Vec<CfgExpr>,
 }
 
 pub type UseSites = BTreeMap<String, BTreeMap<String, usize>>;
 pub type UseSitesCount = UseSites;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FileReport {
     pub document: DocumentDetails,
+    /// target the file is compiled into
+    #[serde(default)]
+    pub target: TargetKind,
+    /// that target's name (crate name for lib/bin, file stem for tests, ...)
+    #[serde(default)]
+    pub target_name: String,
+    /// canonical module path from the target's module tree (`crate::net::http`);
+    /// derived from the file's location when no tree declares it
+    #[serde(default)]
+    pub module_path: String,
+    /// `#[cfg(...)]` predicates on the `mod` items that load this file
+    #[serde(default)]
+    pub module_cfg: Vec<CfgExpr>,
+    /// file holding the `mod` item that loads this one, relative to the crate root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module_declared_in: Option<String>,
+    /// hand-written inner `#![cfg(...)]` attributes (outside the header block)
+    #[serde(default)]
+    pub file_cfg: Vec<CfgExpr>,
+    /// inner `#![cfg(...)]` attributes inside the generated header block
+    #[serde(default)]
+    pub header_cfg: Vec<CfgExpr>,
+    /// platforms of the target-specific deps the file uses (`unix` for a
+    /// `[target.'cfg(unix)'.dependencies]` entry)
+    #[serde(default)]
+    pub platform_cfgs: Vec<CfgExpr>,
+    /// full scope path (`impl Foo > fn new`) -> gate on that scope
+    #[serde(default)]
+    pub scope_cfgs: BTreeMap<String, CfgExpr>,
+    /// optional-dep uses reachable with the dep's features off
+    #[serde(default)]
+    pub ungated_uses: Vec<UngatedUse>,
+    /// other files of the same target referring into this module
+    #[serde(default)]
+    pub usages: Vec<Usage>,
+    /// reports from before syntax scanning existed were regex-scanned
+    #[serde(default)]
+    pub scan_mode: ScanMode,
     pub used: UsedSymbols,
     pub notes: Vec<FeatureNote>,
     pub corpus_features: Vec<String>,
@@ -102,16 +682,122 @@ pub struct FileReport {
 //     pub corpus_features: Vec<String>,
 // }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PlanSection {
     DocumentDetails,
     FeatureNotes,
     CorpusGates,
-    Custom(&'static str),
+    /// rendered by the provider registered under this name
+    Custom(String),
+}
+
+impl PlanSection {
+    /// `document_details`, `feature_notes`, `corpus_gates`; any other name is a
+    /// custom section.
+    pub fn parse(name: &str) -> Self {
+        match name {
+            "document_details" => PlanSection::DocumentDetails,
+            "feature_notes" => PlanSection::FeatureNotes,
+            "corpus_gates" => PlanSection::CorpusGates,
+            other => PlanSection::Custom(other.to_string()),
+        }
+    }
 }
 
 
 
+/// What picked a file's header plan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanSource {
+    /// a `code_passenger:plan=<name>` comment in the file
+    Directive,
+    /// the `overrides` entry with this key
+    Override(String),
+    /// `--plan` or the config's `plan`
+    Config,
+    Default,
+}
+
+impl std::fmt::Display for PlanSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlanSource::Directive => write!(f, "file directive"),
+            PlanSource::Override(key) => write!(f, "override `{key}`"),
+            PlanSource::Config => write!(f, "`--plan`/config"),
+            PlanSource::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// Why annotate would change a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeReason {
+    /// no header block yet
+    HeaderMissing,
+    /// file, item or `mod` gates differ
+    GatesChanged,
+    /// computed header fields (notes, usages, ...) differ
+    NotesChanged,
+    /// only the end marker's hash stamp differs
+    StampChanged,
+    Other,
+}
+
+impl std::fmt::Display for ChangeReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ChangeReason::HeaderMissing => "header missing",
+            ChangeReason::GatesChanged => "gates changed",
+            ChangeReason::NotesChanged => "notes changed",
+            ChangeReason::StampChanged => "hash stamp changed",
+            ChangeReason::Other => "other edits",
+        })
+    }
+}
+
+/// Where annotate puts feature gates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GateLevel {
+    /// `#![cfg(...)]` in the header block for the whole file
+    #[default]
+    File,
+    /// `#[cfg(...)]` on the items that use the gated deps; the header keeps only
+    /// features every item needs
+    Item,
+}
+
+impl GateLevel {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "file" => Some(Self::File),
+            "item" => Some(Self::Item),
+            _ => None,
+        }
+    }
+}
+
+/// Gate wanted on a child module's declaration (`mod foo;`) and its `pub use`
+/// re-exports in the parent file.
+#[derive(Debug, Clone)]
+pub struct ModGate {
+    /// module path relative to the parent file (`["foo"]`, or `["inline", "foo"]`
+    /// when declared inside an inline `mod inline { ... }`)
+    pub path: Vec<String>,
+    /// the child's file-level gate
+    pub gate: CfgExpr,
+}
+
+/// A file with its item-level gates rewritten.
+#[derive(Debug, Clone)]
+pub struct GatedItems {
+    pub content: String,
+    /// features left for the header's file-level gate
+    pub file_features: Vec<String>,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum ScaffoldKind {
     Module,
@@ -148,3 +834,99 @@ impl ScaffoldOutput {
     pub fn unsupported() -> Self { Self { files: vec![] } }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_without_scan_mode_are_regex_scanned() {
+        let mut json = serde_json::to_value(FileReport::default()).unwrap();
+        json.as_object_mut().unwrap().remove("scan_mode");
+        let report: FileReport = serde_json::from_value(json).unwrap();
+        assert_eq!(report.scan_mode, ScanMode::Regex);
+    }
+
+    #[test]
+    fn cfg_eval_is_three_valued() {
+        // features known, everything else unknown
+        let leaf = |e: &CfgExpr| match e {
+            CfgExpr::KeyValue { key, value } if key == "feature" => Some(value == "on"),
+            _ => None,
+        };
+        let on = CfgExpr::feature("on");
+        let off = CfgExpr::feature("off");
+        let unix = CfgExpr::Name("unix".to_string());
+
+        assert_eq!(CfgExpr::All(vec![on.clone(), unix.clone()]).eval(&leaf), None);
+        assert_eq!(CfgExpr::All(vec![off.clone(), unix.clone()]).eval(&leaf), Some(false));
+        assert_eq!(CfgExpr::Any(vec![on.clone(), unix.clone()]).eval(&leaf), Some(true));
+        assert_eq!(CfgExpr::Any(vec![off.clone(), unix.clone()]).eval(&leaf), None);
+        assert_eq!(CfgExpr::Not(Box::new(off.clone())).eval(&leaf), Some(true));
+        assert_eq!(CfgExpr::Not(Box::new(unix)).eval(&leaf), None);
+        // empty `all()` holds, empty `any()` doesn't, like rustc
+        assert_eq!(CfgExpr::All(vec![]).eval(&leaf), Some(true));
+        assert_eq!(CfgExpr::Any(vec![]).eval(&leaf), Some(false));
+    }
+
+    #[test]
+    fn cfg_strings_parse_like_rustc_predicates() {
+        let e = CfgExpr::parse(r#"all( unix , any(feature = "a", not(target_os="macos")), )"#).unwrap();
+        assert_eq!(
+            e,
+            CfgExpr::All(vec![
+                CfgExpr::Name("unix".into()),
+                CfgExpr::Any(vec![
+                    CfgExpr::feature("a"),
+                    CfgExpr::Not(Box::new(CfgExpr::KeyValue { key: "target_os".into(), value: "macos".into() })),
+                ]),
+            ])
+        );
+        // Display writes back what parse reads
+        assert_eq!(CfgExpr::parse(&e.to_string()), Some(e));
+        assert_eq!(CfgExpr::parse("all()"), Some(CfgExpr::All(vec![])));
+    }
+
+    #[test]
+    fn malformed_cfg_strings_are_rejected() {
+        for bad in ["", "unix windows", "not(unix, windows)", "not()", "maybe(unix)", "feature = a", "feature = \"a", "any(unix", "unix)"] {
+            assert_eq!(CfgExpr::parse(bad), None, "{bad}");
+        }
+    }
+
+    fn dep(platforms: &[&str], any_platform: bool) -> DepInfo {
+        DepInfo {
+            key: "nix".into(),
+            package: "nix".into(),
+            version_req: None,
+            optional: true,
+            features: vec![],
+            default_features: true,
+            workspace: false,
+            sources: BTreeMap::new(),
+            kinds: BTreeSet::new(),
+            platforms: platforms.iter().map(|p| p.to_string()).collect(),
+            any_platform,
+        }
+    }
+
+    #[test]
+    fn platform_cfgs_come_from_target_tables() {
+        assert_eq!(dep(&["cfg(unix)"], false).platform_cfg(), Some(CfgExpr::Name("unix".into())));
+        assert_eq!(
+            dep(&["cfg(unix)", "cfg(target_os = \"wasi\")"], false).platform_cfg().unwrap().to_string(),
+            "any(target_os = \"wasi\", unix)"
+        );
+        // also declared for every platform, or under a bare triple: no usable cfg
+        assert_eq!(dep(&["cfg(unix)"], true).platform_cfg(), None);
+        assert_eq!(dep(&["cfg(unix)", "x86_64-pc-windows-msvc"], false).platform_cfg(), None);
+    }
+
+    #[test]
+    fn positive_features_skip_negated_ones() {
+        let e = CfgExpr::All(vec![
+            CfgExpr::feature("a"),
+            CfgExpr::Not(Box::new(CfgExpr::Any(vec![CfgExpr::feature("b"), CfgExpr::Not(Box::new(CfgExpr::feature("c")))]))),
+        ]);
+        assert_eq!(e.positive_features(), ["a".to_string(), "c".to_string()].into());
+    }
+}
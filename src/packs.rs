@@ -1,9 +1,9 @@
 use crate::{
     error::{PassengerError, Result},
-    model::{FileReport, ScaffoldOutput, ScaffoldRequest, UsedSymbols},
-    plans::HeaderPlan, scaffolds::ScaffoldPlan,
+    model::{ChangeReason, FileReport, FileScan, GateLevel, GatedItems, ModGate, ManifestInfo, ModuleTree, ReExports, ScanContext, ScaffoldOutput, ScaffoldRequest, UsedSymbols},
+    plans::{HeaderPlan, RenderContext}, scaffolds::ScaffoldPlan,
 };
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
 pub trait LanguagePack: Send + Sync {
     fn id(&self) -> &'static str;
@@ -11,15 +11,77 @@ pub trait LanguagePack: Send + Sync {
     /// Which files this pack owns.
     fn matches_path(&self, path: &Path) -> bool;
 
+    /// The file carries the opt-out marker (`code_passenger:skip` in a comment)
+    /// and must not be scanned or annotated.
+    fn opted_out(&self, _content: &str) -> bool {
+        false
+    }
+
+    /// `content` without its header block, and without the item and `mod` gates
+    /// annotate generated when `gates` is set; `None` if there is nothing to remove.
+    fn strip(&self, _content: &str, _gates: bool) -> Option<String> {
+        None
+    }
+
+    /// Why `original` became `updated` under annotate; never empty when they differ.
+    fn change_reasons(&self, original: &str, updated: &str) -> Vec<ChangeReason> {
+        if original == updated { vec![] } else { vec![ChangeReason::Other] }
+    }
+
+    /// Plan named by the file's plan directive (`code_passenger:plan=compact` as a
+    /// comment line of its own), which wins over every configured plan.
+    fn plan_directive<'a>(&self, _content: &'a str) -> Option<&'a str> {
+        None
+    }
+
     /// Extract “used deps/symbols” from file content.
     fn detect_usage(&self, content: &str) -> UsedSymbols;
 
+    /// Parse the file into a syntax tree and extract use sites + scopes from real items.
+    /// `None` means no parser is available or the file failed to parse; core then
+    /// falls back to regex scanning.
+    fn scan_syntax(&self, _content: &str, _manifest: &ManifestInfo, _ctx: ScanContext<'_>) -> Option<FileScan> {
+        None
+    }
+
+    /// Items a file re-exports (`pub use ...`), keyed by absolute path, so other
+    /// files' paths through them can be resolved. Runs before any `scan_syntax`.
+    fn collect_reexports(&self, _content: &str, _manifest: &ManifestInfo, _module_path: &str) -> ReExports {
+        ReExports::new()
+    }
+
+    /// Files the crate root `entry` pulls in, with their module paths. `None`
+    /// means the pack can't tell, so every walked file counts as a module.
+    fn module_tree(&self, _entry: &Path) -> Option<ModuleTree> {
+        None
+    }
+
     /// Render the header (doc + notes + gates) using a plan.
-    fn render_header(&self, plan: &dyn HeaderPlan, report: &FileReport) -> String;
+    fn render_header(&self, plan: &dyn HeaderPlan, cx: &RenderContext) -> String;
 
     /// Insert/replace header block idempotently.
     fn apply_header(&self, original: &str, header: &str) -> String;
 
+    /// The header block already in the file, begin marker through end marker line.
+    fn existing_header<'a>(&self, _content: &'a str) -> Option<&'a str> {
+        None
+    }
+
+    /// Rewrite the pack's item-level gates for `level` and say which of the
+    /// report's corpus features the header should still put on the whole file.
+    /// `None` means item gating isn't supported (or the file doesn't parse);
+    /// callers fall back to file-level gates.
+    fn gate_items(&self, _original: &str, _report: &FileReport, _level: GateLevel) -> Option<GatedItems> {
+        None
+    }
+
+    /// Gate the declarations of gated child modules (and re-exports from them) in
+    /// their parent file. `None` means unsupported or unparsable; the parent is
+    /// left as is.
+    fn gate_mod_decls(&self, _original: &str, _children: &[ModGate]) -> Option<String> {
+        None
+    }
+
     /// Optional: generate scaffolding for a new file/module.
     fn scaffold(&self, _plan: &dyn ScaffoldPlan, _req: ScaffoldRequest) -> Result<ScaffoldOutput> {
         Err(PassengerError::Unsupported("scaffold not supported by this pack".into()))
@@ -37,7 +99,25 @@ pub mod rust;
 pub fn get_pack(lang: &str) -> Result<Box<dyn LanguagePack>> {
     match lang {
         #[cfg(feature = "lang_rust")]
-        "rust" | "rs" => Ok(Box::new(rust::RustPack::default())),
+        "rust" | "rs" => Ok(Box::new(rust::RustPack)),
         _ => Err(PassengerError::Unsupported(format!("language pack '{lang}' not available"))),
     }
-}
\ No newline at end of file
+}
+
+/// Lexically drop `.` and fold `..`, keeping a leading `./` so paths compare
+/// equal to what a directory walk from the same root yields. `module_tree`
+/// results use this form.
+pub fn normalize_path(p: &Path) -> PathBuf {
+    let mut out: Vec<Component> = vec![];
+    for c in p.components() {
+        match c {
+            Component::CurDir if out.is_empty() => out.push(c),
+            Component::CurDir => {}
+            Component::ParentDir if matches!(out.last(), Some(Component::Normal(_))) => {
+                out.pop();
+            }
+            _ => out.push(c),
+        }
+    }
+    out.iter().collect()
+}
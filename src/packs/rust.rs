@@ -1,8 +1,9 @@
-use crate::model::{FileReport, ScaffoldKind, ScaffoldOutput, ScaffoldRequest, UsedSymbols};
+use crate::model::{ChangeReason, FileReport, FileScan, GateLevel, GatedItems, ModGate, ManifestInfo, ModuleTree, ReExports, ScanContext, ScaffoldKind, ScaffoldOutput, ScaffoldRequest, UsedSymbols};
 use crate::packs::rust::helpers::to_type_name;
-use crate::plans::HeaderPlan;
+use crate::plans::{HeaderPlan, RenderContext};
 use crate::scaffolds::ScaffoldPlan;
 use regex::RegexSet;
+use std::collections::BTreeSet;
 
 #[derive(Default)]
 pub struct RustPack;
@@ -10,6 +11,12 @@ pub struct RustPack;
 impl RustPack {
     pub const BEGIN_MARK: &'static str = "//! code_passenger:begin";
     pub const END_MARK: &'static str = "//! code_passenger:end";
+    pub const SKIP_MARK: &'static str = "code_passenger:skip";
+    /// trailing comment on generated item-level `#[cfg]`s
+    pub const GATE_MARK: &'static str = "code_passenger:gate";
+    /// `// code_passenger:plan=<name>` on a line of its own picks the file's
+    /// header plan (`<name>`: letters, digits, `_` and `-`)
+    pub const PLAN_MARK: &'static str = "code_passenger:plan=";
 
     fn build_usage_set(deps: &std::collections::BTreeSet<String>) -> (Vec<String>, RegexSet) {
         let deps: Vec<String> = deps.iter().cloned().collect();
@@ -44,15 +51,12 @@ impl RustPack {
 
     fn replace_or_insert(original: &str, header: &str) -> String {
         // Replace existing marked region if present
-        if let Some(beg) = original.find(Self::BEGIN_MARK) {
-            if let Some(end) = original.find(Self::END_MARK) {
-                let end_line = original[end..].find('\n').map(|i| end + i + 1).unwrap_or(original.len());
-                let mut out = String::new();
-                out.push_str(&original[..beg]);
-                out.push_str(header);
-                out.push_str(&original[end_line..]);
-                return out;
-            }
+        if let Some(span) = header_span(original) {
+            let mut out = String::new();
+            out.push_str(&original[..span.start]);
+            out.push_str(header);
+            out.push_str(&original[span.end..]);
+            return out;
         }
 
         // Insert after leading inner attrs `#![...]` (keep them first)
@@ -76,6 +80,46 @@ impl RustPack {
     }
 }
 
+/// Byte range of the header block, whole lines: from a line that is just the
+/// begin marker to the next line that is the end marker (plus its hash stamp).
+/// Markers inside other text don't count, and an end marker with no begin
+/// marker before it is passed over.
+pub(crate) fn header_span(content: &str) -> Option<std::ops::Range<usize>> {
+    let mut begin = None;
+    let mut at = 0;
+    for line in content.split_inclusive('\n') {
+        let t = line.trim();
+        if t == RustPack::BEGIN_MARK {
+            begin = Some(at);
+        } else if let Some(b) = begin.filter(|_| is_end_marker(t)) {
+            return Some(b..at + line.len());
+        }
+        at += line.len();
+    }
+    None
+}
+
+fn is_end_marker(line: &str) -> bool {
+    line.strip_prefix(RustPack::END_MARK).is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+}
+
+/// A line holding just a `#[cfg(...)]` attribute.
+fn is_cfg_line(line: &str) -> bool {
+    use syn::parse::Parser;
+    let t = line.trim();
+    t.starts_with("#[") && syn::Attribute::parse_outer.parse_str(t).is_ok_and(|a| a.len() == 1 && a[0].path().is_ident("cfg"))
+}
+
+/// Bodies of standalone plain `//` comment lines (not `///` or `//!` docs), trimmed.
+/// Directives (`// code_passenger:skip`) only count on lines like these.
+fn plain_comments(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("//"))
+        .filter(|body| !body.starts_with(['/', '!']))
+        .map(str::trim)
+}
+
 impl super::LanguagePack for RustPack {
     fn id(&self) -> &'static str { "rust" }
 
@@ -83,6 +127,18 @@ impl super::LanguagePack for RustPack {
         path.extension().and_then(|s| s.to_str()) == Some("rs")
     }
 
+    fn opted_out(&self, content: &str) -> bool {
+        plain_comments(content).any(|c| c == Self::SKIP_MARK)
+    }
+
+    fn plan_directive<'a>(&self, content: &'a str) -> Option<&'a str> {
+        plain_comments(content).find_map(|c| {
+            let name = c.strip_prefix(Self::PLAN_MARK)?;
+            let valid = !name.is_empty() && name.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_' || ch == '-');
+            valid.then_some(name)
+        })
+    }
+
     fn detect_usage(&self, content: &str) -> UsedSymbols {
         // NOTE: usage detection depends on optional_deps; pack alone can’t know them.
         // So core will call `RustPack::detect_usage_with_optional_deps(...)`.
@@ -91,14 +147,96 @@ impl super::LanguagePack for RustPack {
         UsedSymbols::default()
     }
 
-    fn render_header(&self, plan: &dyn HeaderPlan, report: &FileReport) -> String {
-        plan.render_for_rust(report, Self::BEGIN_MARK, Self::END_MARK)
+    fn scan_syntax(&self, content: &str, manifest: &ManifestInfo, ctx: ScanContext<'_>) -> Option<FileScan> {
+        syntax::scan_file(content, &manifest.all_deps, &manifest.crate_name, ctx)
+    }
+
+    fn collect_reexports(&self, content: &str, manifest: &ManifestInfo, module_path: &str) -> ReExports {
+        syntax::file_reexports(content, &manifest.all_deps, &manifest.crate_name, module_path)
+    }
+
+    fn module_tree(&self, entry: &std::path::Path) -> Option<ModuleTree> {
+        Some(modules::build_tree(entry))
+    }
+
+    fn render_header(&self, plan: &dyn HeaderPlan, cx: &RenderContext) -> String {
+        plan.render_for_rust(cx, Self::BEGIN_MARK, Self::END_MARK)
     }
 
     fn apply_header(&self, original: &str, header: &str) -> String {
         Self::replace_or_insert(original, header)
     }
 
+    fn existing_header<'a>(&self, content: &'a str) -> Option<&'a str> {
+        header_span(content).map(|span| &content[span])
+    }
+
+    fn strip(&self, content: &str, gates: bool) -> Option<String> {
+        // annotate inserts the block as whole lines and nothing around it
+        let mut out = match header_span(content) {
+            Some(span) => format!("{}{}", &content[..span.start], &content[span.end..]),
+            None => content.to_string(),
+        };
+        if gates {
+            out = out.split_inclusive('\n').filter(|l| !gates::is_gate_line(l)).collect();
+        }
+        (out != content).then_some(out)
+    }
+
+    fn change_reasons(&self, original: &str, updated: &str) -> Vec<ChangeReason> {
+        let mut out = BTreeSet::new();
+        let (old, new) = (self.existing_header(original), self.existing_header(updated));
+        match (old, new) {
+            (None, Some(_)) => {
+                out.insert(ChangeReason::HeaderMissing);
+            }
+            (Some(old), Some(new)) if old != new => {
+                // inner attributes in the header are the file gates; the last line is the end marker
+                fn split(h: &str) -> (Vec<&str>, Vec<&str>, Option<&str>) {
+                    let lines: Vec<&str> = h.lines().collect();
+                    let Some((end, body)) = lines.split_last() else { return (vec![], vec![], None) };
+                    let (gates, notes) = body.iter().partition(|l| l.starts_with("#!["));
+                    (gates, notes, Some(*end))
+                }
+                let (old_gates, old_notes, old_end) = split(old);
+                let (new_gates, new_notes, new_end) = split(new);
+                if old_gates != new_gates {
+                    out.insert(ChangeReason::GatesChanged);
+                }
+                if old_notes != new_notes {
+                    out.insert(ChangeReason::NotesChanged);
+                }
+                if out.is_empty() && old_end != new_end {
+                    out.insert(ChangeReason::StampChanged);
+                }
+            }
+            _ => {}
+        }
+
+        // outside the header annotate only touches item and `mod` gates
+        let body = |s: &str, h: Option<&str>| h.map_or(s.to_string(), |h| s.replacen(h, "", 1));
+        let (old_body, new_body) = (body(original, old), body(updated, new));
+        if old_body != new_body {
+            let gate_edit = similar::TextDiff::from_lines(&old_body, &new_body)
+                .iter_all_changes()
+                .filter(|c| c.tag() != similar::ChangeTag::Equal)
+                .all(|c| gates::is_gate_line(c.value()) || is_cfg_line(c.value()));
+            out.insert(if gate_edit { ChangeReason::GatesChanged } else { ChangeReason::Other });
+        }
+        if out.is_empty() && original != updated {
+            out.insert(ChangeReason::Other);
+        }
+        out.into_iter().collect()
+    }
+
+    fn gate_items(&self, original: &str, report: &FileReport, level: GateLevel) -> Option<GatedItems> {
+        gates::gate_items(original, report, level)
+    }
+
+    fn gate_mod_decls(&self, original: &str, children: &[ModGate]) -> Option<String> {
+        gates::gate_mod_decls(original, children)
+    }
+
 
     // inside impl super::LanguagePack for RustPack:
     fn scaffold(&self, plan: &dyn ScaffoldPlan, req: ScaffoldRequest) -> crate::error::Result<ScaffoldOutput> {
@@ -128,5 +266,147 @@ impl super::LanguagePack for RustPack {
 }
 
 
+pub mod aliases;
+pub mod cfg;
+pub mod derives;
 pub mod detectors;
-pub mod helpers;
\ No newline at end of file
+pub mod gates;
+pub mod helpers;
+pub mod modules;
+pub mod syntax;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packs::LanguagePack;
+
+    #[test]
+    fn opt_out_needs_a_standalone_marker_line() {
+        let pack = RustPack;
+        assert!(pack.opted_out("// code_passenger:skip\nfn main() {}\n"));
+        assert!(pack.opted_out("fn main() {}\n    //   code_passenger:skip  \n"));
+
+        // docs talking about the marker, trailing comments and look-alikes don't count
+        assert!(!pack.opted_out("//! Files with `// code_passenger:skip` are left alone.\n"));
+        assert!(!pack.opted_out("/// see code_passenger:skip\nfn f() {}\n"));
+        assert!(!pack.opted_out("fn f() {} // code_passenger:skip\n"));
+        assert!(!pack.opted_out("// code_passenger:skip-this-one\n"));
+        assert!(!pack.opted_out("const M: &str = \"code_passenger:skip\";\n"));
+    }
+
+    const HEADER: &str = "//! code_passenger:begin\n//! filename:a.rs\n//! code_passenger:end hash=abc\n";
+
+    #[test]
+    fn header_markers_only_count_as_whole_lines() {
+        let pack = RustPack;
+        let file = format!("#![allow(dead_code)]\n{HEADER}fn f() {{}}\n");
+        assert_eq!(pack.existing_header(&file), Some(HEADER));
+
+        // in a string or in doc text they're just text
+        let quoted = "const B: &str = \"//! code_passenger:begin\";\nconst E: &str = \"//! code_passenger:end\";\n";
+        assert_eq!(pack.existing_header(quoted), None);
+        let file = format!("{quoted}{HEADER}");
+        assert_eq!(pack.existing_header(&file), Some(HEADER));
+        assert_eq!(pack.existing_header("//! code_passenger:begin\n//! code_passenger:ending\n"), None);
+    }
+
+    #[test]
+    fn a_stray_end_marker_before_the_header_is_passed_over() {
+        let pack = RustPack;
+        let file = format!("//! code_passenger:end\nfn f() {{}}\n{HEADER}");
+        assert_eq!(pack.existing_header(&file), Some(HEADER));
+
+        let new = "//! code_passenger:begin\n//! code_passenger:end hash=def\n";
+        assert_eq!(pack.apply_header(&file, new), format!("//! code_passenger:end\nfn f() {{}}\n{new}"));
+        // only an end marker: nothing to replace, the header goes in at the top
+        assert_eq!(pack.apply_header("//! code_passenger:end\n", new), format!("{new}//! code_passenger:end\n"));
+    }
+
+    #[test]
+    fn headers_are_replaced_in_place_or_go_after_inner_attributes() {
+        let pack = RustPack;
+        let new = "//! code_passenger:begin\n//! code_passenger:end hash=def\n";
+        assert_eq!(pack.apply_header(&format!("// c\n{HEADER}fn f() {{}}\n"), new), format!("// c\n{new}fn f() {{}}\n"));
+        assert_eq!(pack.apply_header("#![allow(x)]\nfn f() {}\n", new), format!("#![allow(x)]\n{new}fn f() {{}}\n"));
+    }
+
+    #[test]
+    fn plan_directives_are_standalone_plain_comments() {
+        let pack = RustPack;
+        assert_eq!(pack.plan_directive("// code_passenger:plan=compact\nfn f() {}\n"), Some("compact"));
+        assert_eq!(pack.plan_directive("mod a;\n  // code_passenger:plan=my-plan_2  \n"), Some("my-plan_2"));
+
+        // docs describing the directive, trailing comments and odd names don't pick a plan
+        assert_eq!(pack.plan_directive("/// `// code_passenger:plan=<name>` picks the plan\nfn f() {}\n"), None);
+        assert_eq!(pack.plan_directive("//! code_passenger:plan=compact\n"), None);
+        assert_eq!(pack.plan_directive("fn f() {} // code_passenger:plan=compact\n"), None);
+        assert_eq!(pack.plan_directive("// code_passenger:plan=compact please\n"), None);
+        assert_eq!(pack.plan_directive("// code_passenger:plan=\n// code_passenger:plan=../x\n"), None);
+    }
+
+    #[test]
+    fn change_reasons_tell_gate_edits_from_other_body_edits() {
+        let pack = RustPack;
+        let reasons = |a: &str, b: &str| pack.change_reasons(a, b);
+        let gated = "#[cfg(feature = \"net\")] // code_passenger:gate\nfn f() {}\n";
+        assert_eq!(reasons("fn f() {}\n", gated), [ChangeReason::GatesChanged]);
+        assert_eq!(reasons("#[cfg(unix)]\nfn f() {}\n", "#[cfg(windows)]\nfn f() {}\n"), [ChangeReason::GatesChanged]);
+
+        // mentioning cfg isn't enough
+        assert_eq!(reasons("fn cfg() {}\n", "fn cfg2() {}\n"), [ChangeReason::Other]);
+        assert_eq!(reasons("fn f() {}\n", "// see cfg(unix)\nfn f() {}\n"), [ChangeReason::Other]);
+    }
+
+    #[test]
+    fn change_reasons_read_the_header_parts() {
+        let pack = RustPack;
+        let header = |gate: &str, note: &str, stamp: &str| {
+            format!("//! code_passenger:begin\n{gate}//! {note}\n//! code_passenger:end{stamp}\nfn f() {{}}\n")
+        };
+        let old = header("#![cfg(feature = \"a\")]\n", "deps:[x]", " hash=1");
+        assert_eq!(pack.change_reasons("fn f() {}\n", &old), [ChangeReason::HeaderMissing]);
+        assert_eq!(pack.change_reasons(&old, &header("", "deps:[x]", " hash=1")), [ChangeReason::GatesChanged]);
+        assert_eq!(pack.change_reasons(&old, &header("#![cfg(feature = \"a\")]\n", "deps:[y]", " hash=2")), [ChangeReason::NotesChanged]);
+        assert_eq!(pack.change_reasons(&old, &header("#![cfg(feature = \"a\")]\n", "deps:[x]", " hash=2")), [ChangeReason::StampChanged]);
+        assert!(pack.change_reasons(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn strip_undoes_annotate_exactly() {
+        let pack = RustPack;
+        let originals = [
+            "fn f() {}\n",
+            "\n\n//! Crate docs\n\npub mod a;\n",
+            "#![allow(dead_code)]\n\n\nfn a() {}\n",
+            "// licence\n\nfn f() {}",
+            "",
+        ];
+        for original in originals {
+            let annotated = pack.apply_header(original, HEADER);
+            assert_ne!(annotated, original);
+            assert_eq!(pack.strip(&annotated, false).as_deref(), Some(original), "{original:?}");
+        }
+        assert_eq!(pack.strip("fn f() {}\n", true), None);
+    }
+
+    #[test]
+    fn strip_with_gates_also_drops_item_gates() {
+        use crate::model::{FeatureNote, UseSite};
+
+        let pack = RustPack;
+        let original = "\nfn plain() {}\n\nfn b() {\n    tokio::spawn(async {});\n}\n";
+        let report = FileReport {
+            notes: vec![FeatureNote { feature_name: "net".into(), deps: vec!["tokio".into()], corpus: true, ..Default::default() }],
+            corpus_features: vec!["net".into()],
+            external_use_sites: vec![UseSite { dep: "tokio".into(), line: 5, ..Default::default() }],
+            ..Default::default()
+        };
+        let gated = pack.gate_items(original, &report, GateLevel::Item).unwrap().content;
+        let annotated = pack.apply_header(&gated, HEADER);
+        assert!(annotated.contains("// code_passenger:gate\nfn b()"));
+
+        assert_eq!(pack.strip(&annotated, true).as_deref(), Some(original));
+        // without `gates` the item gates stay
+        assert_eq!(pack.strip(&annotated, false).as_deref(), Some(gated.as_str()));
+    }
+}
@@ -0,0 +1,321 @@
+//! Per-file alias table and crate-wide re-export resolution.
+//!
+//! `use serde_json as sj;` makes `sj::to_string` a `serde_json` use,
+//! `use tokio::sync::Mutex;` makes every later `Mutex` a `tokio` use, and
+//! `pub use tokio::sync as tsync;` in `lib.rs` makes `crate::tsync::Mutex` a
+//! `tokio` use from any other file.
+use std::collections::{BTreeMap, BTreeSet};
+
+use syn::visit::{self, Visit};
+
+use crate::model::ReExports;
+
+/// How many re-export hops we follow before giving up (cycles, deep chains).
+const MAX_REEXPORT_HOPS: usize = 8;
+
+/// Names `use` items bind, per inline module: local name -> path segments it
+/// stands for (`sj` -> `["serde_json"]`, `Mutex` -> `["tokio", "sync", "Mutex"]`).
+/// The file's top level is the empty module path.
+#[derive(Debug, Clone, Default)]
+pub struct AliasTable {
+    scopes: BTreeMap<Vec<String>, BTreeMap<String, Vec<String>>>,
+}
+
+impl AliasTable {
+    /// Collect every name a `use` item in the file binds (any depth), renamed or
+    /// not, under the inline module it sits in. Bare crate imports (`use tokio;`)
+    /// bind nothing new and are skipped.
+    pub fn from_file(file: &syn::File) -> Self {
+        let mut uses = UseItems::default();
+        uses.visit_file(file);
+
+        let mut scopes: BTreeMap<Vec<String>, BTreeMap<String, Vec<String>>> = BTreeMap::new();
+        for (mods, item) in &uses.items {
+            for b in bindings(&item.tree) {
+                if !b.renamed && b.target.len() == 1 {
+                    continue;
+                }
+                scopes.entry(mods.clone()).or_default().insert(b.local, b.target);
+            }
+        }
+        Self { scopes }
+    }
+
+    /// What `local` stands for inside inline module `mods`: the innermost module
+    /// binding it wins (`use super::*` makes outer imports visible in practice).
+    fn lookup(&self, mods: &[String], local: &str) -> Option<&Vec<String>> {
+        (0..=mods.len()).rev().find_map(|n| self.scopes.get(&mods[..n])?.get(local))
+    }
+
+    pub fn contains(&self, mods: &[String], local: &str) -> bool {
+        self.lookup(mods, local).is_some()
+    }
+
+    /// Rewrite a leading imported/aliased segment into the path it stands for.
+    pub fn resolve(&self, mods: &[String], segs: &[String]) -> Option<Vec<String>> {
+        let target = self.lookup(mods, segs.first()?)?;
+        let mut out = target.clone();
+        out.extend(segs[1..].iter().cloned());
+        Some(out)
+    }
+}
+
+/// One name a `use` tree brings into scope.
+struct Binding {
+    local: String,
+    target: Vec<String>,
+    renamed: bool,
+}
+
+fn bindings(tree: &syn::UseTree) -> Vec<Binding> {
+    fn walk(tree: &syn::UseTree, prefix: &mut Vec<String>, out: &mut Vec<Binding>) {
+        match tree {
+            syn::UseTree::Path(p) => {
+                prefix.push(p.ident.to_string());
+                walk(&p.tree, prefix, out);
+                prefix.pop();
+            }
+            syn::UseTree::Name(n) => {
+                let name = n.ident.to_string();
+                // `use a::b::{self}` binds `b`
+                let (local, target) = if name == "self" {
+                    (prefix.last().cloned().unwrap_or_default(), prefix.clone())
+                } else {
+                    let mut t = prefix.clone();
+                    t.push(name.clone());
+                    (name, t)
+                };
+                if !local.is_empty() {
+                    out.push(Binding { local, target, renamed: false });
+                }
+            }
+            syn::UseTree::Rename(r) => {
+                let mut target = prefix.clone();
+                if r.ident != "self" {
+                    target.push(r.ident.to_string());
+                }
+                out.push(Binding { local: r.rename.to_string(), target, renamed: true });
+            }
+            syn::UseTree::Glob(_) => {}
+            syn::UseTree::Group(g) => {
+                for item in &g.items {
+                    walk(item, prefix, out);
+                }
+            }
+        }
+    }
+
+    let mut out = vec![];
+    walk(tree, &mut vec![], &mut out);
+    out
+}
+
+/// Every `use` item in a file, with the inline `mod` path it sits in.
+#[derive(Default)]
+struct UseItems<'ast> {
+    mods: Vec<String>,
+    items: Vec<(Vec<String>, &'ast syn::ItemUse)>,
+}
+
+impl<'ast> Visit<'ast> for UseItems<'ast> {
+    fn visit_item_mod(&mut self, i: &'ast syn::ItemMod) {
+        self.mods.push(i.ident.to_string());
+        visit::visit_item_mod(self, i);
+        self.mods.pop();
+    }
+
+    fn visit_item_use(&mut self, i: &'ast syn::ItemUse) {
+        self.items.push((self.mods.clone(), i));
+    }
+}
+
+/// Turn `self::`/`super::`/`<crate>::` paths into `crate::...` paths, given the
+/// module the path is written in. `None` if the path isn't crate-internal.
+pub fn absolutize(segs: &[String], module_path: &str, crate_id: &str) -> Option<Vec<String>> {
+    let mut base: Vec<String> = module_path.split("::").map(|s| s.to_string()).collect();
+    let mut rest = segs;
+
+    match rest.first()?.as_str() {
+        "crate" => {
+            base = vec!["crate".to_string()];
+            rest = &rest[1..];
+        }
+        s if s == crate_id => {
+            base = vec!["crate".to_string()];
+            rest = &rest[1..];
+        }
+        "self" => rest = &rest[1..],
+        "super" => {
+            while rest.first().is_some_and(|s| s == "super") {
+                if base.len() > 1 {
+                    base.pop();
+                }
+                rest = &rest[1..];
+            }
+        }
+        _ => return None,
+    }
+
+    base.extend(rest.iter().cloned());
+    Some(base)
+}
+
+/// Follow re-exports by longest matching prefix until the path leaves the crate
+/// (or stops matching).
+pub fn follow_reexports(abs: &[String], reexports: &ReExports) -> Option<Vec<String>> {
+    let mut cur = abs.to_vec();
+    let mut changed = false;
+
+    for _ in 0..MAX_REEXPORT_HOPS {
+        if cur.first().map(|s| s.as_str()) != Some("crate") {
+            break;
+        }
+        let hit = (1..=cur.len()).rev().find_map(|n| {
+            reexports.get(&cur[..n].join("::")).map(|t| (n, t))
+        });
+        let Some((n, target)) = hit else { break };
+
+        let mut next: Vec<String> = target.split("::").map(|s| s.to_string()).collect();
+        next.extend(cur[n..].iter().cloned());
+        if next == cur {
+            break;
+        }
+        cur = next;
+        changed = true;
+    }
+
+    changed.then_some(cur)
+}
+
+/// Collect `pub use` re-exports declared in a file living at `module_path`.
+/// Targets go through the file's aliases and are made absolute where they point
+/// inside the crate, so the table can be chased across files.
+pub fn collect_reexports(
+    file: &syn::File,
+    module_path: &str,
+    deps: &BTreeSet<String>,
+    crate_id: &str,
+) -> ReExports {
+    let aliases = AliasTable::from_file(file);
+    let mut uses = UseItems::default();
+    uses.visit_file(file);
+
+    let mut out = ReExports::new();
+    for (mods, item) in &uses.items {
+        if matches!(item.vis, syn::Visibility::Inherited) {
+            continue;
+        }
+
+        let mut here = module_path.to_string();
+        for m in mods {
+            here = format!("{here}::{m}");
+        }
+
+        for b in bindings(&item.tree) {
+            let target = aliases.resolve(mods, &b.target).unwrap_or(b.target);
+            let Some(first) = target.first() else { continue };
+
+            let target = if deps.contains(first) {
+                target
+            } else if let Some(abs) = absolutize(&target, &here, crate_id) {
+                abs
+            } else if matches!(first.as_str(), "std" | "core" | "alloc") {
+                continue;
+            } else {
+                // 2018-style relative path to a child item
+                let mut abs: Vec<String> = here.split("::").map(|s| s.to_string()).collect();
+                abs.extend(target);
+                abs
+            };
+
+            out.insert(format!("{here}::{}", b.local), target.join("::"));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segs(path: &str) -> Vec<String> {
+        path.split("::").map(str::to_string).collect()
+    }
+
+    fn table(src: &str) -> AliasTable {
+        AliasTable::from_file(&syn::parse_file(src).unwrap())
+    }
+
+    #[test]
+    fn renames_and_imports_resolve() {
+        let t = table("use serde_json as sj;\nuse tokio::sync::{Mutex, mpsc::{self, Sender as Tx}};\nuse tokio;");
+        assert_eq!(t.resolve(&[], &segs("sj::to_string")), Some(segs("serde_json::to_string")));
+        assert_eq!(t.resolve(&[], &segs("Mutex::new")), Some(segs("tokio::sync::Mutex::new")));
+        assert_eq!(t.resolve(&[], &segs("mpsc::channel")), Some(segs("tokio::sync::mpsc::channel")));
+        assert_eq!(t.resolve(&[], &segs("Tx")), Some(segs("tokio::sync::mpsc::Sender")));
+        // a bare crate import binds nothing new
+        assert!(!t.contains(&[], "tokio"));
+    }
+
+    #[test]
+    fn inline_module_imports_stay_in_their_module() {
+        let t = table(
+            "use std::sync::Mutex;\n\
+             mod rt { use tokio::sync::Mutex; }\n\
+             mod tests { use super::*; use parking_lot::RwLock; }",
+        );
+        assert_eq!(t.resolve(&[], &segs("Mutex")), Some(segs("std::sync::Mutex")));
+        assert_eq!(t.resolve(&segs("rt"), &segs("Mutex")), Some(segs("tokio::sync::Mutex")));
+        // outer imports are visible further in, inner ones not further out
+        assert_eq!(t.resolve(&segs("tests"), &segs("Mutex")), Some(segs("std::sync::Mutex")));
+        assert!(t.contains(&segs("tests"), "RwLock"));
+        assert!(!t.contains(&[], "RwLock"));
+        assert!(!t.contains(&segs("rt"), "RwLock"));
+    }
+
+    #[test]
+    fn absolutize_relative_roots() {
+        assert_eq!(absolutize(&segs("self::x"), "crate::net", "me"), Some(segs("crate::net::x")));
+        assert_eq!(absolutize(&segs("super::super::x"), "crate::net::http", "me"), Some(segs("crate::x")));
+        assert_eq!(absolutize(&segs("me::a::b"), "crate::net", "me"), Some(segs("crate::a::b")));
+        assert_eq!(absolutize(&segs("tokio::spawn"), "crate", "me"), None);
+    }
+
+    #[test]
+    fn reexports_are_chased_out_of_the_crate() {
+        let mut re = ReExports::new();
+        re.insert("crate::rt".to_string(), "crate::inner::rt".to_string());
+        re.insert("crate::inner::rt".to_string(), "tokio::runtime".to_string());
+        assert_eq!(follow_reexports(&segs("crate::rt::Handle"), &re), Some(segs("tokio::runtime::Handle")));
+        assert_eq!(follow_reexports(&segs("crate::other"), &re), None);
+
+        // a cycle stops after the hop limit instead of looping
+        let mut cyc = ReExports::new();
+        cyc.insert("crate::a".to_string(), "crate::b".to_string());
+        cyc.insert("crate::b".to_string(), "crate::a".to_string());
+        assert!(follow_reexports(&segs("crate::a"), &cyc).is_some());
+    }
+
+    #[test]
+    fn collects_pub_uses_with_absolute_targets() {
+        let file = syn::parse_file(
+            "pub use tokio::sync as tsync;\n\
+             use serde_json as sj;\n\
+             pub use sj::Value;\n\
+             pub use self::inner::Thing;\n\
+             pub use std::fmt;\n\
+             use tokio::task;\n\
+             pub mod net { pub use super::inner::Conn; }",
+        )
+        .unwrap();
+        let deps: BTreeSet<String> = ["tokio", "serde_json"].map(str::to_string).into();
+        let re = collect_reexports(&file, "crate", &deps, "me");
+
+        assert_eq!(re.get("crate::tsync").map(String::as_str), Some("tokio::sync"));
+        assert_eq!(re.get("crate::Value").map(String::as_str), Some("serde_json::Value"));
+        assert_eq!(re.get("crate::Thing").map(String::as_str), Some("crate::inner::Thing"));
+        assert_eq!(re.get("crate::net::Conn").map(String::as_str), Some("crate::inner::Conn"));
+        assert!(!re.contains_key("crate::fmt"));
+        assert!(!re.contains_key("crate::task"));
+    }
+}
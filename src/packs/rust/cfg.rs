@@ -0,0 +1,100 @@
+//! `cfg` predicate parsing: `cfg(...)`, `cfg_attr(pred, ...)` and `cfg!(...)`
+//! bodies into [`CfgExpr`].
+use proc_macro2::TokenStream;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::Token;
+
+use crate::model::CfgExpr;
+
+/// Parse the inside of `cfg(...)` / `cfg!(...)`.
+pub fn parse_cfg(tokens: TokenStream) -> Option<CfgExpr> {
+    let metas = Punctuated::<syn::Meta, Token![,]>::parse_terminated.parse2(tokens).ok()?;
+    let mut it = metas.iter();
+    let expr = from_meta(it.next()?)?;
+    it.next().is_none().then_some(expr)
+}
+
+/// Predicate of `cfg_attr(pred, attr...)` plus the attributes it applies.
+pub fn parse_cfg_attr(tokens: TokenStream) -> Option<(CfgExpr, Vec<syn::Meta>)> {
+    let metas = Punctuated::<syn::Meta, Token![,]>::parse_terminated.parse2(tokens).ok()?;
+    let mut it = metas.into_iter();
+    let pred = from_meta(&it.next()?)?;
+    Some((pred, it.collect()))
+}
+
+/// `#[cfg(...)]` predicates among `attrs`.
+pub fn cfg_attrs(attrs: &[syn::Attribute]) -> Vec<CfgExpr> {
+    attrs
+        .iter()
+        .filter_map(|a| match &a.meta {
+            syn::Meta::List(l) if l.path.is_ident("cfg") => parse_cfg(l.tokens.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn from_meta(meta: &syn::Meta) -> Option<CfgExpr> {
+    match meta {
+        syn::Meta::Path(p) => Some(CfgExpr::Name(p.get_ident()?.to_string())),
+        syn::Meta::NameValue(nv) => {
+            let key = nv.path.get_ident()?.to_string();
+            match &nv.value {
+                syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(s), .. }) => {
+                    Some(CfgExpr::KeyValue { key, value: s.value() })
+                }
+                _ => None,
+            }
+        }
+        syn::Meta::List(l) => {
+            let args = l.parse_args_with(Punctuated::<syn::Meta, Token![,]>::parse_terminated).ok()?;
+            let args: Vec<CfgExpr> = args.iter().map(from_meta).collect::<Option<_>>()?;
+            match l.path.get_ident()?.to_string().as_str() {
+                "all" => Some(CfgExpr::All(args)),
+                "any" => Some(CfgExpr::Any(args)),
+                "not" => {
+                    let [inner] = <[CfgExpr; 1]>::try_from(args).ok()?;
+                    Some(CfgExpr::Not(Box::new(inner)))
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<CfgExpr> {
+        parse_cfg(s.parse().unwrap())
+    }
+
+    #[test]
+    fn predicates_nest() {
+        let e = parse("all(unix, any(feature = \"a\", not(target_os = \"macos\")))").unwrap();
+        assert_eq!(e.to_string(), "all(unix, any(feature = \"a\", not(target_os = \"macos\")))");
+        assert_eq!(parse("test"), Some(CfgExpr::Name("test".to_string())));
+    }
+
+    #[test]
+    fn malformed_predicates_are_rejected() {
+        for bad in ["", "unix, windows", "not(unix, windows)", "feature = 1", "maybe(unix)", "a::b"] {
+            assert_eq!(parse(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn cfg_attr_splits_predicate_and_attrs() {
+        let (pred, attrs) = parse_cfg_attr("feature = \"serde\", derive(Serialize), serde(default)".parse().unwrap()).unwrap();
+        assert_eq!(pred, CfgExpr::feature("serde"));
+        assert_eq!(attrs.len(), 2);
+        assert!(attrs[0].path().is_ident("derive"));
+    }
+
+    #[test]
+    fn only_cfg_attrs_are_collected() {
+        let item: syn::ItemFn = syn::parse_str("#[cfg(unix)] #[inline] #[cfg(feature = \"x\")] fn f() {}").unwrap();
+        assert_eq!(cfg_attrs(&item.attrs), [CfgExpr::Name("unix".to_string()), CfgExpr::feature("x")]);
+    }
+}
@@ -0,0 +1,75 @@
+//! Built-in derive / helper attribute -> crate table.
+//!
+//! Covers derives that are normally written bare after a prelude or glob import,
+//! so `serde` doesn't look unused in a file that is all `#[derive(Serialize)]`.
+//! Helper attributes (`#[error]`, `#[arg]`, ...) only count for their crate on
+//! items that also carry one of that crate's derives.
+//! Users layer their own entries on top via `[derives]` / `[helper_attrs]` in
+//! `code_passenger.toml`.
+use crate::model::AttrCrates;
+
+const DERIVES: &[(&str, &str)] = &[
+    ("Serialize", "serde"),
+    ("Deserialize", "serde"),
+    ("Error", "thiserror"),
+    ("Parser", "clap"),
+    ("Subcommand", "clap"),
+    ("Args", "clap"),
+    ("ValueEnum", "clap"),
+    ("Zeroize", "zeroize"),
+    ("ZeroizeOnDrop", "zeroize"),
+    ("EnumString", "strum"),
+    ("EnumIter", "strum"),
+    ("IntoStaticStr", "strum"),
+    ("AsRefStr", "strum"),
+    ("Builder", "derive_builder"),
+];
+
+const HELPER_ATTRS: &[(&str, &str)] = &[
+    ("serde", "serde"),
+    ("error", "thiserror"),
+    ("from", "thiserror"),
+    ("source", "thiserror"),
+    ("backtrace", "thiserror"),
+    ("command", "clap"),
+    ("arg", "clap"),
+    ("clap", "clap"),
+    ("group", "clap"),
+    ("value", "clap"),
+    ("zeroize", "zeroize"),
+    ("strum", "strum"),
+    ("builder", "derive_builder"),
+];
+
+pub fn builtin_attr_crates() -> AttrCrates {
+    AttrCrates {
+        derives: DERIVES.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        helper_attrs: HELPER_ATTRS.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_helper_crate_has_a_derive() {
+        // helper attrs only count when one of their crate's derives is on the item
+        let t = builtin_attr_crates();
+        for (attr, krate) in &t.helper_attrs {
+            assert!(t.derives.values().any(|d| d == krate), "#[{attr}] maps to {krate}, which has no derive");
+        }
+    }
+
+    #[test]
+    fn user_entries_layer_over_builtins() {
+        let user = AttrCrates {
+            derives: [("Error".to_string(), "snafu".to_string())].into(),
+            helper_attrs: [("snafu".to_string(), "snafu".to_string())].into(),
+        };
+        let t = builtin_attr_crates().layered(&user);
+        assert_eq!(t.derives["Error"], "snafu");
+        assert_eq!(t.derives["Serialize"], "serde");
+        assert_eq!(t.helper_attrs["snafu"], "snafu");
+    }
+}
@@ -1,9 +1,9 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use crate::model::UsedSymbols;
 use crate::packs::rust::RustPack;
 use crate::packs::rust::helpers::norm_ident;
-use regex::Regex;
+use regex::{Regex, RegexSet};
 
 
 /// Helper used by core (since usage depends on optional deps list).
@@ -44,10 +44,40 @@ pub fn detect_usage_with_deps(
         }
     }
 
+    // `use serde_json as sj;` ... `sj::to_string(..)`
+    let aliases: Vec<(String, String)> = detect_aliases(content, deps).into_iter().collect();
+    let pats = aliases
+        .iter()
+        .map(|(alias, _)| format!(r"(?m)(?:^|[^A-Za-z0-9_:]){}\s*::", regex::escape(alias)));
+    let set = RegexSet::new(pats).expect("valid alias regex set");
+    for i in set.matches(content).into_iter() {
+        let target = &aliases[i].1;
+        let dep = target.split("::").next().unwrap_or(target).to_string();
+        used.packages.insert(dep);
+    }
+
     used.modules = detect_internal_paths(content, crate_name);
     used
 }
 
+/// Collect `use <dep>::... as <alias>;` renames: alias -> target path.
+/// Only plain (non-grouped) use statements; the syntax front end handles the rest.
+pub fn detect_aliases(content: &str, deps: &BTreeSet<String>) -> BTreeMap<String, String> {
+    let re = Regex::new(
+        r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?use\s+(?:::)?\s*([A-Za-z_][A-Za-z0-9_]*(?:\s*::\s*[A-Za-z_][A-Za-z0-9_]*)*)\s+as\s+([A-Za-z_][A-Za-z0-9_]*)\s*;"
+    ).expect("valid alias regex");
+
+    let mut out = BTreeMap::new();
+    for cap in re.captures_iter(content) {
+        let target: String = cap[1].split_whitespace().collect();
+        let dep = target.split("::").next().unwrap_or("");
+        if deps.contains(dep) {
+            out.insert(cap[2].to_string(), target);
+        }
+    }
+    out
+}
+
 /// Collect internal crate path usage like:
 /// - crate::engine::RunContext
 /// - super::foo::Bar
@@ -85,4 +115,38 @@ fn detect_internal_paths(content: &str, crate_name: &str) -> BTreeSet<String> {
     }
 
     out
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deps(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn aliases_only_for_known_deps() {
+        let src = "use serde_json as sj;\nuse std::fmt as f;\npub use regex :: Regex as Re;";
+        let got = detect_aliases(src, &deps(&["serde_json", "regex"]));
+        assert_eq!(got["sj"], "serde_json");
+        assert_eq!(got["Re"], "regex::Regex");
+        assert!(!got.contains_key("f"));
+    }
+
+    #[test]
+    fn aliased_paths_count_for_their_dep() {
+        let src = "use serde_json as sj;\nfn f() { sj::to_string(&1).ok(); }";
+        let used = detect_usage_with_deps(src, &deps(&["serde_json", "regex"]), "me");
+        assert!(used.packages.contains("serde_json"));
+        assert!(!used.packages.contains("regex"));
+    }
+
+    #[test]
+    fn internal_paths_record_two_levels() {
+        let got = detect_internal_paths("crate::packs::rust::RustPack; my_crate::model::X", "my-crate");
+        assert!(got.contains("crate::packs"));
+        assert!(got.contains("crate::packs::rust"));
+        assert!(got.contains("my_crate::model"));
+        assert!(got.contains("my_crate::model::X"));
+    }
+}
@@ -0,0 +1,627 @@
+//! Item-level gates: `#[cfg(feature = "...")]` on the items that use a gated dep
+//! instead of one `#![cfg]` over the whole file. Generated attributes end in a
+//! `// code_passenger:gate` comment so reruns can drop and recompute them. The
+//! same markers gate a gated child's `mod` declaration in its parent. Items
+//! naming a gated item of the same file are gated along with it.
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+
+use proc_macro2::{TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::spanned::Spanned;
+
+use crate::model::{CfgExpr, FileReport, GateLevel, GatedItems, ModGate};
+use crate::packs::rust::cfg::cfg_attrs;
+use crate::packs::rust::syntax::{impl_item_attrs, item_attrs};
+use crate::packs::rust::RustPack;
+
+/// Where one gate goes: before `line` (1-based), unless the item's own `#[cfg]`s
+/// already cover the features.
+struct Gate {
+    line: usize,
+    features: BTreeSet<String>,
+    existing: BTreeSet<String>,
+}
+
+/// Rewrite `content`'s item gates for `level`. `File` only drops gates a previous
+/// `Item` run left behind. `None` if the file doesn't parse.
+pub fn gate_items(content: &str, report: &FileReport, level: GateLevel) -> Option<GatedItems> {
+    if level == GateLevel::File {
+        let content = content.split_inclusive('\n').filter(|l| !is_gate_line(l)).collect();
+        return Some(GatedItems { content, file_features: report.corpus_features.clone() });
+    }
+
+    let file = syn::parse_file(content).ok()?;
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let marked = |line: usize| line >= 1 && lines.get(line - 1).is_some_and(|l| is_gate_line(l));
+
+    // dep -> the corpus features turning it on
+    let mut dep_features = BTreeMap::<&str, BTreeSet<String>>::new();
+    for n in report.notes.iter().filter(|n| report.corpus_features.contains(&n.feature_name)) {
+        for d in &n.deps {
+            dep_features.entry(d).or_default().insert(n.feature_name.clone());
+        }
+    }
+    let needs = |lines: RangeInclusive<usize>| -> BTreeSet<String> {
+        report
+            .external_use_sites
+            .iter()
+            .filter(|u| lines.contains(&u.line))
+            .filter_map(|u| dep_features.get(u.dep.as_str()))
+            .flatten()
+            .cloned()
+            .collect()
+    };
+    // first line of a spanned node, skipping our own gate above it
+    let span_lines = |span: proc_macro2::Span| {
+        let mut start = span.start().line;
+        while marked(start) {
+            start += 1;
+        }
+        start..=span.end().line
+    };
+
+    // every top-level item, plus the members of inherent impls
+    let mut units: Vec<Unit> = vec![];
+    for (idx, item) in file.items.iter().enumerate() {
+        let range = span_lines(item.span());
+        let owner = impl_owner(item);
+        units.push(Unit { item: idx, member: false, owner: owner.clone(), name: item_name(item), features: needs(range.clone()), refs: refs(item) });
+        if let syn::Item::Impl(i @ syn::ItemImpl { trait_: None, .. }) = item {
+            for ii in &i.items {
+                let r = span_lines(ii.span());
+                let (owner, name) = (owner.clone(), impl_item_name(ii));
+                units.push(Unit { item: idx, member: true, owner, name, features: needs(r), refs: refs(ii) });
+            }
+        }
+    }
+    let module: Vec<String> = report.module_path.split("::").map(str::to_string).collect();
+    propagate(&mut units, &module);
+    let gated_name = |refs: &[Ref]| {
+        units.iter().any(|d| !d.member && !d.features.is_empty() && refs.iter().any(|r| r.reaches(d, None, &module)))
+    };
+
+    let mut top_needs = vec![];
+    let mut gates = vec![];
+    for (idx, item) in file.items.iter().enumerate() {
+        let range = span_lines(item.span());
+        let top = units.iter().find(|u| u.item == idx && !u.member).expect("one unit per item");
+        let existing = positive_features(item_attrs(item), &marked);
+
+        // inherent impls: gate the methods that need it and the impl only with
+        // what every method needs, unless the impl header itself uses a dep or
+        // names a gated item
+        let mut inner = vec![];
+        let mut inner_ranges = vec![];
+        let mut header_gated = false;
+        if let syn::Item::Impl(i @ syn::ItemImpl { trait_: None, .. }) = item {
+            let members = units.iter().filter(|u| u.item == idx && u.member);
+            for (ii, u) in i.items.iter().zip(members) {
+                let r = span_lines(ii.span());
+                let existing = positive_features(impl_item_attrs(ii), &marked);
+                inner.push(Gate { line: *r.start(), features: u.features.clone(), existing });
+                inner_ranges.push(r);
+            }
+            let mut header = vec![];
+            collect_refs(i.generics.to_token_stream(), &mut header);
+            collect_refs(i.self_ty.to_token_stream(), &mut header);
+            header_gated = gated_name(&header);
+        }
+        let header_uses = report
+            .external_use_sites
+            .iter()
+            .any(|u| range.contains(&u.line) && !inner_ranges.iter().any(|r| r.contains(&u.line)));
+
+        if inner.is_empty() || header_uses || header_gated {
+            gates.push(Gate { line: *range.start(), features: top.features.clone(), existing });
+        } else {
+            let own = common(inner.iter().map(|g| &g.features));
+            for g in &mut inner {
+                g.features.retain(|f| !own.contains(f));
+            }
+            gates.extend(inner);
+            gates.push(Gate { line: *range.start(), features: own, existing });
+        }
+        top_needs.push(top.features.clone());
+    }
+
+    // features every item needs stay on the file
+    let file_features = common(top_needs.iter());
+
+    let mut inserts = BTreeMap::<usize, String>::new();
+    for g in gates {
+        let features: Vec<CfgExpr> = g
+            .features
+            .iter()
+            .filter(|f| !file_features.contains(*f) && !g.existing.contains(*f))
+            .map(|f| CfgExpr::feature(f))
+            .collect();
+        if let Some(gate) = CfgExpr::all_of(features) {
+            inserts.insert(g.line, gate_line(lines[g.line - 1], &gate));
+        }
+    }
+
+    let content = rewrite(&lines, &inserts, |l| is_gate_line(lines[l - 1]));
+    Some(GatedItems { content, file_features: file_features.into_iter().collect() })
+}
+
+/// Gate `mod foo;` declarations and `pub use foo::...` re-exports in a parent
+/// file like the child modules themselves are gated. Generated gates already on
+/// those items (from item-level gating) are merged in, not replaced. `None` if
+/// the file doesn't parse.
+pub fn gate_mod_decls(content: &str, children: &[ModGate]) -> Option<String> {
+    let file = syn::parse_file(content).ok()?;
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let marked = |line: usize| line >= 1 && lines.get(line - 1).is_some_and(|l| is_gate_line(l));
+
+    let mut targets = vec![];
+    decl_targets(&file.items, &[], children, &mut targets);
+
+    let mut inserts = BTreeMap::<usize, String>::new();
+    let mut dropped = BTreeSet::<usize>::new();
+    for (item, want) in targets {
+        let attrs = item_attrs(item);
+        let mut start = item.span().start().line;
+        while marked(start) {
+            dropped.insert(start);
+            start += 1;
+        }
+
+        // what our gate on the item already asks for, minus the user's own cfgs
+        let ours: Vec<syn::Attribute> = attrs.iter().filter(|a| marked(a.span().start().line)).cloned().collect();
+        let mut parts: Vec<CfgExpr> = cfg_attrs(&ours).iter().flat_map(|c| c.conjuncts()).cloned().collect();
+        for w in want {
+            if !parts.contains(&w) {
+                parts.push(w);
+            }
+        }
+
+        let theirs: Vec<syn::Attribute> = attrs.iter().filter(|a| !marked(a.span().start().line)).cloned().collect();
+        let theirs = cfg_attrs(&theirs);
+        let existing = positive_features(attrs, &marked);
+        parts.retain(|p| {
+            let covered_feature = matches!(p, CfgExpr::KeyValue { key, value } if key == "feature" && existing.contains(value));
+            !covered_feature && !theirs.iter().any(|t| t.conjuncts().contains(&p))
+        });
+
+        if let Some(gate) = CfgExpr::all_of(parts) {
+            inserts.insert(start, gate_line(lines[start - 1], &gate));
+        }
+    }
+
+    Some(rewrite(&lines, &inserts, |l| dropped.contains(&l)))
+}
+
+/// Items under `prefix` (an inline module path) that `children` gates apply to:
+/// the `mod` declarations and the `pub use`s reaching into them.
+fn decl_targets<'a>(items: &'a [syn::Item], prefix: &[String], children: &[ModGate], out: &mut Vec<(&'a syn::Item, Vec<CfgExpr>)>) {
+    let child = |name: &str| {
+        children
+            .iter()
+            .find(|c| c.path.len() == prefix.len() + 1 && c.path.starts_with(prefix) && c.path[prefix.len()] == name)
+    };
+
+    for item in items {
+        match item {
+            syn::Item::Mod(m) => {
+                let name = m.ident.to_string().trim_start_matches("r#").to_string();
+                match &m.content {
+                    Some((_, inner)) => {
+                        let mut p = prefix.to_vec();
+                        p.push(name);
+                        decl_targets(inner, &p, children, out);
+                    }
+                    None => {
+                        if let Some(c) = child(&name) {
+                            out.push((item, c.gate.conjuncts().into_iter().cloned().collect()));
+                        }
+                    }
+                }
+            }
+            syn::Item::Use(u) if !matches!(u.vis, syn::Visibility::Inherited) => {
+                let mut heads = vec![];
+                use_heads(&u.tree, &mut heads);
+                // a group also re-exporting ungated items can't take the gate
+                let gated: Vec<&ModGate> = heads.iter().filter_map(|h| child(h)).collect();
+                if !gated.is_empty() && gated.len() == heads.len() {
+                    let mut parts: Vec<CfgExpr> = vec![];
+                    for p in gated.iter().flat_map(|c| c.gate.conjuncts()) {
+                        if !parts.contains(p) {
+                            parts.push(p.clone());
+                        }
+                    }
+                    out.push((item, parts));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// First segment of each path in a use tree, looking through `self::`.
+fn use_heads(tree: &syn::UseTree, out: &mut Vec<String>) {
+    match tree {
+        syn::UseTree::Path(p) if p.ident == "self" => use_heads(&p.tree, out),
+        syn::UseTree::Path(p) => out.push(p.ident.to_string()),
+        syn::UseTree::Name(n) => out.push(n.ident.to_string()),
+        syn::UseTree::Rename(r) => out.push(r.ident.to_string()),
+        syn::UseTree::Group(g) => g.items.iter().for_each(|t| use_heads(t, out)),
+        syn::UseTree::Glob(_) => {}
+    }
+}
+
+/// `#[cfg(...)] // code_passenger:gate`, indented like `line`.
+fn gate_line(line: &str, gate: &CfgExpr) -> String {
+    let indent: String = line.chars().take_while(|c| c.is_whitespace() && *c != '\n').collect();
+    format!("{indent}#[cfg({gate})] // {}\n", RustPack::GATE_MARK)
+}
+
+/// `lines` with `inserts` placed before their (1-based) line and `drop`ped lines left out.
+fn rewrite(lines: &[&str], inserts: &BTreeMap<usize, String>, drop: impl Fn(usize) -> bool) -> String {
+    let mut out = String::new();
+    for (i, l) in lines.iter().enumerate() {
+        if let Some(gate) = inserts.get(&(i + 1)) {
+            out.push_str(gate);
+        }
+        if !drop(i + 1) {
+            out.push_str(l);
+        }
+    }
+    out
+}
+
+/// A top-level item or a member of an inherent impl, with the features it needs.
+struct Unit {
+    /// index into the file's items
+    item: usize,
+    /// inside an inherent impl: only reachable through `self.`, `Self::` or `Type::`
+    member: bool,
+    /// the implementing type's name, for impls and their members
+    owner: Option<String>,
+    name: Option<String>,
+    features: BTreeSet<String>,
+    refs: Vec<Ref>,
+}
+
+/// Items referring to a gated item of the same file get its features too, so
+/// the ungated rest still compiles without them. Free items count when named
+/// unqualified or through `self::`/this module's `crate::` path, members only
+/// through `self.`, `Self::` or their type, so `map.get(..)` or `Vec::new()`
+/// don't pick up the gate of a local `get` or `new`.
+fn propagate(units: &mut [Unit], module: &[String]) {
+    loop {
+        let mut changed = false;
+        for u in 0..units.len() {
+            let mut extra = BTreeSet::new();
+            for (d, def) in units.iter().enumerate() {
+                // an impl's own members are already folded into it
+                let own_member = def.member && !units[u].member && def.item == units[u].item;
+                if d == u || own_member || def.features.is_empty() {
+                    continue;
+                }
+                if units[u].refs.iter().any(|r| r.reaches(def, units[u].owner.as_deref(), module)) {
+                    extra.extend(def.features.iter().cloned());
+                }
+            }
+            let before = units[u].features.len();
+            units[u].features.extend(extra);
+            changed |= units[u].features.len() != before;
+        }
+        if !changed {
+            return;
+        }
+    }
+}
+
+/// An identifier as written, with how it's reached.
+struct Ref {
+    name: String,
+    via: Via,
+}
+
+enum Via {
+    /// `name` or `a::b::name`: the path segments before it
+    Path(Vec<String>),
+    /// `self.name`
+    SelfDot,
+    /// `expr.name` on anything but `self`
+    Dot,
+}
+
+impl Ref {
+    /// Whether this names `def`, written inside an impl for `owner` (if any)
+    /// in the file of module `module` (`["crate", "net"]`).
+    fn reaches(&self, def: &Unit, owner: Option<&str>, module: &[String]) -> bool {
+        if def.name.as_deref() != Some(self.name.as_str()) {
+            return false;
+        }
+        match (def.member, &self.via) {
+            (true, Via::SelfDot) => owner.is_some() && owner == def.owner.as_deref(),
+            (true, Via::Path(p)) => match p.as_slice() {
+                [s] if s == "Self" => owner.is_some() && owner == def.owner.as_deref(),
+                [ty] => Some(ty.as_str()) == def.owner.as_deref(),
+                _ => false,
+            },
+            (false, Via::Path(p)) => p.is_empty() || *p == ["self"] || p == module,
+            _ => false,
+        }
+    }
+}
+
+fn refs(node: &impl ToTokens) -> Vec<Ref> {
+    let mut out = vec![];
+    collect_refs(node.to_token_stream(), &mut out);
+    out
+}
+
+fn collect_refs(tokens: TokenStream, out: &mut Vec<Ref>) {
+    let toks: Vec<TokenTree> = tokens.into_iter().collect();
+    let punct = |i: Option<usize>, c: char| matches!(i.map(|i| &toks[i]), Some(TokenTree::Punct(p)) if p.as_char() == c);
+    let ident = |i: Option<usize>| match i.map(|i| &toks[i]) {
+        Some(TokenTree::Ident(id)) => Some(id.to_string()),
+        _ => None,
+    };
+
+    for (i, t) in toks.iter().enumerate() {
+        match t {
+            TokenTree::Group(g) => collect_refs(g.stream(), out),
+            TokenTree::Ident(id) => {
+                let back = |n: usize| i.checked_sub(n);
+                // `a.b`, but not the `..` of a range
+                let via = if punct(back(1), '.') && !punct(back(2), '.') {
+                    match ident(back(2)).as_deref() {
+                        Some("self") => Via::SelfDot,
+                        _ => Via::Dot,
+                    }
+                } else {
+                    let mut segs = vec![];
+                    let mut at = i;
+                    while punct(at.checked_sub(1), ':') && punct(at.checked_sub(2), ':') {
+                        let Some(seg) = ident(at.checked_sub(3)) else { break };
+                        segs.push(seg);
+                        at -= 3;
+                    }
+                    segs.reverse();
+                    Via::Path(segs)
+                };
+                out.push(Ref { name: id.to_string(), via });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Name of the type an impl is for (`Foo` in `impl<T> Trait for a::Foo<T>`).
+fn impl_owner(item: &syn::Item) -> Option<String> {
+    let syn::Item::Impl(i) = item else { return None };
+    match &*i.self_ty {
+        syn::Type::Path(p) => p.path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    }
+}
+
+/// Name other items can refer to this one by.
+fn item_name(item: &syn::Item) -> Option<String> {
+    let ident = match item {
+        syn::Item::Const(x) => &x.ident,
+        syn::Item::Enum(x) => &x.ident,
+        syn::Item::Fn(x) => &x.sig.ident,
+        syn::Item::Macro(x) => x.ident.as_ref()?,
+        syn::Item::Mod(x) => &x.ident,
+        syn::Item::Static(x) => &x.ident,
+        syn::Item::Struct(x) => &x.ident,
+        syn::Item::Trait(x) => &x.ident,
+        syn::Item::TraitAlias(x) => &x.ident,
+        syn::Item::Type(x) => &x.ident,
+        syn::Item::Union(x) => &x.ident,
+        _ => return None,
+    };
+    Some(ident.to_string())
+}
+
+fn impl_item_name(item: &syn::ImplItem) -> Option<String> {
+    match item {
+        syn::ImplItem::Const(x) => Some(x.ident.to_string()),
+        syn::ImplItem::Fn(x) => Some(x.sig.ident.to_string()),
+        _ => None,
+    }
+}
+
+pub(crate) fn is_gate_line(line: &str) -> bool {
+    let t = line.trim();
+    t.starts_with("#[cfg(") && t.ends_with(&format!("// {}", RustPack::GATE_MARK))
+}
+
+/// Features the hand-written `#[cfg]`s in `attrs` already require (`marked`
+/// tells our own gate lines apart).
+fn positive_features(attrs: &[syn::Attribute], marked: &dyn Fn(usize) -> bool) -> BTreeSet<String> {
+    let own: Vec<syn::Attribute> = attrs.iter().filter(|a| !marked(a.span().start().line)).cloned().collect();
+    cfg_attrs(&own).iter().flat_map(|c| c.positive_features()).collect()
+}
+
+/// Intersection of `sets`; empty when there are none.
+fn common<'a>(mut sets: impl Iterator<Item = &'a BTreeSet<String>>) -> BTreeSet<String> {
+    let Some(first) = sets.next() else { return BTreeSet::new() };
+    sets.fold(first.clone(), |acc, s| acc.intersection(s).cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{FeatureNote, UseSite};
+
+    /// `features`: (feature, dep) pairs, all in the corpus; `uses`: (dep, line)
+    fn report(features: &[(&str, &str)], uses: &[(&str, usize)]) -> FileReport {
+        FileReport {
+            notes: features
+                .iter()
+                .map(|(f, d)| FeatureNote { feature_name: f.to_string(), deps: vec![d.to_string()], corpus: true, ..Default::default() })
+                .collect(),
+            corpus_features: features.iter().map(|(f, _)| f.to_string()).collect(),
+            external_use_sites: uses.iter().map(|(d, l)| UseSite { dep: d.to_string(), line: *l, ..Default::default() }).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn gate(content: &str, report: &FileReport) -> GatedItems {
+        gate_items(content, report, GateLevel::Item).expect("parses")
+    }
+
+    #[test]
+    fn gates_land_on_the_items_using_the_dep() {
+        let src = "fn plain() {}\n\nfn fetch() {\n    tokio::spawn(async {});\n}\n";
+        let r = report(&[("net", "tokio")], &[("tokio", 4)]);
+        let out = gate(src, &r);
+        assert_eq!(
+            out.content,
+            "fn plain() {}\n\n#[cfg(feature = \"net\")] // code_passenger:gate\nfn fetch() {\n    tokio::spawn(async {});\n}\n"
+        );
+        assert!(out.file_features.is_empty());
+
+        // a rerun on the gated file (use sites shifted by the gate line) is stable
+        let again = gate(&out.content, &report(&[("net", "tokio")], &[("tokio", 5)]));
+        assert_eq!(again.content, out.content);
+
+        // file level takes them back out
+        let file = gate_items(&out.content, &r, GateLevel::File).unwrap();
+        assert_eq!(file.content, src);
+        assert_eq!(file.file_features, ["net"]);
+    }
+
+    #[test]
+    fn features_every_item_needs_stay_on_the_file() {
+        let src = "fn a() { tokio::spawn(async {}); }\nfn b() { tokio::spawn(async {}); }\n";
+        let out = gate(src, &report(&[("net", "tokio")], &[("tokio", 1), ("tokio", 2)]));
+        assert_eq!(out.content, src);
+        assert_eq!(out.file_features, ["net"]);
+    }
+
+    #[test]
+    fn inherent_impls_gate_their_methods() {
+        let src = "struct C;\nimpl C {\n    fn get(&self) { reqwest::get(\"x\"); }\n    fn name(&self) {}\n}\n";
+        let out = gate(src, &report(&[("http", "reqwest")], &[("reqwest", 3)]));
+        assert_eq!(
+            out.content,
+            "struct C;\nimpl C {\n    #[cfg(feature = \"http\")] // code_passenger:gate\n    fn get(&self) { reqwest::get(\"x\"); }\n    fn name(&self) {}\n}\n"
+        );
+    }
+
+    #[test]
+    fn items_naming_gated_items_are_gated_too() {
+        let src = "\
+fn connect() { tokio::spawn(async {}); }
+fn run() { connect(); }
+fn idle() {}
+struct Conn { s: tokio::net::TcpStream }
+impl Conn {
+    fn close(&self) {}
+}
+";
+        let out = gate(src, &report(&[("net", "tokio")], &[("tokio", 1), ("tokio", 4)]));
+        let gated: Vec<&str> = out
+            .content
+            .lines()
+            .collect::<Vec<_>>()
+            .windows(2)
+            .filter(|w| is_gate_line(w[0]))
+            .map(|w| w[1])
+            .collect();
+        // `run` calls `connect`; `impl Conn` names the gated struct in its header
+        assert_eq!(gated, ["fn connect() { tokio::spawn(async {}); }", "fn run() { connect(); }", "struct Conn { s: tokio::net::TcpStream }", "impl Conn {"]);
+        assert!(out.file_features.is_empty());
+    }
+
+    #[test]
+    fn methods_calling_gated_methods_are_gated_too() {
+        let src = "\
+struct C;
+impl C {
+    fn fetch(&self) { reqwest::get(\"x\"); }
+    fn get(&self) { self.fetch() }
+    fn fetch_count(&self) -> usize { 0 }
+}
+fn call(c: &C) { C::get(c); }
+";
+        let out = gate(src, &report(&[("http", "reqwest")], &[("reqwest", 3)]));
+        let gates = out.content.lines().filter(|l| is_gate_line(l)).count();
+        assert_eq!(gates, 3, "{}", out.content);
+        assert!(out.content.contains("// code_passenger:gate\n    fn get(&self)"));
+        assert!(out.content.contains("// code_passenger:gate\nfn call("));
+        assert!(out.content.contains("}\n    fn fetch_count"));
+    }
+
+    #[test]
+    fn same_named_calls_on_other_types_stay_ungated() {
+        let src = "\
+use std::collections::HashMap;
+struct Store;
+impl Store {
+    fn get(&self) { reqwest::get(\"x\"); }
+}
+fn new() { reqwest::get(\"y\"); }
+fn lookup(map: &HashMap<u8, u8>) -> Option<&u8> { map.get(&1) }
+fn fresh() -> Vec<u8> { Vec::new() }
+fn via_type(s: &Store) { Store::get(s) }
+fn via_path() { self::new(); crate::net::new(); }
+";
+        let mut r = report(&[("http", "reqwest")], &[("reqwest", 4), ("reqwest", 6)]);
+        r.module_path = "crate::net".to_string();
+        let out = gate(src, &r);
+        let gated: Vec<&str> = out
+            .content
+            .lines()
+            .collect::<Vec<_>>()
+            .windows(2)
+            .filter(|w| is_gate_line(w[0]))
+            .map(|w| w[1].trim())
+            .collect();
+        assert_eq!(
+            gated,
+            [
+                // its only method needs it, so the whole impl takes the gate
+                "impl Store {",
+                "fn new() { reqwest::get(\"y\"); }",
+                "fn via_type(s: &Store) { Store::get(s) }",
+                "fn via_path() { self::new(); crate::net::new(); }",
+            ]
+        );
+    }
+
+    fn child(path: &[&str], gate: CfgExpr) -> ModGate {
+        ModGate { path: path.iter().map(|p| p.to_string()).collect(), gate }
+    }
+
+    #[test]
+    fn mod_decls_and_their_reexports_take_the_child_gate() {
+        let src = "mod plain;\npub mod net;\npub use net::Client;\npub use self::{net::Server, plain::Thing};\nmod inline {\n    mod net;\n}\n";
+        let out = gate_mod_decls(src, &[child(&["net"], CfgExpr::feature("net")), child(&["inline", "net"], CfgExpr::Name("unix".into()))]).unwrap();
+        assert_eq!(
+            out,
+            "mod plain;\n\
+             #[cfg(feature = \"net\")] // code_passenger:gate\npub mod net;\n\
+             #[cfg(feature = \"net\")] // code_passenger:gate\npub use net::Client;\n\
+             pub use self::{net::Server, plain::Thing};\n\
+             mod inline {\n    #[cfg(unix)] // code_passenger:gate\n    mod net;\n}\n"
+        );
+        // rerunning recomputes the same gates instead of stacking them
+        assert_eq!(gate_mod_decls(&out, &[child(&["net"], CfgExpr::feature("net")), child(&["inline", "net"], CfgExpr::Name("unix".into()))]).unwrap(), out);
+    }
+
+    #[test]
+    fn hand_written_cfgs_are_respected_and_item_gates_merged() {
+        let gate = CfgExpr::All(vec![CfgExpr::feature("net"), CfgExpr::Name("unix".into())]);
+        // the user already gates on `net`; only `unix` is left
+        let src = "#[cfg(feature = \"net\")]\nmod net;\n";
+        assert_eq!(
+            gate_mod_decls(src, &[child(&["net"], gate.clone())]).unwrap(),
+            "#[cfg(unix)] // code_passenger:gate\n#[cfg(feature = \"net\")]\nmod net;\n"
+        );
+
+        // an item-level gate from `gate_items` on the same decl is kept and extended
+        let src = "#[cfg(feature = \"tls\")] // code_passenger:gate\nmod net;\n";
+        assert_eq!(
+            gate_mod_decls(src, &[child(&["net"], gate)]).unwrap(),
+            "#[cfg(all(feature = \"tls\", feature = \"net\", unix))] // code_passenger:gate\nmod net;\n"
+        );
+    }
+}
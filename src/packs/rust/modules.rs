@@ -0,0 +1,215 @@
+//! Module tree for one crate root: follows `mod foo;` items (with `#[path]` and
+//! `#[cfg]`) and inline `mod foo { ... }` blocks the way rustc loads files.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::model::{CfgExpr, MissingModule, ModuleFile, ModuleTree};
+use crate::packs::rust::cfg::cfg_attrs;
+use crate::packs::normalize_path;
+
+/// Walk the module tree starting at crate root `entry` (`src/lib.rs`, `tests/it.rs`, ...).
+pub fn build_tree(entry: &Path) -> ModuleTree {
+    let mut tree = ModuleTree::default();
+    let mut b = Builder { tree: &mut tree, root: crate_dir(entry) };
+    // crate roots own their directory like `mod.rs` files do
+    b.file(&normalize_path(entry), "crate".to_string(), vec![], None, true);
+    tree
+}
+
+struct Builder<'a> {
+    tree: &'a mut ModuleTree,
+    /// reported paths are relative to this
+    root: PathBuf,
+}
+
+impl Builder<'_> {
+    fn file(&mut self, path: &Path, module_path: String, cfg: Vec<CfgExpr>, declared_in: Option<String>, owns_dir: bool) {
+        if self.tree.files.contains_key(path) {
+            return;
+        }
+        let module = ModuleFile { module_path: module_path.clone(), cfg: cfg.clone(), declared_in };
+        self.tree.files.insert(path.to_path_buf(), module);
+
+        let Ok(content) = fs::read_to_string(path) else { return };
+        let Ok(file) = syn::parse_file(&content) else { return };
+
+        let parent = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        // `a.rs` keeps its children in `a/`; `mod.rs`/`lib.rs`/`#[path]` files next to themselves
+        let child_dir = if owns_dir || is_mod_rs(path) {
+            parent.clone()
+        } else {
+            parent.join(path.file_stem().unwrap_or_default())
+        };
+
+        self.items(&file.items, path, &parent, &child_dir, &module_path, &cfg);
+    }
+
+    /// `path_base`: what `#[path]` is relative to; `child_dir`: where `mod x;` looks.
+    fn items(
+        &mut self,
+        items: &[syn::Item],
+        file: &Path,
+        path_base: &Path,
+        child_dir: &Path,
+        module_path: &str,
+        cfg: &[CfgExpr],
+    ) {
+        for item in items {
+            let syn::Item::Mod(m) = item else { continue };
+            let name = m.ident.to_string().trim_start_matches("r#").to_string();
+            let child_path = format!("{module_path}::{name}");
+
+            let mut child_cfg = cfg.to_vec();
+            child_cfg.extend(cfg_attrs(&m.attrs));
+            let path_attr = path_attr(&m.attrs);
+
+            match &m.content {
+                Some((_, inner)) => {
+                    // inline block: nested `mod x;` / `#[path]` resolve under `child_dir/name`
+                    let dir = match &path_attr {
+                        Some(p) => path_base.join(p),
+                        None => child_dir.join(&name),
+                    };
+                    self.items(inner, file, &dir, &dir, &child_path, &child_cfg);
+                }
+                None => {
+                    let candidates = match &path_attr {
+                        Some(p) => vec![path_base.join(p)],
+                        None => vec![child_dir.join(format!("{name}.rs")), child_dir.join(&name).join("mod.rs")],
+                    };
+                    let candidates: Vec<PathBuf> = candidates.iter().map(|c| normalize_path(c)).collect();
+
+                    match candidates.iter().find(|c| c.is_file()) {
+                        Some(found) => {
+                            let found = found.clone();
+                            let declared_in = Some(self.rel(file));
+                            self.file(&found, child_path, child_cfg, declared_in, path_attr.is_some());
+                        }
+                        None => self.tree.missing.push(MissingModule {
+                            module_path: child_path,
+                            declared_in: self.rel(file),
+                            candidates: candidates.iter().map(|c| self.rel(c)).collect(),
+                        }),
+                    }
+                }
+            }
+        }
+    }
+
+    fn rel(&self, p: &Path) -> String {
+        p.strip_prefix(&self.root).unwrap_or(p).to_string_lossy().replace('\\', "/")
+    }
+}
+
+fn is_mod_rs(path: &Path) -> bool {
+    path.file_name().is_some_and(|f| f == "mod.rs")
+}
+
+/// Crate dir of `entry`: the nearest ancestor holding a `Cargo.toml`.
+fn crate_dir(entry: &Path) -> PathBuf {
+    let mut dirs = entry.ancestors().skip(1);
+    let parent = entry.parent().unwrap_or(Path::new(""));
+    dirs.find(|a| a.join("Cargo.toml").is_file()).unwrap_or(parent).to_path_buf()
+}
+
+/// `#[path = "..."]`
+fn path_attr(attrs: &[syn::Attribute]) -> Option<String> {
+    attrs.iter().find_map(|a| match &a.meta {
+        syn::Meta::NameValue(nv) if nv.path.is_ident("path") => match &nv.value {
+            syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(s), .. }) => Some(s.value()),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tree;
+
+    fn crate_with(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tree(files);
+        fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"a\"\n").unwrap();
+        dir
+    }
+
+    /// crate-relative file, module path, cfgs, declared in
+    type Row = (String, String, String, Option<String>);
+
+    fn modules(dir: &tempfile::TempDir, entry: &str) -> (Vec<Row>, ModuleTree) {
+        let tree = build_tree(&dir.path().join(entry));
+        let root = normalize_path(dir.path());
+        let files = tree
+            .files
+            .iter()
+            .map(|(p, m)| {
+                let rel = p.strip_prefix(&root).unwrap().to_string_lossy().replace('\\', "/");
+                let cfg = m.cfg.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(" & ");
+                (rel, m.module_path.clone(), cfg, m.declared_in.clone())
+            })
+            .collect();
+        (files, tree)
+    }
+
+    #[test]
+    fn follows_mod_items_like_rustc() {
+        let dir = crate_with(&[
+            ("src/lib.rs", "pub mod a;\nmod b;\n#[cfg(feature = \"net\")]\nmod net;\n"),
+            ("src/a.rs", "mod child;\n"),
+            ("src/a/child.rs", ""),
+            ("src/b/mod.rs", "#[cfg(unix)] mod sys;\n"),
+            ("src/b/sys.rs", ""),
+            ("src/net.rs", ""),
+        ]);
+        let (files, tree) = modules(&dir, "src/lib.rs");
+        let row = |f: &str, m: &str, c: &str, d: Option<&str>| (f.to_string(), m.to_string(), c.to_string(), d.map(str::to_string));
+
+        assert_eq!(
+            files,
+            [
+                row("src/a/child.rs", "crate::a::child", "", Some("src/a.rs")),
+                row("src/a.rs", "crate::a", "", Some("src/lib.rs")),
+                row("src/b/mod.rs", "crate::b", "", Some("src/lib.rs")),
+                row("src/b/sys.rs", "crate::b::sys", "unix", Some("src/b/mod.rs")),
+                row("src/lib.rs", "crate", "", None),
+                row("src/net.rs", "crate::net", "feature = \"net\"", Some("src/lib.rs")),
+            ]
+        );
+        assert!(tree.missing.is_empty());
+    }
+
+    #[test]
+    fn path_attrs_and_inline_blocks() {
+        let dir = crate_with(&[
+            ("src/main.rs", "#[path = \"impls/unix.rs\"]\nmod sys;\nmod outer {\n    mod inner;\n}\n"),
+            ("src/impls/unix.rs", "mod helper;\n"),
+            ("src/impls/helper.rs", ""),
+            ("src/outer/inner.rs", ""),
+        ]);
+        let (files, _) = modules(&dir, "src/main.rs");
+        let mods: Vec<(&str, &str)> = files.iter().map(|(f, m, _, _)| (f.as_str(), m.as_str())).collect();
+
+        assert_eq!(
+            mods,
+            [
+                // a `#[path]` file keeps its children next to itself
+                ("src/impls/helper.rs", "crate::sys::helper"),
+                ("src/impls/unix.rs", "crate::sys"),
+                ("src/main.rs", "crate"),
+                ("src/outer/inner.rs", "crate::outer::inner"),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_files_list_the_candidates() {
+        let dir = crate_with(&[("src/lib.rs", "mod gone;\n")]);
+        let (_, tree) = modules(&dir, "src/lib.rs");
+
+        assert_eq!(tree.missing.len(), 1);
+        let m = &tree.missing[0];
+        assert_eq!((m.module_path.as_str(), m.declared_in.as_str()), ("crate::gone", "src/lib.rs"));
+        assert_eq!(m.candidates, ["src/gone.rs", "src/gone/mod.rs"]);
+    }
+}
@@ -0,0 +1,776 @@
+//! Syntax-tree front end for the Rust pack.
+//!
+//! Parses a file with `syn` and walks real items, paths, attributes and macro
+//! invocations, so braces/paths inside strings, comments and doc comments never
+//! count as uses or throw off scope tracking.
+use std::collections::{BTreeMap, BTreeSet};
+
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::Token;
+use syn::visit::{self, Visit};
+
+use crate::model::{AttrCrates, CfgExpr, FileScan, ReExports, ScanContext, ScanMode, UseKind, UseSite, UsedSymbols};
+use crate::packs::rust::aliases::{absolutize, collect_reexports, follow_reexports, AliasTable};
+use crate::packs::rust::cfg::{cfg_attrs, parse_cfg, parse_cfg_attr};
+use crate::packs::rust::header_span;
+use crate::packs::rust::derives::builtin_attr_crates;
+use crate::packs::rust::helpers::norm_ident;
+
+/// Parse `content` and collect external/internal use sites.
+/// Returns `None` if the file does not parse (caller falls back to regex scanning).
+pub fn scan_file(
+    content: &str,
+    deps: &BTreeSet<String>,
+    crate_name: &str,
+    ctx: ScanContext<'_>,
+) -> Option<FileScan> {
+    let file = syn::parse_file(content).ok()?;
+
+    // inner `#![cfg]`s: hand-written ones vs the ones our header block emits
+    let header = header_lines(content);
+    let (mut file_cfg, mut header_cfg) = (vec![], vec![]);
+    for a in file.attrs.iter().filter(|a| matches!(a.style, syn::AttrStyle::Inner(_))) {
+        let Some(expr) = cfg_attrs(std::slice::from_ref(a)).pop() else { continue };
+        let line = a.span().start().line;
+        if header.as_ref().is_some_and(|r| r.contains(&line)) {
+            header_cfg.push(expr);
+        } else {
+            file_cfg.push(expr);
+        }
+    }
+
+    let mut c = SiteCollector {
+        deps,
+        crate_id: norm_ident(crate_name),
+        aliases: AliasTable::from_file(&file),
+        attrs: builtin_attr_crates().layered(ctx.attr_crates),
+        ctx,
+        scopes: vec![],
+        mods: vec![],
+        derived: vec![],
+        test_depth: 0,
+        gates: vec![],
+        runtime: vec![],
+        scope_cfgs: BTreeMap::new(),
+        external: vec![],
+        internal: vec![],
+    };
+    c.visit_file(&file);
+
+    let mut scan = c.finish();
+    scan.file_cfg = file_cfg;
+    scan.header_cfg = header_cfg;
+    Some(scan)
+}
+
+/// 1-based line range of the generated header block, if the file has one.
+fn header_lines(content: &str) -> Option<std::ops::RangeInclusive<usize>> {
+    let span = header_span(content)?;
+    Some(content[..span.start].lines().count() + 1..=content[..span.end].lines().count())
+}
+
+/// Parse `content` and collect its `pub use` re-exports (empty if it doesn't parse).
+pub fn file_reexports(content: &str, deps: &BTreeSet<String>, crate_name: &str, module_path: &str) -> ReExports {
+    match syn::parse_file(content) {
+        Ok(file) => collect_reexports(&file, module_path, deps, &norm_ident(crate_name)),
+        Err(_) => ReExports::new(),
+    }
+}
+
+struct SiteCollector<'a> {
+    deps: &'a BTreeSet<String>,
+    crate_id: String,
+    aliases: AliasTable,
+    ctx: ScanContext<'a>,
+    attrs: AttrCrates,
+    /// innermost scope label last ("fn run", "impl Foo", ...)
+    scopes: Vec<String>,
+    /// inline `mod` path the visitor is in, for alias lookups
+    mods: Vec<String>,
+    /// crates derived on the enclosing items, innermost last; helper attrs only count against these
+    derived: Vec<BTreeSet<String>>,
+    /// > 0 while inside a `#[cfg(test)]` / `#[test]` item
+    test_depth: usize,
+    /// `cfg`/`cfg_attr` predicates of the enclosing items, outermost first
+    gates: Vec<CfgExpr>,
+    /// enclosing `if cfg!(...)` conditions
+    runtime: Vec<CfgExpr>,
+    /// full scope path (`mod net > impl Client > fn get`) -> gate on that scope
+    scope_cfgs: BTreeMap<String, CfgExpr>,
+    external: Vec<UseSite>,
+    internal: Vec<UseSite>,
+}
+
+impl SiteCollector<'_> {
+    fn scope(&self) -> String {
+        self.scopes.last().cloned().unwrap_or_else(|| "file".to_string())
+    }
+
+    fn is_internal_root(&self, seg: &str) -> bool {
+        matches!(seg, "crate" | "self" | "super") || seg == self.crate_id
+    }
+
+    /// Follow aliases, then crate re-exports. Internal paths come back absolute.
+    fn resolve(&self, segs: &[String]) -> Vec<String> {
+        let segs = self.aliases.resolve(&self.mods, segs).unwrap_or_else(|| segs.to_vec());
+        match absolutize(&segs, self.ctx.module_path, &self.crate_id) {
+            Some(abs) => follow_reexports(&abs, self.ctx.reexports).unwrap_or(abs),
+            None => segs,
+        }
+    }
+
+    /// Record `segs` (already split on `::`) if it resolves to a dep or an internal root.
+    fn record(&mut self, segs: &[String], line: usize, kind: UseKind) {
+        let Some(root) = segs.first() else { return };
+
+        // bare `use dep;` / `extern crate dep;` import the crate itself
+        let bare = matches!(kind, UseKind::UseStmt | UseKind::ExternCrate) && segs.len() == 1;
+        let imported = self.aliases.contains(&self.mods, root);
+        if segs.len() < 2 && !bare && !imported {
+            return;
+        }
+        // `Mutex::new()` after `use tokio::sync::Mutex;`
+        let kind = if imported && matches!(kind, UseKind::Path) { UseKind::ImportedIdent } else { kind };
+
+        let resolved = self.resolve(segs);
+        let Some(resolved_root) = resolved.first() else { return };
+
+        // an imported ident counts under what was imported (`Mutex`), not the module it sits in
+        let head = if matches!(kind, UseKind::ImportedIdent) {
+            self.resolve(&segs[..1]).last().cloned()
+        } else {
+            resolved.get(1).cloned()
+        }
+        .unwrap_or_else(|| "self".to_string());
+        let site = UseSite {
+            dep: resolved_root.clone(),
+            path: segs.join("::"),
+            resolved: resolved.join("::"),
+            head,
+            kind,
+            line,
+            scope: self.scope(),
+            test_only: self.test_depth > 0,
+            cfg: CfgExpr::all_of(self.gates.clone()),
+            runtime_cfg: CfgExpr::all_of(self.runtime.clone()),
+        };
+
+        if self.deps.contains(resolved_root) {
+            self.external.push(site);
+        } else if self.is_internal_root(root) && segs.len() >= 2 {
+            // keep the written root (crate/self/super/<crate>) for internal counts
+            self.internal.push(UseSite { dep: root.clone(), head: segs[1].clone(), ..site });
+        } else if resolved_root == "crate" && resolved.len() >= 2 {
+            // aliased internal module (`use crate::engine as eng; eng::run()`)
+            self.internal.push(UseSite { dep: "crate".to_string(), ..site });
+        }
+    }
+
+    fn with_scope(&mut self, label: String, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(label);
+        if let Some(gate) = CfgExpr::all_of(self.gates.clone()) {
+            // full path, so same-named fns in different impls/mods don't collide
+            self.scope_cfgs.insert(self.scopes.join(" > "), gate);
+        }
+        f(self);
+        self.scopes.pop();
+    }
+
+    /// Visit something carrying `attrs` with its `#[cfg]`s (and test markers) in effect.
+    fn with_attrs(&mut self, attrs: &[syn::Attribute], f: impl FnOnce(&mut Self)) {
+        let gates = cfg_attrs(attrs);
+        let test = attrs.iter().any(is_test_attr);
+        let depth = self.gates.len();
+
+        self.gates.extend(gates);
+        self.test_depth += usize::from(test);
+        f(self);
+        self.test_depth -= usize::from(test);
+        self.gates.truncate(depth);
+    }
+
+    /// Macro bodies and attribute arguments are unparsed tokens; pick out
+    /// `a::b::c` sequences (optionally followed by `!`) from them.
+    fn record_tokens(&mut self, tokens: TokenStream, default_kind: UseKind) {
+        let tts: Vec<TokenTree> = tokens.into_iter().collect();
+        let mut i = 0;
+        while i < tts.len() {
+            match &tts[i] {
+                TokenTree::Ident(id) => {
+                    let line = id.span().start().line;
+                    let mut segs = vec![id.to_string()];
+                    let mut j = i + 1;
+                    while let (Some(TokenTree::Punct(a)), Some(TokenTree::Punct(b)), Some(TokenTree::Ident(next))) =
+                        (tts.get(j), tts.get(j + 1), tts.get(j + 2))
+                    {
+                        if a.as_char() == ':' && a.spacing() == Spacing::Joint && b.as_char() == ':' {
+                            segs.push(next.to_string());
+                            j += 3;
+                        } else {
+                            break;
+                        }
+                    }
+                    let bang = matches!(tts.get(j), Some(TokenTree::Punct(p)) if p.as_char() == '!');
+                    let kind = if bang { UseKind::MacroCall } else { default_kind.clone() };
+                    self.record(&segs, line, kind);
+                    i = j;
+                }
+                TokenTree::Group(g) => {
+                    self.record_tokens(g.stream(), default_kind.clone());
+                    i += 1;
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    /// One attribute body: derives, `cfg_attr` payloads, helper attrs, paths.
+    fn record_meta(&mut self, meta: &syn::Meta) {
+        let path = meta.path();
+        let segs = path_segs(path);
+        let line = path.span().start().line;
+
+        match (segs.as_slice(), meta) {
+            ([name], syn::Meta::List(l)) if name == "derive" => {
+                let Ok(paths) = l.parse_args_with(Punctuated::<syn::Path, Token![,]>::parse_terminated) else {
+                    return;
+                };
+                for p in paths {
+                    self.record_derive(&path_segs(&p), p.span().start().line);
+                }
+            }
+            ([name], syn::Meta::List(l)) if name == "cfg_attr" => {
+                // `cfg_attr(pred, attr, attr...)`: the attrs only apply under `pred`
+                if let Some((pred, metas)) = parse_cfg_attr(l.tokens.clone()) {
+                    self.gates.push(pred);
+                    for m in &metas {
+                        self.record_meta(m);
+                    }
+                    self.gates.pop();
+                }
+            }
+            _ => {
+                match segs.as_slice() {
+                    [name] if !self.aliases.contains(&self.mods, name) && self.attrs.helper_attrs.contains_key(name) => {
+                        // `#[error(..)]`, `#[arg(..)]`... are only that crate's when its derive is on the item
+                        let dep = self.attrs.helper_attrs[name].clone();
+                        if self.derived.last().is_some_and(|d| d.contains(&dep)) {
+                            self.record_mapped(&dep, name, line, UseKind::HelperAttr);
+                        }
+                    }
+                    _ => self.record(&segs, line, UseKind::Attribute),
+                }
+
+                match meta {
+                    syn::Meta::Path(_) => {}
+                    syn::Meta::List(l) => self.record_tokens(l.tokens.clone(), UseKind::Attribute),
+                    // `#[doc = "..."]` lands here; string literals hold no paths
+                    syn::Meta::NameValue(nv) => self.visit_expr(&nv.value),
+                }
+            }
+        }
+    }
+
+    /// `#[derive(x)]` member: real paths and imported names resolve as usual,
+    /// bare names fall back to the derive table.
+    fn record_derive(&mut self, segs: &[String], line: usize) {
+        match segs {
+            [name] if !self.aliases.contains(&self.mods, name) => {
+                if let Some(dep) = self.attrs.derives.get(name).cloned() {
+                    self.record_mapped(&dep, name, line, UseKind::Derive);
+                }
+            }
+            _ => self.record(segs, line, UseKind::Derive),
+        }
+    }
+
+    /// Crates behind the `#[derive]`s in `attrs` (including ones under `cfg_attr`).
+    fn derive_crates(&self, attrs: &[syn::Attribute]) -> BTreeSet<String> {
+        fn derive_paths(meta: &syn::Meta, out: &mut Vec<syn::Path>) {
+            let syn::Meta::List(l) = meta else { return };
+            if l.path.is_ident("derive") {
+                if let Ok(paths) = l.parse_args_with(Punctuated::<syn::Path, Token![,]>::parse_terminated) {
+                    out.extend(paths);
+                }
+            } else if l.path.is_ident("cfg_attr")
+                && let Some((_, metas)) = parse_cfg_attr(l.tokens.clone())
+            {
+                metas.iter().for_each(|m| derive_paths(m, out));
+            }
+        }
+
+        let mut paths = vec![];
+        attrs.iter().for_each(|a| derive_paths(&a.meta, &mut paths));
+        paths
+            .iter()
+            .filter_map(|p| match path_segs(p).as_slice() {
+                [name] if !self.aliases.contains(&self.mods, name) => self.attrs.derives.get(name).cloned(),
+                segs => self.resolve(segs).first().cloned(),
+            })
+            .collect()
+    }
+
+    /// Site for a bare name that only the derive/helper table ties to `dep`.
+    fn record_mapped(&mut self, dep: &str, name: &str, line: usize, kind: UseKind) {
+        if !self.deps.contains(dep) {
+            return;
+        }
+        self.external.push(UseSite {
+            dep: dep.to_string(),
+            path: name.to_string(),
+            resolved: format!("{dep}::{name}"),
+            head: name.to_string(),
+            kind,
+            line,
+            scope: self.scope(),
+            test_only: self.test_depth > 0,
+            cfg: CfgExpr::all_of(self.gates.clone()),
+            runtime_cfg: CfgExpr::all_of(self.runtime.clone()),
+        });
+    }
+
+    fn finish(self) -> FileScan {
+        let mut used = UsedSymbols::default();
+        let mut dep_scopes: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+
+        for u in &self.external {
+            used.packages.insert(u.dep.clone());
+            dep_scopes.entry(u.dep.clone()).or_default().insert(u.scope.clone());
+        }
+
+        // mirror detect_internal_paths: root::top and root::top::second
+        for u in &self.internal {
+            let parts: Vec<&str> = u.path.split("::").collect();
+            if parts.len() >= 2 {
+                used.modules.insert(format!("{}::{}", parts[0], parts[1]));
+            }
+            if parts.len() >= 3 {
+                used.modules.insert(format!("{}::{}::{}", parts[0], parts[1], parts[2]));
+            }
+        }
+
+        FileScan {
+            mode: ScanMode::Syntax,
+            used,
+            external_use_sites: self.external,
+            internal_use_sites: self.internal,
+            dep_scopes,
+            scope_cfgs: self.scope_cfgs,
+            file_cfg: vec![],
+            header_cfg: vec![],
+        }
+    }
+}
+
+fn path_segs(p: &syn::Path) -> Vec<String> {
+    p.segments.iter().map(|s| s.ident.to_string()).collect()
+}
+
+/// Expand a use tree into one path per leaf, with the line the leaf sits on.
+/// `use a::{b, c::{self, d}, e::*}` -> `a::b`, `a::c::self`, `a::c::d`, `a::e::*`.
+fn use_tree_leaves(tree: &syn::UseTree, prefix: &mut Vec<String>, out: &mut Vec<(Vec<String>, usize)>) {
+    match tree {
+        syn::UseTree::Path(p) => {
+            prefix.push(p.ident.to_string());
+            use_tree_leaves(&p.tree, prefix, out);
+            prefix.pop();
+        }
+        syn::UseTree::Name(n) => out.push((leaf(prefix, n.ident.to_string()), n.ident.span().start().line)),
+        syn::UseTree::Rename(r) => out.push((leaf(prefix, r.ident.to_string()), r.ident.span().start().line)),
+        syn::UseTree::Glob(g) => out.push((leaf(prefix, "*".to_string()), g.star_token.span.start().line)),
+        syn::UseTree::Group(g) => {
+            for item in &g.items {
+                use_tree_leaves(item, prefix, out);
+            }
+        }
+    }
+}
+
+fn leaf(prefix: &[String], last: String) -> Vec<String> {
+    let mut segs = prefix.to_vec();
+    segs.push(last);
+    segs
+}
+
+/// Render tokens compactly (`Foo<T>` instead of `Foo < T >`) for scope labels.
+fn compact(tokens: TokenStream) -> String {
+    let mut out = String::new();
+    let mut prev_word = false;
+    for tt in tokens {
+        match tt {
+            TokenTree::Ident(_) | TokenTree::Literal(_) => {
+                if prev_word {
+                    out.push(' ');
+                }
+                out.push_str(&tt.to_string());
+                prev_word = true;
+            }
+            TokenTree::Punct(p) => {
+                out.push(p.as_char());
+                if p.as_char() == ',' {
+                    out.push(' ');
+                }
+                prev_word = false;
+            }
+            TokenTree::Group(g) => {
+                let (open, close) = match g.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::None => ("", ""),
+                };
+                out.push_str(open);
+                out.push_str(&compact(g.stream()));
+                out.push_str(close);
+                prev_word = false;
+            }
+        }
+    }
+    out
+}
+
+fn impl_label(i: &syn::ItemImpl) -> String {
+    let ty = compact(i.self_ty.to_token_stream());
+    match &i.trait_ {
+        Some((bang, path, _)) => {
+            let not = if bang.is_some() { "!" } else { "" };
+            format!("impl {not}{} for {ty}", compact(path.to_token_stream()))
+        }
+        None => format!("impl {ty}"),
+    }
+}
+
+pub(crate) fn item_attrs(i: &syn::Item) -> &[syn::Attribute] {
+    match i {
+        syn::Item::Const(x) => &x.attrs,
+        syn::Item::Enum(x) => &x.attrs,
+        syn::Item::ExternCrate(x) => &x.attrs,
+        syn::Item::Fn(x) => &x.attrs,
+        syn::Item::ForeignMod(x) => &x.attrs,
+        syn::Item::Impl(x) => &x.attrs,
+        syn::Item::Macro(x) => &x.attrs,
+        syn::Item::Mod(x) => &x.attrs,
+        syn::Item::Static(x) => &x.attrs,
+        syn::Item::Struct(x) => &x.attrs,
+        syn::Item::Trait(x) => &x.attrs,
+        syn::Item::TraitAlias(x) => &x.attrs,
+        syn::Item::Type(x) => &x.attrs,
+        syn::Item::Union(x) => &x.attrs,
+        syn::Item::Use(x) => &x.attrs,
+        _ => &[],
+    }
+}
+
+pub(crate) fn impl_item_attrs(i: &syn::ImplItem) -> &[syn::Attribute] {
+    match i {
+        syn::ImplItem::Const(x) => &x.attrs,
+        syn::ImplItem::Fn(x) => &x.attrs,
+        syn::ImplItem::Type(x) => &x.attrs,
+        syn::ImplItem::Macro(x) => &x.attrs,
+        _ => &[],
+    }
+}
+
+/// `#[test]` or `#[cfg(test)]`
+fn is_test_attr(a: &syn::Attribute) -> bool {
+    match &a.meta {
+        syn::Meta::Path(p) => p.is_ident("test"),
+        syn::Meta::List(l) => l.path.is_ident("cfg") && l.tokens.to_string() == "test",
+        syn::Meta::NameValue(_) => false,
+    }
+}
+
+/// `cfg!(pred)` or `!cfg!(pred)` as an `if` condition.
+fn cfg_macro_cond(cond: &syn::Expr) -> Option<CfgExpr> {
+    match cond {
+        syn::Expr::Macro(m) if m.mac.path.is_ident("cfg") => parse_cfg(m.mac.tokens.clone()),
+        syn::Expr::Unary(u) if matches!(u.op, syn::UnOp::Not(_)) => {
+            cfg_macro_cond(&u.expr).map(|e| CfgExpr::Not(Box::new(e)))
+        }
+        syn::Expr::Paren(p) => cfg_macro_cond(&p.expr),
+        _ => None,
+    }
+}
+
+impl<'ast> Visit<'ast> for SiteCollector<'_> {
+    fn visit_item(&mut self, i: &'ast syn::Item) {
+        let derived = self.derive_crates(item_attrs(i));
+        self.derived.push(derived);
+        self.with_attrs(item_attrs(i), |s| visit::visit_item(s, i));
+        self.derived.pop();
+    }
+
+    fn visit_impl_item(&mut self, i: &'ast syn::ImplItem) {
+        self.with_attrs(impl_item_attrs(i), |s| visit::visit_impl_item(s, i));
+    }
+
+    fn visit_trait_item(&mut self, i: &'ast syn::TraitItem) {
+        let attrs: &[syn::Attribute] = match i {
+            syn::TraitItem::Const(x) => &x.attrs,
+            syn::TraitItem::Fn(x) => &x.attrs,
+            syn::TraitItem::Type(x) => &x.attrs,
+            syn::TraitItem::Macro(x) => &x.attrs,
+            _ => &[],
+        };
+        self.with_attrs(attrs, |s| visit::visit_trait_item(s, i));
+    }
+
+    fn visit_field(&mut self, i: &'ast syn::Field) {
+        self.with_attrs(&i.attrs, |s| visit::visit_field(s, i));
+    }
+
+    fn visit_variant(&mut self, i: &'ast syn::Variant) {
+        self.with_attrs(&i.attrs, |s| visit::visit_variant(s, i));
+    }
+
+    fn visit_local(&mut self, i: &'ast syn::Local) {
+        self.with_attrs(&i.attrs, |s| visit::visit_local(s, i));
+    }
+
+    fn visit_arm(&mut self, i: &'ast syn::Arm) {
+        self.with_attrs(&i.attrs, |s| visit::visit_arm(s, i));
+    }
+
+    fn visit_expr_if(&mut self, i: &'ast syn::ExprIf) {
+        let Some(pred) = cfg_macro_cond(&i.cond) else {
+            return visit::visit_expr_if(self, i);
+        };
+        for a in &i.attrs {
+            self.visit_attribute(a);
+        }
+        self.visit_expr(&i.cond);
+
+        self.runtime.push(pred.clone());
+        self.visit_block(&i.then_branch);
+        self.runtime.pop();
+
+        if let Some((_, els)) = &i.else_branch {
+            self.runtime.push(CfgExpr::Not(Box::new(pred)));
+            self.visit_expr(els);
+            self.runtime.pop();
+        }
+    }
+
+    fn visit_item_fn(&mut self, i: &'ast syn::ItemFn) {
+        self.with_scope(format!("fn {}", i.sig.ident), |s| visit::visit_item_fn(s, i));
+    }
+
+    fn visit_impl_item_fn(&mut self, i: &'ast syn::ImplItemFn) {
+        self.with_scope(format!("fn {}", i.sig.ident), |s| visit::visit_impl_item_fn(s, i));
+    }
+
+    fn visit_trait_item_fn(&mut self, i: &'ast syn::TraitItemFn) {
+        self.with_scope(format!("fn {}", i.sig.ident), |s| visit::visit_trait_item_fn(s, i));
+    }
+
+    fn visit_item_impl(&mut self, i: &'ast syn::ItemImpl) {
+        self.with_scope(impl_label(i), |s| visit::visit_item_impl(s, i));
+    }
+
+    fn visit_item_mod(&mut self, i: &'ast syn::ItemMod) {
+        self.mods.push(i.ident.to_string());
+        self.with_scope(format!("mod {}", i.ident), |s| visit::visit_item_mod(s, i));
+        self.mods.pop();
+    }
+
+    fn visit_item_trait(&mut self, i: &'ast syn::ItemTrait) {
+        self.with_scope(format!("trait {}", i.ident), |s| visit::visit_item_trait(s, i));
+    }
+
+    fn visit_item_struct(&mut self, i: &'ast syn::ItemStruct) {
+        self.with_scope(format!("struct {}", i.ident), |s| visit::visit_item_struct(s, i));
+    }
+
+    fn visit_item_enum(&mut self, i: &'ast syn::ItemEnum) {
+        self.with_scope(format!("enum {}", i.ident), |s| visit::visit_item_enum(s, i));
+    }
+
+    fn visit_item_use(&mut self, i: &'ast syn::ItemUse) {
+        for a in &i.attrs {
+            self.visit_attribute(a);
+        }
+        let mut leaves = vec![];
+        use_tree_leaves(&i.tree, &mut vec![], &mut leaves);
+        for (segs, line) in leaves {
+            self.record(&segs, line, UseKind::UseStmt);
+        }
+    }
+
+    fn visit_item_extern_crate(&mut self, i: &'ast syn::ItemExternCrate) {
+        for a in &i.attrs {
+            self.visit_attribute(a);
+        }
+        self.record(&[i.ident.to_string()], i.span().start().line, UseKind::ExternCrate);
+    }
+
+    fn visit_attribute(&mut self, a: &'ast syn::Attribute) {
+        self.record_meta(&a.meta);
+    }
+
+    fn visit_macro(&mut self, m: &'ast syn::Macro) {
+        self.record(&path_segs(&m.path), m.path.span().start().line, UseKind::MacroCall);
+        self.record_tokens(m.tokens.clone(), UseKind::Path);
+    }
+
+    fn visit_path(&mut self, p: &'ast syn::Path) {
+        self.record(&path_segs(p), p.span().start().line, UseKind::Path);
+        // generic args may hold more paths (`Arc<tokio::sync::Mutex<T>>`)
+        visit::visit_path(self, p);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan_with(src: &str, deps: &[&str], reexports: &ReExports) -> Option<FileScan> {
+        let deps: BTreeSet<String> = deps.iter().map(|d| d.to_string()).collect();
+        let attr_crates = AttrCrates::default();
+        let ctx = ScanContext { module_path: "crate", reexports, attr_crates: &attr_crates };
+        scan_file(src, &deps, "me", ctx)
+    }
+
+    fn scan(src: &str, deps: &[&str]) -> FileScan {
+        scan_with(src, deps, &ReExports::new()).expect("parses")
+    }
+
+    fn sites<'a>(scan: &'a FileScan, dep: &str) -> Vec<&'a UseSite> {
+        scan.external_use_sites.iter().filter(|u| u.dep == dep).collect()
+    }
+
+    #[test]
+    fn unparsable_files_fall_back() {
+        assert!(scan_with("fn broken( {", &["tokio"], &ReExports::new()).is_none());
+    }
+
+    #[test]
+    fn strings_and_comments_are_not_uses() {
+        let s = scan(
+            "// tokio::spawn in a comment\n\
+             /// doc mentioning tokio::task\n\
+             fn run() { let _ = \"tokio::time::sleep {\"; }\n\
+             fn real() { tokio::spawn(async {}); }",
+            &["tokio"],
+        );
+        assert_eq!(s.mode, ScanMode::Syntax);
+        let t = sites(&s, "tokio");
+        assert_eq!(t.len(), 1);
+        assert_eq!((t[0].path.as_str(), t[0].scope.as_str(), t[0].line), ("tokio::spawn", "fn real", 4));
+    }
+
+    #[test]
+    fn scopes_follow_items() {
+        let s = scan(
+            "struct S;\n\
+             impl S { fn a(&self) { serde_json::json!({}); } }\n\
+             impl std::fmt::Display for S {\n\
+                 fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { let _ = serde_json::Value::Null; Ok(()) }\n\
+             }\n\
+             mod inner { pub fn b() { serde_json::to_string(&1).ok(); } }",
+            &["serde_json"],
+        );
+        let scopes: BTreeSet<&str> = sites(&s, "serde_json").iter().map(|u| u.scope.as_str()).collect();
+        assert_eq!(scopes, ["fn a", "fn b", "fn fmt"].into());
+        assert_eq!(s.dep_scopes["serde_json"].len(), 3);
+    }
+
+    #[test]
+    fn internal_paths_keep_their_written_root() {
+        let s = scan("fn f() { crate::engine::run(); me::cli::run(); self::x::y(); }", &[]);
+        let roots: Vec<&str> = s.internal_use_sites.iter().map(|u| u.dep.as_str()).collect();
+        assert_eq!(roots, ["crate", "me", "self"]);
+        assert!(s.used.modules.contains("crate::engine"));
+        assert!(s.used.modules.contains("crate::engine::run"));
+    }
+
+    #[test]
+    fn use_trees_expand_per_leaf() {
+        let tree: syn::ItemUse = syn::parse_str("use a::{b, c::{self, d}, e::*, f as g};").unwrap();
+        let mut leaves = vec![];
+        use_tree_leaves(&tree.tree, &mut vec![], &mut leaves);
+        let paths: Vec<String> = leaves.iter().map(|(segs, _)| segs.join("::")).collect();
+        assert_eq!(paths, ["a::b", "a::c::self", "a::c::d", "a::e::*", "a::f"]);
+    }
+
+    #[test]
+    fn grouped_uses_give_one_site_per_leaf_on_its_line() {
+        let s = scan(
+            "use tokio::{\n    sync::{Mutex, mpsc},\n    task::spawn,\n};\nuse serde::*;",
+            &["tokio", "serde"],
+        );
+        let tokio: Vec<(&str, &str, usize)> =
+            sites(&s, "tokio").iter().map(|u| (u.path.as_str(), u.head.as_str(), u.line)).collect();
+        assert_eq!(
+            tokio,
+            [("tokio::sync::Mutex", "sync", 2), ("tokio::sync::mpsc", "sync", 2), ("tokio::task::spawn", "task", 3)]
+        );
+        assert_eq!(sites(&s, "serde")[0].path, "serde::*");
+        assert!(sites(&s, "tokio").iter().all(|u| matches!(u.kind, UseKind::UseStmt)));
+    }
+
+    #[test]
+    fn imported_idents_count_under_the_imported_leaf() {
+        let s = scan("use tokio::sync::Mutex;\nfn f() { let _ = Mutex::new(1); }", &["tokio"]);
+        let call = sites(&s, "tokio").into_iter().find(|u| matches!(u.kind, UseKind::ImportedIdent)).unwrap();
+        assert_eq!(call.resolved, "tokio::sync::Mutex::new");
+        assert_eq!(call.head, "Mutex");
+    }
+
+    #[test]
+    fn helper_attrs_need_their_derive_on_the_item() {
+        let s = scan(
+            "#[derive(Debug, thiserror::Error)]\nenum E { #[error(\"x\")] X }\n\
+             struct Cli { #[arg(long)] v: bool }\n\
+             #[derive(clap::Parser)]\nstruct Args { #[arg(long)] v: bool }",
+            &["thiserror", "clap"],
+        );
+        let helpers = |dep| -> Vec<usize> {
+            sites(&s, dep).iter().filter(|u| matches!(u.kind, UseKind::HelperAttr)).map(|u| u.line).collect()
+        };
+        assert_eq!(helpers("thiserror"), [2]);
+        assert_eq!(helpers("clap"), [5]);
+    }
+
+    #[test]
+    fn bare_derives_map_through_the_table_unless_imported() {
+        let s = scan(
+            "#[derive(Serialize)] struct A;\n#[cfg_attr(feature = \"x\", derive(Deserialize))] struct B;",
+            &["serde"],
+        );
+        let derives: Vec<(&str, usize)> = sites(&s, "serde")
+            .iter()
+            .filter(|u| matches!(u.kind, UseKind::Derive))
+            .map(|u| (u.resolved.as_str(), u.line))
+            .collect();
+        assert_eq!(derives, [("serde::Serialize", 1), ("serde::Deserialize", 2)]);
+
+        // an imported `Error` is whatever it was imported from, not thiserror
+        let s = scan("use my_err::Error;\n#[derive(Error)] enum E {}", &["thiserror", "my_err"]);
+        assert!(sites(&s, "thiserror").is_empty());
+        assert!(sites(&s, "my_err").iter().any(|u| matches!(u.kind, UseKind::Derive)));
+    }
+
+    #[test]
+    fn scope_cfgs_are_keyed_by_the_full_path() {
+        let s = scan(
+            "struct A;\nstruct B;\n\
+             impl A { #[cfg(unix)] fn new() {} }\n\
+             impl B { #[cfg(windows)] fn new() {} }\n\
+             #[cfg(feature = \"net\")]\nmod net { fn new() {} }",
+            &[],
+        );
+        let cfgs: Vec<(&str, String)> = s.scope_cfgs.iter().map(|(k, v)| (k.as_str(), v.to_string())).collect();
+        assert_eq!(
+            cfgs,
+            [
+                ("impl A > fn new", "unix".to_string()),
+                ("impl B > fn new", "windows".to_string()),
+                ("mod net", "feature = \"net\"".to_string()),
+                ("mod net > fn new", "feature = \"net\"".to_string()),
+            ]
+        );
+    }
+}
@@ -1,43 +1,519 @@
+use std::collections::BTreeMap;
+
+use crate::analysis::state::AnalysisState;
 use crate::error::{PassengerError, Result};
-use crate::model::{FeatureNote, FileReport, PlanSection};
-use std::collections::BTreeSet;
+use crate::model::{CfgExpr, FileReport, GateOptions, PlanOptions, PlanSection, Usage};
 
-pub trait HeaderPlan: Send + Sync {
+pub trait HeaderPlan: HeaderPlanV2 {
     fn id(&self) -> &'static str;
 
-    /// Rust rendering (pack supplies markers; plan decides sections + gate selection).
-    fn render_for_rust(&self, report: &FileReport, begin: &str, end: &str) -> String;
+    /// Rust rendering (pack supplies markers; plan decides sections + gate
+    /// selection). Renders [`HeaderPlanV2::sections`] unless overridden.
+    fn render_for_rust(&self, cx: &RenderContext, begin: &str, end: &str) -> String {
+        render_sections(self, cx, begin, end)
+    }
+
+    /// Header fields developers fill in; re-annotation keeps their values.
+    fn user_fields(&self) -> &[&str] {
+        USER_FIELDS
+    }
+}
+
+/// Default user-owned header fields.
+pub const USER_FIELDS: &[&str] = &["description", "owner", "notes"];
+
+/// A freshly rendered header merged with the one already in the file.
+#[derive(Debug, Clone)]
+pub struct Reconciled {
+    pub header: String,
+    /// the existing header's computed part was edited by hand (its hash no
+    /// longer matches the one stamped on the end marker)
+    pub conflict: bool,
+}
+
+/// Carry the user fields of `existing` over into `fresh` and stamp the end
+/// marker with a hash of the computed lines, so hand edits to those show up as
+/// a conflict on the next run. Both are whole blocks, begin marker to end
+/// marker; fields are `//! key:value` lines, continued by `//!` lines indented
+/// two or more spaces.
+pub fn reconcile(plan: &dyn HeaderPlan, fresh: &str, existing: Option<&str>) -> Reconciled {
+    let user = plan.user_fields();
+    let fresh_lines: Vec<&str> = fresh.lines().collect();
+    let Some((end, body)) = fresh_lines.split_last() else {
+        return Reconciled { header: fresh.to_string(), conflict: false };
+    };
+
+    let mut conflict = false;
+    let mut kept: Vec<(String, Vec<String>)> = vec![];
+    if let Some(existing) = existing {
+        let old: Vec<&str> = existing.lines().collect();
+        if let Some((old_end, old_body)) = old.split_last() {
+            let stamped = old_end.split_once(" hash=").map(|(_, h)| h.trim());
+            conflict = stamped.is_some_and(|h| h != computed_hash(old_body, user));
+        }
+        kept = fields(&old, user).into_iter().filter(|(_, lines)| !lines.is_empty()).collect();
+    }
+
+    // fresh lines with user fields swapped for the kept ones
+    let mut out: Vec<String> = vec![];
+    let mut i = 0;
+    let mut last_user = None;
+    while i < body.len() {
+        match field_key(body[i]).filter(|k| user.contains(k)) {
+            Some(key) => {
+                let n = field_len(&body[i..]);
+                match kept.iter().position(|(k, _)| k == key) {
+                    Some(p) => out.extend(kept.remove(p).1),
+                    None => out.extend(body[i..i + n].iter().map(|l| l.to_string())),
+                }
+                last_user = Some(out.len());
+                i += n;
+            }
+            None => {
+                out.push(body[i].to_string());
+                i += 1;
+            }
+        }
+    }
+    // user fields this plan doesn't render go after the last one it does (or
+    // right after the begin marker)
+    let at = last_user.unwrap_or(1.min(out.len()));
+    out.splice(at..at, kept.into_iter().flat_map(|(_, lines)| lines));
+
+    let hash = computed_hash(&out.iter().map(String::as_str).collect::<Vec<_>>(), user);
+    let end = end.split_once(" hash=").map_or(*end, |(e, _)| e);
+    out.push(format!("{end} hash={hash}"));
+
+    let mut header = out.join("\n");
+    header.push('\n');
+    Reconciled { header, conflict }
+}
+
+/// `key` of a `//! key:value` line.
+fn field_key(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix("//! ")?;
+    let (key, _) = rest.split_once(':')?;
+    (!key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')).then_some(key)
+}
+
+/// Lines of the field starting at `lines[0]`: the `key:` line plus continuations.
+fn field_len(lines: &[&str]) -> usize {
+    1 + lines[1..].iter().take_while(|l| l.starts_with("//!   ")).count()
+}
+
+/// User fields of a header block, in order, with their lines.
+fn fields(lines: &[&str], user: &[&str]) -> Vec<(String, Vec<String>)> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        match field_key(lines[i]).filter(|k| user.contains(k)) {
+            Some(key) => {
+                let n = field_len(&lines[i..]);
+                let has_value = lines[i].split_once(':').is_some_and(|(_, v)| !v.trim().is_empty()) || n > 1;
+                let kept = if has_value { lines[i..i + n].iter().map(|l| l.to_string()).collect() } else { vec![] };
+                out.push((key.to_string(), kept));
+                i += n;
+            }
+            None => i += 1,
+        }
+    }
+    out
+}
+
+/// Short hash of everything but the user fields (markers included, minus any
+/// hash already on the end marker).
+fn computed_hash(body: &[&str], user: &[&str]) -> String {
+    use sha2::{Digest, Sha256};
+
+    let mut h = Sha256::new();
+    let mut i = 0;
+    while i < body.len() {
+        if field_key(body[i]).is_some_and(|k| user.contains(&k)) {
+            i += field_len(&body[i..]);
+            continue;
+        }
+        h.update(body[i].trim_end().as_bytes());
+        h.update(b"\n");
+        i += 1;
+    }
+    hex::encode(&h.finalize()[..6])
 }
 
-/// v2 plan metadata: section ordering + gate selection rules.
-/// (Doesn't replace HeaderPlan yet; it complements it.)
+/// v2 plan metadata: section ordering + gate selection rules, one renderer per
+/// built-in section.
 pub trait HeaderPlanV2: Send + Sync {
-    fn id(&self) -> &'static str;
+    fn options(&self) -> &PlanOptions;
 
     fn sections(&self) -> &[PlanSection];
 
-    fn select_gates(&self, notes: &[FeatureNote]) -> Vec<String> {
-        let mut set = BTreeSet::<String>::new();
-        for n in notes {
-            if n.corpus && n.used_in_file {
-                set.insert(n.feature_name.clone());
+    /// The file gate, `None` for an ungated file. See [`select_gate`].
+    fn select_gates(&self, report: &FileReport, opts: &GateOptions) -> Option<CfgExpr> {
+        select_gate(report, opts)
+    }
+
+    /// Lines (`\n`-terminated) of a built-in section; custom sections come from
+    /// the [`SectionRegistry`].
+    fn render_section(&self, section: &PlanSection, cx: &SectionContext) -> String {
+        builtin_section(section, cx)
+    }
+}
+
+/// The default renderer of each built-in section.
+pub fn builtin_section(section: &PlanSection, cx: &SectionContext) -> String {
+    match section {
+        PlanSection::DocumentDetails => document_details(cx),
+        PlanSection::FeatureNotes => feature_notes(cx),
+        PlanSection::CorpusGates => corpus_gates(cx),
+        PlanSection::Custom(_) => String::new(),
+    }
+}
+
+/// What header rendering gets besides the plan.
+pub struct RenderContext<'a> {
+    pub report: &'a FileReport,
+    /// the analysis of the crate the file belongs to
+    pub analysis: &'a AnalysisState,
+    pub sections: &'a SectionRegistry,
+}
+
+/// What a section renderer gets.
+pub struct SectionContext<'a> {
+    pub report: &'a FileReport,
+    pub analysis: &'a AnalysisState,
+    pub opts: &'a PlanOptions,
+    /// the plan's file gate
+    pub gate: Option<&'a CfgExpr>,
+}
+
+/// Renders a [`PlanSection::Custom`] block (`OWNERS`, `SAFETY`, ...).
+pub trait SectionProvider: Send + Sync {
+    /// `//!` lines, each ending in `\n`; empty leaves the section out.
+    fn render(&self, cx: &SectionContext) -> String;
+}
+
+impl<F: Fn(&SectionContext) -> String + Send + Sync> SectionProvider for F {
+    fn render(&self, cx: &SectionContext) -> String {
+        self(cx)
+    }
+}
+
+/// Custom section providers by name.
+#[derive(Default)]
+pub struct SectionRegistry {
+    providers: BTreeMap<String, Box<dyn SectionProvider>>,
+}
+
+impl SectionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Provide `PlanSection::Custom(name)`; replaces an earlier provider of that name.
+    pub fn register(&mut self, name: impl Into<String>, provider: impl SectionProvider + 'static) -> &mut Self {
+        self.providers.insert(name.into(), Box::new(provider));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn SectionProvider> {
+        self.providers.get(name).map(|p| p.as_ref())
+    }
+
+    /// Every custom section in `sections` has a provider.
+    pub fn check(&self, sections: &[PlanSection]) -> Result<()> {
+        for s in sections {
+            if let PlanSection::Custom(name) = s
+                && !self.providers.contains_key(name)
+            {
+                let known: Vec<&str> = self.providers.keys().map(String::as_str).collect();
+                return Err(PassengerError::Config(format!(
+                    "no provider registered for header section '{name}' (registered: [{}])",
+                    known.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Begin marker, every section of `plan` in order, end marker.
+pub fn render_sections<P: HeaderPlanV2 + ?Sized>(plan: &P, rcx: &RenderContext, begin: &str, end: &str) -> String {
+    let opts = plan.options();
+    let gate = plan.select_gates(rcx.report, &opts.gates);
+    let cx = SectionContext { report: rcx.report, analysis: rcx.analysis, opts, gate: gate.as_ref() };
+
+    let mut out = format!("{begin}\n");
+    for section in plan.sections() {
+        match section {
+            PlanSection::Custom(name) => {
+                if let Some(p) = rcx.sections.get(name) {
+                    out.push_str(&p.render(&cx));
+                }
             }
+            s => out.push_str(&plan.render_section(s, &cx)),
         }
-        set.into_iter().collect()
     }
+    out.push_str(end);
+    out.push('\n');
+    out
+}
+
+/// `DOCUMENT DETAILS`: filename, the user's description, usages.
+pub fn document_details(cx: &SectionContext) -> String {
+    let mut out = String::new();
+    out.push_str("//! ----------------------------------------------\n");
+    out.push_str("//! DOCUMENT DETAILS -----------------------------\n");
+    out.push_str("//!\n");
+    out.push_str(&format!("//! filename:{}\n", cx.report.document.filename));
+    out.push_str("//! description:\n");
+    out.push_str(&format!("//! usages:{}\n", render_usages(&cx.report.usages, cx.opts.max_usages)));
+    out.push_str("//!\n");
+    out
+}
+
+/// `FEATURE NOTES`: one block per feature turning on a dep the file uses.
+pub fn feature_notes(cx: &SectionContext) -> String {
+    let mut out = String::new();
+    out.push_str("//! ----------------------------------------------\n");
+    out.push_str("//! FEATURE NOTES --------------------------------\n");
+    for n in &cx.report.notes {
+        out.push_str("//!\n");
+        out.push_str(&format!("//! feature_name:{}\n", n.feature_name));
+        out.push_str(&format!("//! deps:{:?}\n", n.dep_labels()));
+        out.push_str(&format!("//! scope:{:?}\n", n.scope));
+        out.push_str(&format!("//! corpus:{}\n", n.corpus));
+    }
+    out
+}
+
+/// `CORPUS FEATURES`: the file gate.
+pub fn corpus_gates(cx: &SectionContext) -> String {
+    let mut out = String::new();
+    out.push_str("//!\n");
+    out.push_str("//! ----------------------------------------------\n");
+    out.push_str("//! CORPUS FEATURES ------------------------------\n");
+    out.push_str("//!\n");
+    out.push_str(&render_gates(cx.gate, cx.opts.gates.docsrs));
+    out
+}
+
+/// Default gate selection: every corpus feature (an implicit `all`), with
+/// features from one `alternatives` group folded into `any(...)`, plus the
+/// platforms of target-specific deps in use. An override's `gate` wins.
+pub fn select_gate(report: &FileReport, opts: &GateOptions) -> Option<CfgExpr> {
+    if let Some(g) = &opts.gate {
+        return Some(g.clone());
+    }
+
+    let mut parts = vec![];
+    let mut grouped = vec![];
+    for alts in &opts.alternatives {
+        let hit: Vec<&String> = report.corpus_features.iter().filter(|f| alts.contains(f)).collect();
+        if hit.len() > 1 {
+            parts.push(CfgExpr::Any(hit.iter().map(|f| CfgExpr::feature(f)).collect()));
+            grouped.extend(hit);
+        }
+    }
+    let single = report.corpus_features.iter().filter(|f| !grouped.contains(f)).map(|f| CfgExpr::feature(f));
+    let mut all: Vec<CfgExpr> = single.collect();
+    all.extend(parts);
+    all.extend(report.platform_cfgs.iter().cloned());
+
+    CfgExpr::all_of(all)
+}
+
+/// `[cli.rs: fn run][engine.rs: fn run_scan]`, at most `max` entries, or
+/// `none in crate yet`.
+pub fn render_usages(usages: &[Usage], max: usize) -> String {
+    if usages.is_empty() {
+        return "none in crate yet".to_string();
+    }
+    let mut out: String = usages.iter().take(max).map(|u| format!("[{}: {}]", u.file, u.scope)).collect();
+    if usages.len() > max {
+        out.push_str(&format!(" (+{} more)", usages.len() - max));
+    }
+    out
+}
+
+/// `#![cfg(...)]` lines for `gate` (one per top-level `all` part), then the
+/// `docsrs` annotation for the whole gate when asked for.
+pub fn render_gates(gate: Option<&CfgExpr>, docsrs: bool) -> String {
+    let Some(gate) = gate else { return String::new() };
+    let mut out = String::new();
+    for part in gate.conjuncts() {
+        out.push_str(&format!("#![cfg({part})]\n"));
+    }
+    if docsrs {
+        out.push_str(&format!("#![cfg_attr(docsrs, doc(cfg({gate})))]\n"));
+    }
+    out
 }
 
 #[cfg(feature = "plan_verbose")]
 pub mod verbose;
 #[cfg(feature = "plan_compact")]
 pub mod compact;
+pub mod template;
+
+/// Where a crate keeps its template plans (`<name>.tpl`).
+pub const PLAN_DIR: &str = ".passenger/plans";
+
+/// A template at `{root}/.passenger/plans/<plan>.tpl` wins over a built-in plan
+/// of the same name.
+pub fn get_plan(root: &std::path::Path, plan: &str, opts: PlanOptions) -> Result<Box<dyn HeaderPlan>> {
+    let tpl = root.join(PLAN_DIR).join(format!("{plan}.tpl"));
+    if tpl.is_file() {
+        return Ok(Box::new(template::TemplatePlan::load(&tpl, opts)?));
+    }
 
-pub fn get_plan(plan: &str) -> Result<Box<dyn HeaderPlan>> {
     match plan {
         #[cfg(feature = "plan_verbose")]
-        "verbose" => Ok(Box::new(verbose::VerbosePlan)),
+        "verbose" => Ok(Box::new(verbose::VerbosePlan { opts })),
         #[cfg(feature = "plan_compact")]
-        "compact" => Ok(Box::new(compact::CompactPlan)),
-        _ => Err(PassengerError::Unsupported(format!("header plan '{plan}' not available"))),
+        "compact" => Ok(Box::new(compact::CompactPlan { opts })),
+        _ => Err(PassengerError::Unsupported(format!(
+            "header plan '{plan}' not available (no built-in plan and no {})",
+            tpl.display()
+        ))),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(features: &[&str], platforms: &[CfgExpr]) -> FileReport {
+        FileReport {
+            corpus_features: features.iter().map(|f| f.to_string()).collect(),
+            platform_cfgs: platforms.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn gates_combine_features_alternatives_and_platforms() {
+        let opts = GateOptions { alternatives: vec![vec!["rt-tokio".into(), "rt-smol".into()]], ..Default::default() };
+        let r = report(&["net", "rt-smol", "rt-tokio"], &[CfgExpr::Name("unix".into())]);
+        let gate = select_gate(&r, &opts).unwrap();
+        assert_eq!(gate.to_string(), "all(feature = \"net\", any(feature = \"rt-smol\", feature = \"rt-tokio\"), unix)");
+
+        // one feature of a group alone stays a plain feature gate
+        assert_eq!(select_gate(&report(&["rt-tokio"], &[]), &opts), Some(CfgExpr::feature("rt-tokio")));
+        assert_eq!(select_gate(&report(&[], &[]), &opts), None);
+
+        // a hand-picked gate replaces the computed one
+        let opts = GateOptions { gate: CfgExpr::parse("not(windows)"), ..opts };
+        assert_eq!(select_gate(&r, &opts).unwrap().to_string(), "not(windows)");
+    }
+
+    #[test]
+    fn gates_render_one_line_per_conjunct() {
+        let gate = CfgExpr::parse(r#"all(feature = "net", any(unix, windows))"#).unwrap();
+        assert_eq!(
+            render_gates(Some(&gate), true),
+            "#![cfg(feature = \"net\")]\n#![cfg(any(unix, windows))]\n\
+             #![cfg_attr(docsrs, doc(cfg(all(feature = \"net\", any(unix, windows)))))]\n"
+        );
+        assert_eq!(render_gates(None, true), "");
+    }
+
+    #[test]
+    fn usages_are_capped_with_a_count() {
+        let u = |f: &str| Usage { file: f.to_string(), scope: "fn run".to_string() };
+        assert_eq!(render_usages(&[], 2), "none in crate yet");
+        assert_eq!(render_usages(&[u("a.rs"), u("b.rs"), u("c.rs")], 2), "[a.rs: fn run][b.rs: fn run] (+1 more)");
+    }
+
+    #[derive(Default)]
+    struct Fields(PlanOptions);
+
+    impl HeaderPlan for Fields {
+        fn id(&self) -> &'static str { "fields" }
     }
-}
\ No newline at end of file
+
+    impl HeaderPlanV2 for Fields {
+        fn options(&self) -> &PlanOptions {
+            &self.0
+        }
+
+        fn sections(&self) -> &[PlanSection] {
+            self.0.sections.as_deref().unwrap_or_default()
+        }
+    }
+
+    const FRESH: &str = "//! code_passenger:begin\n//! filename:a.rs\n//! description:\n//! usages:none in crate yet\n//! code_passenger:end\n";
+
+    #[test]
+    fn user_fields_survive_and_the_end_marker_is_stamped() {
+        let first = reconcile(&Fields::default(), FRESH, None);
+        assert!(!first.conflict);
+        assert!(first.header.starts_with(&FRESH[..FRESH.len() - "//! code_passenger:end\n".len()]));
+        let stamp = first.header.lines().last().unwrap();
+        assert!(stamp.starts_with("//! code_passenger:end hash="), "{stamp}");
+
+        // the developer fills in a description over two lines and adds an owner
+        let edited = first
+            .header
+            .replace("//! description:\n", "//! description:Pools threads\n//!   for the runtime\n//! owner:net-team\n");
+        let again = reconcile(&Fields::default(), &FRESH.replace("none in crate yet", "[b.rs: fn run]"), Some(&edited));
+        assert!(!again.conflict);
+        assert_eq!(
+            again.header.lines().collect::<Vec<_>>()[..6],
+            [
+                "//! code_passenger:begin",
+                "//! filename:a.rs",
+                "//! description:Pools threads",
+                "//!   for the runtime",
+                "//! owner:net-team",
+                "//! usages:[b.rs: fn run]",
+            ]
+        );
+        // user fields don't feed the hash
+        assert_eq!(again.header.lines().last(), reconcile(&Fields::default(), &FRESH.replace("none in crate yet", "[b.rs: fn run]"), None).header.lines().last());
+    }
+
+    #[test]
+    fn hand_edited_computed_lines_are_a_conflict() {
+        let stamped = reconcile(&Fields::default(), FRESH, None).header;
+        let edited = stamped.replace("filename:a.rs", "filename:renamed.rs");
+        assert!(reconcile(&Fields::default(), FRESH, Some(&edited)).conflict);
+
+        // headers from before the stamp existed can't conflict; they just get stamped
+        let old = reconcile(&Fields::default(), FRESH, Some(FRESH));
+        assert!(!old.conflict);
+        assert_eq!(old.header, stamped);
+    }
+
+    #[test]
+    fn custom_sections_render_in_place_with_the_file_report() {
+        let config = crate::config::ProjectConfig {
+            sections: Some(vec!["document_details".into(), "OWNERS".into(), "corpus_gates".into()]),
+            ..Default::default()
+        };
+        let plan = Fields(config.plan_options("src/net.rs").unwrap());
+        let mut registry = SectionRegistry::new();
+        registry.register("OWNERS", |cx: &SectionContext| format!("//! OWNERS: net-team ({})\n", cx.report.document.filename));
+        registry.check(plan.sections()).unwrap();
+
+        let mut r = report(&["net"], &[]);
+        r.document.filename = "net.rs".into();
+        let analysis = AnalysisState::default();
+        let header = render_sections(&plan, &RenderContext { report: &r, analysis: &analysis, sections: &registry }, "//! begin", "//! end");
+
+        let lines: Vec<&str> = header.lines().collect();
+        let at = |needle: &str| lines.iter().position(|l| l.contains(needle)).unwrap_or_else(|| panic!("no {needle} in\n{header}"));
+        assert!(at("DOCUMENT DETAILS") < at("filename:net.rs"));
+        assert!(at("filename:net.rs") < at("//! OWNERS: net-team (net.rs)"));
+        assert!(at("//! OWNERS: net-team (net.rs)") < at("CORPUS FEATURES"));
+        assert!(at("CORPUS FEATURES") < at("#![cfg(feature = \"net\")]"));
+        assert_eq!((lines[0], lines[lines.len() - 1]), ("//! begin", "//! end"));
+    }
+
+    #[test]
+    fn unregistered_custom_sections_are_rejected() {
+        let mut registry = SectionRegistry::new();
+        registry.register("SAFETY", |_: &SectionContext| String::new());
+        let sections = [PlanSection::DocumentDetails, PlanSection::parse("OWNERS")];
+        let Err(PassengerError::Config(msg)) = registry.check(&sections) else { panic!("OWNERS has no provider") };
+        assert_eq!(msg, "no provider registered for header section 'OWNERS' (registered: [SAFETY])");
+        assert!(registry.check(&[PlanSection::parse("SAFETY"), PlanSection::CorpusGates]).is_ok());
+    }
+}
@@ -1,38 +1,37 @@
-use crate::model::{FileReport, PlanSection};
-use super::{HeaderPlan, HeaderPlanV2};
+use crate::model::{PlanOptions, PlanSection};
+use super::{builtin_section, render_gates, HeaderPlan, HeaderPlanV2, SectionContext};
 
 
-pub struct CompactPlan;
+pub struct CompactPlan {
+    pub opts: PlanOptions,
+}
 
 impl HeaderPlan for CompactPlan {
     fn id(&self) -> &'static str { "compact" }
-
-    fn render_for_rust(&self, report: &FileReport, begin: &str, end: &str) -> String {
-        let mut out = String::new();
-        out.push_str(begin);
-        out.push('\n');
-
-        out.push_str(&format!("//! file: {}\n", report.document.relative_path));
-        out.push_str(&format!("//! used_deps: {:?}\n", report.used.packages));
-        out.push_str("//!\n");
-        for f in &report.corpus_features {
-            out.push_str(&format!("#![cfg(feature = \"{}\")]\n", f));
-        }
-
-        out.push_str(end);
-        out.push('\n');
-        out
-    }
 }
 
 impl HeaderPlanV2 for CompactPlan {
-    fn id(&self) -> &'static str { "compact" }
+    fn options(&self) -> &PlanOptions { &self.opts }
 
     fn sections(&self) -> &[PlanSection] {
         const SECTIONS: &[PlanSection] = &[
             PlanSection::DocumentDetails,
             PlanSection::CorpusGates,
         ];
-        SECTIONS
+        self.opts.sections.as_deref().unwrap_or(SECTIONS)
+    }
+
+    fn render_section(&self, section: &PlanSection, cx: &SectionContext) -> String {
+        match section {
+            PlanSection::DocumentDetails => {
+                let mut out = String::new();
+                out.push_str(&format!("//! file: {}\n", cx.report.document.relative_path));
+                out.push_str(&format!("//! used_deps: {:?}\n", cx.report.used.packages));
+                out.push_str("//!\n");
+                out
+            }
+            PlanSection::CorpusGates => render_gates(cx.gate, cx.opts.gates.docsrs),
+            other => builtin_section(other, cx),
+        }
     }
 }
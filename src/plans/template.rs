@@ -0,0 +1,589 @@
+//! Header plans read from template files (`.passenger/plans/<name>.tpl`).
+//!
+//! - `{{ path }}`, `{{ path | filter | filter:"arg" }}` print a value
+//!   (`document.filename`, `note.deps | join`)
+//! - `{% for x in path %}...{% endfor %}` loops over a list; `loop.index`
+//!   (from 1), `loop.first` and `loop.last` are set inside
+//! - `{% if [not] path [== "text"] %}...{% else %}...{% endif %}`; empty
+//!   strings and lists, `0` and `false` are false
+//!
+//! A `{% %}` tag alone on its line takes the line with it. Variables and filters
+//! are checked against the report shape when the template is loaded.
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::{PassengerError, Result};
+use crate::model::{CfgExpr, FeatureNote, FileReport, PlanOptions, PlanSection, UseSite, Usage};
+
+use super::{HeaderPlan, HeaderPlanV2, RenderContext, render_gates, render_usages, select_gate};
+
+const FILTERS: &[&str] = &["upper", "lower", "trim", "len", "join", "debug", "default", "first", "last"];
+
+pub struct TemplatePlan {
+    nodes: Vec<Node>,
+    opts: PlanOptions,
+}
+
+impl TemplatePlan {
+    /// Read, parse and check the template at `path`.
+    pub fn load(path: &Path, opts: PlanOptions) -> Result<Self> {
+        let src = std::fs::read_to_string(path)?;
+        Self::parse(&src, opts).map_err(|e| PassengerError::Config(format!("{}: {e}", path.display())))
+    }
+
+    pub fn parse(src: &str, opts: PlanOptions) -> std::result::Result<Self, String> {
+        let toks = lex(src)?;
+        let mut i = 0;
+        let (nodes, stop) = parse_nodes(&toks, &mut i)?;
+        if let Some((tag, line)) = stop {
+            return Err(format!("line {line}: unexpected `{{% {tag} %}}`"));
+        }
+
+        let sample = sample_report();
+        let sample = context(&sample, &opts, select_gate(&sample, &opts.gates));
+        check(&nodes, &mut vec![], &sample)?;
+        Ok(Self { nodes, opts })
+    }
+}
+
+impl HeaderPlan for TemplatePlan {
+    fn id(&self) -> &'static str {
+        "template"
+    }
+
+    /// The template lays out the whole header; `sections` doesn't apply.
+    fn render_for_rust(&self, cx: &RenderContext, begin: &str, end: &str) -> String {
+        let ctx = context(cx.report, &self.opts, self.select_gates(cx.report, &self.opts.gates));
+        let mut body = String::new();
+        render(&self.nodes, &mut vec![], &ctx, &mut body);
+
+        let mut out = format!("{begin}\n{body}");
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str(end);
+        out.push('\n');
+        out
+    }
+}
+
+impl HeaderPlanV2 for TemplatePlan {
+    fn options(&self) -> &PlanOptions {
+        &self.opts
+    }
+
+    fn sections(&self) -> &[PlanSection] {
+        &[]
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Str(String),
+    Bool(bool),
+    Num(usize),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Str(s) => !s.is_empty(),
+            Value::Bool(b) => *b,
+            Value::Num(n) => *n != 0,
+            Value::List(xs) => !xs.is_empty(),
+            Value::Map(m) => !m.is_empty(),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Value::Str(s) => s.clone(),
+            Value::Bool(b) => b.to_string(),
+            Value::Num(n) => n.to_string(),
+            Value::List(_) => self.debug(),
+            Value::Map(_) => String::new(),
+        }
+    }
+
+    /// `["a", "b"]`, like the built-in plans print lists
+    fn debug(&self) -> String {
+        match self {
+            Value::Str(s) => format!("{s:?}"),
+            Value::List(xs) => format!("[{}]", xs.iter().map(|x| x.debug()).collect::<Vec<_>>().join(", ")),
+            other => other.text(),
+        }
+    }
+}
+
+fn strs<'a>(xs: impl IntoIterator<Item = &'a String>) -> Value {
+    Value::List(xs.into_iter().map(|s| Value::Str(s.clone())).collect())
+}
+
+fn map<const N: usize>(entries: [(&str, Value); N]) -> Value {
+    Value::Map(entries.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+fn note_value(n: &FeatureNote) -> Value {
+    map([
+        ("feature_name", Value::Str(n.feature_name.clone())),
+        ("deps", strs(&n.dep_labels())),
+        ("scope", strs(&n.scope)),
+        ("corpus", Value::Bool(n.corpus)),
+        ("used_in_file", Value::Bool(n.used_in_file)),
+    ])
+}
+
+fn site_value(u: &UseSite) -> Value {
+    map([
+        ("dep", Value::Str(u.dep.clone())),
+        ("path", Value::Str(u.path.clone())),
+        ("resolved", Value::Str(u.resolved.clone())),
+        ("head", Value::Str(u.head.clone())),
+        ("kind", Value::Str(format!("{:?}", u.kind))),
+        ("line", Value::Num(u.line)),
+        ("scope", Value::Str(u.scope.clone())),
+        ("test_only", Value::Bool(u.test_only)),
+    ])
+}
+
+fn usage_value(u: &Usage) -> Value {
+    map([("file", Value::Str(u.file.clone())), ("scope", Value::Str(u.scope.clone()))])
+}
+
+/// What templates can refer to.
+fn context(r: &FileReport, opts: &PlanOptions, gate: Option<CfgExpr>) -> Value {
+    map([
+        (
+            "document",
+            map([
+                ("filename", Value::Str(r.document.filename.clone())),
+                ("relative_path", Value::Str(r.document.relative_path.clone())),
+                ("path", Value::Str(r.document.display_path())),
+            ]),
+        ),
+        ("module_path", Value::Str(r.module_path.clone())),
+        ("target", Value::Str(r.target.as_str().to_string())),
+        ("target_name", Value::Str(r.target_name.clone())),
+        ("scan_mode", Value::Str(format!("{:?}", r.scan_mode))),
+        ("used_deps", strs(&r.used.packages)),
+        ("corpus_features", strs(&r.corpus_features)),
+        ("notes", Value::List(r.notes.iter().map(note_value).collect())),
+        ("external_use_sites", Value::List(r.external_use_sites.iter().map(site_value).collect())),
+        ("internal_use_sites", Value::List(r.internal_use_sites.iter().map(site_value).collect())),
+        ("usages", Value::List(r.usages.iter().map(usage_value).collect())),
+        ("usages_text", Value::Str(render_usages(&r.usages, opts.max_usages))),
+        ("gate", Value::Str(gate.as_ref().map(|g| g.to_string()).unwrap_or_default())),
+        ("gates", Value::Str(render_gates(gate.as_ref(), opts.gates.docsrs).trim_end().to_string())),
+    ])
+}
+
+/// A report with one entry in every list, so loop bodies can be checked.
+fn sample_report() -> FileReport {
+    let mut r = FileReport {
+        notes: vec![FeatureNote::default()],
+        external_use_sites: vec![UseSite::default()],
+        internal_use_sites: vec![UseSite::default()],
+        usages: vec![Usage::default()],
+        corpus_features: vec![String::new()],
+        ..Default::default()
+    };
+    r.used.packages.insert(String::new());
+    r
+}
+
+// ---- syntax ----
+
+enum Tok {
+    Text(String),
+    Out(String, usize),
+    Tag(String, usize),
+}
+
+enum Node {
+    Text(String),
+    Var { path: Vec<String>, filters: Vec<(String, Option<String>)>, line: usize },
+    For { var: String, path: Vec<String>, body: Vec<Node>, line: usize },
+    If { cond: Cond, then: Vec<Node>, els: Vec<Node> },
+}
+
+struct Cond {
+    negate: bool,
+    path: Vec<String>,
+    /// `== "x"` (true) / `!= "x"` (false)
+    cmp: Option<(bool, String)>,
+    line: usize,
+}
+
+fn lex(src: &str) -> std::result::Result<Vec<Tok>, String> {
+    let mut toks = vec![];
+    let mut text = String::new();
+    let mut rest = src;
+    let mut line = 1;
+    // whether `text` starts at the beginning of a line
+    let mut line_start = true;
+
+    loop {
+        let Some(at) = [rest.find("{{"), rest.find("{%")].into_iter().flatten().min() else {
+            text.push_str(rest);
+            break;
+        };
+        text.push_str(&rest[..at]);
+        line += rest[..at].matches('\n').count();
+
+        let tag = rest[at..].starts_with("{%");
+        let close = if tag { "%}" } else { "}}" };
+        let end = rest[at + 2..].find(close).ok_or_else(|| format!("line {line}: unclosed `{}`", &rest[at..at + 2]))? + at + 2;
+        let inner = rest[at + 2..end].trim().to_string();
+        let tok_line = line;
+        line += rest[at + 2..end].matches('\n').count();
+        rest = &rest[end + 2..];
+
+        if tag {
+            let (before, nl_before) = match text.rfind('\n') {
+                Some(i) => (&text[i + 1..], true),
+                None => (text.as_str(), line_start),
+            };
+            let eol = rest.find('\n');
+            let after = &rest[..eol.unwrap_or(rest.len())];
+            if nl_before && before.trim().is_empty() && after.trim().is_empty() {
+                text.truncate(text.len() - before.len());
+                rest = eol.map_or("", |i| &rest[i + 1..]);
+                if eol.is_some() {
+                    line += 1;
+                }
+                line_start = true;
+            } else {
+                line_start = false;
+            }
+        } else {
+            line_start = false;
+        }
+
+        if !text.is_empty() {
+            toks.push(Tok::Text(std::mem::take(&mut text)));
+        }
+        toks.push(if tag { Tok::Tag(inner, tok_line) } else { Tok::Out(inner, tok_line) });
+    }
+
+    if !text.is_empty() {
+        toks.push(Tok::Text(text));
+    }
+    Ok(toks)
+}
+
+/// A closing tag (`else`/`endfor`/`endif`) and its line.
+type Stop = Option<(String, usize)>;
+
+/// Nodes up to the next closing tag, or the end.
+fn parse_nodes(toks: &[Tok], i: &mut usize) -> std::result::Result<(Vec<Node>, Stop), String> {
+    let mut nodes = vec![];
+    while let Some(tok) = toks.get(*i) {
+        *i += 1;
+        match tok {
+            Tok::Text(t) => nodes.push(Node::Text(t.clone())),
+            Tok::Out(inner, line) => nodes.push(parse_var(inner, *line)?),
+            Tok::Tag(inner, line) => {
+                let words: Vec<&str> = inner.split_whitespace().collect();
+                match words.as_slice() {
+                    ["for", var, "in", path] => {
+                        let (body, stop) = parse_nodes(toks, i)?;
+                        expect_end(stop, "endfor", *line)?;
+                        nodes.push(Node::For { var: var.to_string(), path: split_path(path), body, line: *line });
+                    }
+                    ["if", ..] => {
+                        let cond = parse_cond(&inner[2..], *line)?;
+                        let (then, stop) = parse_nodes(toks, i)?;
+                        let els = match stop {
+                            Some((s, _)) if s == "else" => {
+                                let (els, stop) = parse_nodes(toks, i)?;
+                                expect_end(stop, "endif", *line)?;
+                                els
+                            }
+                            stop => {
+                                expect_end(stop, "endif", *line)?;
+                                vec![]
+                            }
+                        };
+                        nodes.push(Node::If { cond, then, els });
+                    }
+                    ["else"] | ["endfor"] | ["endif"] => return Ok((nodes, Some((words[0].to_string(), *line)))),
+                    _ => return Err(format!("line {line}: unknown tag `{{% {inner} %}}`")),
+                }
+            }
+        }
+    }
+    Ok((nodes, None))
+}
+
+fn expect_end(stop: Stop, want: &str, line: usize) -> std::result::Result<(), String> {
+    match stop {
+        Some((s, _)) if s == want => Ok(()),
+        Some((s, at)) => Err(format!("line {at}: expected `{{% {want} %}}` for the block on line {line}, found `{{% {s} %}}`")),
+        None => Err(format!("line {line}: block never closed with `{{% {want} %}}`")),
+    }
+}
+
+fn split_path(s: &str) -> Vec<String> {
+    s.split('.').map(str::to_string).collect()
+}
+
+fn parse_var(inner: &str, line: usize) -> std::result::Result<Node, String> {
+    let mut parts = inner.split('|').map(str::trim);
+    let path = parts.next().filter(|p| !p.is_empty()).ok_or_else(|| format!("line {line}: empty `{{{{ }}}}`"))?;
+    let mut filters = vec![];
+    for f in parts {
+        let (name, arg) = match f.split_once(':') {
+            Some((n, a)) => (n.trim(), Some(unquote(a.trim()).ok_or_else(|| format!("line {line}: filter argument must be a \"string\": `{f}`"))?)),
+            None => (f, None),
+        };
+        if !FILTERS.contains(&name) {
+            return Err(format!("line {line}: unknown filter `{name}` (known: {})", FILTERS.join(", ")));
+        }
+        filters.push((name.to_string(), arg));
+    }
+    Ok(Node::Var { path: split_path(path), filters, line })
+}
+
+fn parse_cond(s: &str, line: usize) -> std::result::Result<Cond, String> {
+    let s = s.trim();
+    let (negate, s) = match s.strip_prefix("not ") {
+        Some(rest) => (true, rest.trim()),
+        None => (false, s),
+    };
+    let (path, cmp) = match s.split_once("==").map(|(p, v)| (p, v, true)).or_else(|| s.split_once("!=").map(|(p, v)| (p, v, false))) {
+        Some((p, v, eq)) => {
+            let v = unquote(v.trim()).ok_or_else(|| format!("line {line}: compare against a \"string\": `{s}`"))?;
+            (p.trim(), Some((eq, v)))
+        }
+        None => (s, None),
+    };
+    if path.is_empty() || path.contains(char::is_whitespace) {
+        return Err(format!("line {line}: bad condition `{s}`"));
+    }
+    Ok(Cond { negate, path: split_path(path), cmp, line })
+}
+
+fn unquote(s: &str) -> Option<String> {
+    s.strip_prefix('"')?.strip_suffix('"').map(str::to_string)
+}
+
+// ---- evaluation ----
+
+/// Loop variables, innermost last.
+type Scope = Vec<(String, Value)>;
+
+fn lookup(path: &[String], scope: &Scope, root: &Value) -> Option<Value> {
+    let (head, rest) = path.split_first()?;
+    let mut v = match scope.iter().rev().find(|(k, _)| k == head) {
+        Some((_, v)) => v.clone(),
+        None => match root {
+            Value::Map(m) => m.get(head)?.clone(),
+            _ => return None,
+        },
+    };
+    for seg in rest {
+        v = match v {
+            Value::Map(m) => m.get(seg)?.clone(),
+            _ => return None,
+        };
+    }
+    Some(v)
+}
+
+fn loop_value(i: usize, len: usize) -> Value {
+    map([("index", Value::Num(i + 1)), ("first", Value::Bool(i == 0)), ("last", Value::Bool(i + 1 == len))])
+}
+
+fn check(nodes: &[Node], scope: &mut Scope, root: &Value) -> std::result::Result<(), String> {
+    let unknown = |path: &[String], line: usize| format!("line {line}: unknown variable `{}`", path.join("."));
+    for n in nodes {
+        match n {
+            Node::Text(_) => {}
+            Node::Var { path, line, .. } => {
+                lookup(path, scope, root).ok_or_else(|| unknown(path, *line))?;
+            }
+            Node::For { var, path, body, line } => {
+                let Value::List(items) = lookup(path, scope, root).ok_or_else(|| unknown(path, *line))? else {
+                    return Err(format!("line {line}: `{}` is not a list", path.join(".")));
+                };
+                let item = items.into_iter().next().unwrap_or(Value::Str(String::new()));
+                scope.push((var.clone(), item));
+                scope.push(("loop".to_string(), loop_value(0, 1)));
+                let res = check(body, scope, root);
+                scope.truncate(scope.len() - 2);
+                res?;
+            }
+            Node::If { cond, then, els } => {
+                lookup(&cond.path, scope, root).ok_or_else(|| unknown(&cond.path, cond.line))?;
+                check(then, scope, root)?;
+                check(els, scope, root)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn apply_filter(v: Value, name: &str, arg: Option<&str>) -> Value {
+    match (name, v) {
+        ("upper", v) => Value::Str(v.text().to_uppercase()),
+        ("lower", v) => Value::Str(v.text().to_lowercase()),
+        ("trim", v) => Value::Str(v.text().trim().to_string()),
+        ("len", Value::List(xs)) => Value::Num(xs.len()),
+        ("len", v) => Value::Num(v.text().chars().count()),
+        ("join", Value::List(xs)) => Value::Str(xs.iter().map(|x| x.text()).collect::<Vec<_>>().join(arg.unwrap_or(", "))),
+        ("debug", v) => Value::Str(v.debug()),
+        ("default", v) if !v.truthy() => Value::Str(arg.unwrap_or_default().to_string()),
+        ("first", Value::List(xs)) => xs.into_iter().next().unwrap_or(Value::Str(String::new())),
+        ("last", Value::List(xs)) => xs.into_iter().last().unwrap_or(Value::Str(String::new())),
+        (_, v) => v,
+    }
+}
+
+fn render(nodes: &[Node], scope: &mut Scope, root: &Value, out: &mut String) {
+    for n in nodes {
+        match n {
+            Node::Text(t) => out.push_str(t),
+            Node::Var { path, filters, .. } => {
+                let Some(mut v) = lookup(path, scope, root) else { continue };
+                for (name, arg) in filters {
+                    v = apply_filter(v, name, arg.as_deref());
+                }
+                out.push_str(&v.text());
+            }
+            Node::For { var, path, body, .. } => {
+                let Some(Value::List(items)) = lookup(path, scope, root) else { continue };
+                let len = items.len();
+                for (i, item) in items.into_iter().enumerate() {
+                    scope.push((var.clone(), item));
+                    scope.push(("loop".to_string(), loop_value(i, len)));
+                    render(body, scope, root, out);
+                    scope.truncate(scope.len() - 2);
+                }
+            }
+            Node::If { cond, then, els } => {
+                let v = lookup(&cond.path, scope, root);
+                let mut hit = match (&cond.cmp, &v) {
+                    (Some((eq, want)), Some(v)) => (v.text() == *want) == *eq,
+                    (Some((eq, _)), None) => !*eq,
+                    (None, v) => v.as_ref().is_some_and(Value::truthy),
+                };
+                if cond.negate {
+                    hit = !hit;
+                }
+                render(if hit { then } else { els }, scope, root, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::tree;
+    use crate::model::TargetKind;
+
+    fn report() -> FileReport {
+        let note = |f: &str, deps: &[&str]| FeatureNote {
+            feature_name: f.to_string(),
+            deps: deps.iter().map(|d| d.to_string()).collect(),
+            corpus: true,
+            ..Default::default()
+        };
+        let mut r = FileReport {
+            target: TargetKind::Lib,
+            notes: vec![note("net", &["tokio", "hyper"]), note("json", &["serde_json"])],
+            corpus_features: vec!["json".into(), "net".into()],
+            ..Default::default()
+        };
+        r.document.filename = "pool.rs".into();
+        r
+    }
+
+    fn body(src: &str, r: &FileReport) -> String {
+        let opts = PlanOptions::default();
+        let plan = TemplatePlan::parse(src, opts.clone()).unwrap_or_else(|e| panic!("{e}"));
+        let mut out = String::new();
+        render(&plan.nodes, &mut vec![], &context(r, &opts, select_gate(r, &opts.gates)), &mut out);
+        out
+    }
+
+    fn error(src: &str) -> String {
+        TemplatePlan::parse(src, PlanOptions::default()).err().expect("template should be rejected")
+    }
+
+    #[test]
+    fn variables_filters_and_loops() {
+        let src = "\
+//! file:{{ document.filename | upper }} ({{ target_name | default:\"unnamed\" }})
+{% for n in notes %}
+//! {{ loop.index }}. {{ n.feature_name }}: {{ n.deps | join:\" + \" }}{% if loop.last %} (last){% endif %}
+{% endfor %}
+//! {{ corpus_features | len }} features, first {{ corpus_features | first }}, {{ corpus_features | debug }}
+";
+        assert_eq!(
+            body(src, &report()),
+            "\
+//! file:POOL.RS (unnamed)
+//! 1. net: tokio + hyper
+//! 2. json: serde_json (last)
+//! 2 features, first json, [\"json\", \"net\"]
+"
+        );
+    }
+
+    #[test]
+    fn conditions_compare_negate_and_fall_back() {
+        let src = "\
+{% if target == \"lib\" %}
+library
+{% else %}
+other
+{% endif %}
+{% if not usages %}
+unused
+{% endif %}
+{% if gate != \"\" %}gated on {{ gate }}{% endif %}
+";
+        assert_eq!(body(src, &report()), "library\nunused\ngated on all(feature = \"json\", feature = \"net\")\n");
+
+        let mut r = report();
+        r.target = TargetKind::Bin;
+        r.corpus_features.clear();
+        r.usages.push(Usage { file: "a.rs".into(), scope: "fn run".into() });
+        assert_eq!(body(src, &r), "other\n\n");
+    }
+
+    #[test]
+    fn tags_alone_on_a_line_take_the_line_with_them() {
+        assert_eq!(body("a\n  {% if target %}  \nb\n{% endif %}\nc", &report()), "a\nb\nc");
+        // inline tags leave the surrounding text alone
+        assert_eq!(body("a {% if target %}b{% endif %} c\n", &report()), "a b c\n");
+    }
+
+    #[test]
+    fn mistakes_are_reported_with_their_line_when_loading() {
+        assert_eq!(error("ok\n{{ document.nope }}"), "line 2: unknown variable `document.nope`");
+        assert_eq!(error("{% for n in notes %}{{ n.missing }}{% endfor %}"), "line 1: unknown variable `n.missing`");
+        assert!(error("{{ gate | shout }}").starts_with("line 1: unknown filter `shout` (known: upper, "));
+        assert_eq!(error("{{ gate | default:none }}"), "line 1: filter argument must be a \"string\": `default:none`");
+        assert_eq!(error("a\n\n{{ gate"), "line 3: unclosed `{{`");
+        assert_eq!(error("{% for n in notes %}\nx\n"), "line 1: block never closed with `{% endfor %}`");
+        assert_eq!(
+            error("{% if gate %}\n{% endfor %}"),
+            "line 2: expected `{% endif %}` for the block on line 1, found `{% endfor %}`"
+        );
+        assert_eq!(error("x\n{% endif %}"), "line 2: unexpected `{% endif %}`");
+        assert_eq!(error("{% for x in gate %}{% endfor %}"), "line 1: `gate` is not a list");
+        assert_eq!(error("{% while gate %}"), "line 1: unknown tag `{% while gate %}`");
+    }
+
+    #[test]
+    fn load_names_the_file_in_errors() {
+        let dir = tree(&[("bad.tpl", "{{ nope }}")]);
+        let path = dir.path().join("bad.tpl");
+        let Err(PassengerError::Config(msg)) = TemplatePlan::load(&path, PlanOptions::default()) else { panic!("loads") };
+        assert_eq!(msg, format!("{}: line 1: unknown variable `nope`", path.display()));
+    }
+}
@@ -66,7 +66,7 @@ fn {snake}_smoke() {
 
 pub fn get_scaffold_plan(id: &str) -> Result<Box<dyn ScaffoldPlan>> {
     match id {
-        "default" => Ok(Box::new(DefaultScaffoldPlan::default())),
+        "default" => Ok(Box::new(DefaultScaffoldPlan)),
         _ => Err(PassengerError::Unsupported(format!("scaffold plan '{id}' not available"))),
     }
 }
\ No newline at end of file
@@ -0,0 +1,13 @@
+//! Fixtures shared by the unit tests.
+use std::fs;
+
+/// Write `files` (relative path, contents) under a fresh temp dir.
+pub fn tree(files: &[(&str, &str)]) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (rel, body) in files {
+        let p = dir.path().join(rel);
+        fs::create_dir_all(p.parent().unwrap()).unwrap();
+        fs::write(p, body).unwrap();
+    }
+    dir
+}